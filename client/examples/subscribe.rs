@@ -0,0 +1,56 @@
+//! Subscribe to a topic and print the data received. The connection is made
+//! again if it is lost.
+//!
+//! cargo run -p client --example subscribe -- 127.0.0.1 8558 "LSE.*"
+
+use std::io;
+use std::sync::Arc;
+
+use futures::StreamExt;
+
+use client::authentication::NullCredentials;
+use client::{connect, Backoff, ClientEvent, ClientProtocol, Heartbeat};
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let host = args.get(1).map_or("127.0.0.1", |arg| arg.as_str());
+    let port = args.get(2).map_or(Ok(8558), |arg| arg.parse()).unwrap();
+    let topic = args.get(3).map_or("*", |arg| arg.as_str());
+
+    let (mut client, mut events) = connect(
+        host,
+        port,
+        None,
+        Arc::new(NullCredentials {}),
+        Heartbeat::default(),
+        Some(Backoff::default()),
+    )
+    .await?;
+    println!("Connected as {}.", client.client_id());
+
+    client.add_subscription(topic.into()).await?;
+
+    while let Some(event) = events.next().await {
+        match event {
+            ClientEvent::Data {
+                topic,
+                data_packets,
+                ..
+            } => {
+                for data_packet in data_packets {
+                    println!("{topic}: {}", String::from_utf8_lossy(&data_packet.data));
+                }
+            }
+            ClientEvent::Disconnected(error) => println!("Disconnected: {error}"),
+            ClientEvent::Reconnecting { attempt, delay } => {
+                println!("Reconnecting in {delay:?} (attempt {attempt}).")
+            }
+            ClientEvent::Connected { client_id } => println!("Reconnected as {client_id}."),
+            ClientEvent::Closed(error) => println!("Closed: {error}"),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::io::Result;
+
+use async_trait::async_trait;
+use http_auth_basic::Credentials;
+
+use crate::authentication::traits::CredentialProvider;
+
+/// A username and password, checked by the server against a password file
+/// (`basic`) or an LDAP server (`ldap`).
+#[derive(Clone)]
+pub struct BasicCredentials {
+    method: String,
+    username: String,
+    password: String,
+}
+
+impl BasicCredentials {
+    pub fn basic(username: &str, password: &str) -> Self {
+        BasicCredentials {
+            method: "basic".into(),
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    pub fn ldap(username: &str, password: &str) -> Self {
+        BasicCredentials {
+            method: "ldap".into(),
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for BasicCredentials {
+    fn name(&self) -> &str {
+        self.method.as_str()
+    }
+
+    async fn credentials(&self) -> Result<Vec<u8>> {
+        let credentials = Credentials::new(&self.username, &self.password);
+        Ok(credentials.encode().into())
+    }
+}
@@ -0,0 +1,21 @@
+use std::io::Result;
+
+use async_trait::async_trait;
+
+use crate::authentication::traits::CredentialProvider;
+
+/// No credentials, for a server which authenticates clients by the
+/// certificate presented when the TLS connection is made.
+#[derive(Clone)]
+pub struct CertificateCredentials {}
+
+#[async_trait]
+impl CredentialProvider for CertificateCredentials {
+    fn name(&self) -> &str {
+        "tls"
+    }
+
+    async fn credentials(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+use common::{messages::Message, MessageStream};
+
+mod basic;
+pub use basic::BasicCredentials;
+
+mod certificate;
+pub use certificate::CertificateCredentials;
+
+mod null;
+pub use null::NullCredentials;
+
+mod peer;
+pub use peer::PeerCredentials;
+
+mod token;
+pub use token::TokenCredentials;
+
+mod traits;
+pub use traits::CredentialProvider;
+
+/// Make a credential provider for the authentication method, as chosen on a
+/// command line.
+pub fn create_credential_provider(
+    mode: &str,
+    username: &Option<String>,
+    password: &Option<String>,
+) -> io::Result<Arc<dyn CredentialProvider>> {
+    match mode {
+        "none" => Ok(Arc::new(NullCredentials {})),
+        "tls" => Ok(Arc::new(CertificateCredentials {})),
+        "unix" => Ok(Arc::new(PeerCredentials {})),
+        "basic" | "ldap" => {
+            let Some(username) = username else {
+                return Err(Error::new(ErrorKind::Other, "missing username"));
+            };
+            let Some(password) = password else {
+                return Err(Error::new(ErrorKind::Other, "missing password"));
+            };
+            Ok(Arc::new(match mode {
+                "basic" => BasicCredentials::basic(username, password),
+                _ => BasicCredentials::ldap(username, password),
+            }))
+        }
+        // The token is given as the password.
+        "jwt" => {
+            let Some(token) = password else {
+                return Err(Error::new(ErrorKind::Other, "missing token"));
+            };
+            Ok(Arc::new(TokenCredentials::new(mode, token)))
+        }
+        _ => Err(Error::new(ErrorKind::Other, "invalid method")),
+    }
+}
+
+/// Send the authentication request, returning the client id given by the
+/// server.
+pub async fn authenticate(
+    stream: &mut (impl MessageStream + ?Sized),
+    provider: &dyn CredentialProvider,
+) -> io::Result<String> {
+    let request = Message::AuthenticationRequest {
+        method: provider.name().into(),
+        credentials: provider.credentials().await?,
+    };
+    stream.write(&request).await?;
+
+    let response = stream.read().await?;
+
+    match response {
+        Message::AuthenticationResponse { client_id } => Ok(client_id),
+        Message::Error { code, reason, .. } => Err(Error::new(code.into(), reason)),
+        _ => Err(Error::new(ErrorKind::Other, "invalid message")),
+    }
+}
@@ -0,0 +1,20 @@
+use std::io::Result;
+
+use async_trait::async_trait;
+
+use crate::authentication::traits::CredentialProvider;
+
+/// No credentials, for a server without authentication.
+#[derive(Clone)]
+pub struct NullCredentials {}
+
+#[async_trait]
+impl CredentialProvider for NullCredentials {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    async fn credentials(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
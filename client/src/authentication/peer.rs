@@ -0,0 +1,21 @@
+use std::io::Result;
+
+use async_trait::async_trait;
+
+use crate::authentication::traits::CredentialProvider;
+
+/// No credentials, for a server which authenticates clients connected by a
+/// Unix socket as the user running the client process.
+#[derive(Clone)]
+pub struct PeerCredentials {}
+
+#[async_trait]
+impl CredentialProvider for PeerCredentials {
+    fn name(&self) -> &str {
+        "unix"
+    }
+
+    async fn credentials(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
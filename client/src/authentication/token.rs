@@ -0,0 +1,32 @@
+use std::io::Result;
+
+use async_trait::async_trait;
+
+use crate::authentication::traits::CredentialProvider;
+
+/// A token sent as is, for token based authentication methods.
+#[derive(Clone)]
+pub struct TokenCredentials {
+    method: String,
+    token: String,
+}
+
+impl TokenCredentials {
+    pub fn new(method: &str, token: &str) -> Self {
+        TokenCredentials {
+            method: method.into(),
+            token: token.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for TokenCredentials {
+    fn name(&self) -> &str {
+        self.method.as_str()
+    }
+
+    async fn credentials(&self) -> Result<Vec<u8>> {
+        Ok(self.token.as_bytes().to_vec())
+    }
+}
@@ -0,0 +1,11 @@
+use std::io::Result;
+
+use async_trait::async_trait;
+
+/// Provides the credentials for an authentication method of the server.
+#[async_trait]
+pub trait CredentialProvider: Send + Sync {
+    /// The name of the server authentication method.
+    fn name(&self) -> &str;
+    async fn credentials(&self) -> Result<Vec<u8>>;
+}
@@ -1,30 +1,78 @@
-#![allow(dead_code)]
+use std::collections::HashMap;
 use std::io;
 use std::net::ToSocketAddrs;
-use std::path::PathBuf;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
-use common::MessageSocket;
-use common::MessageStream;
 use futures::future::BoxFuture;
-
-use common::messages::DataPacket;
-use common::messages::Message;
-use tokio::io::{AsyncRead, AsyncWrite};
+use futures::Stream;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
 
-use crate::authentication::authenticate;
-use crate::tls::create_tls_stream;
+use common::messages::{DataPacket, ErrorCode, Message};
+use common::{MessageSocket, MessageStream, MessageWebSocket};
 
-pub trait ClientCallbacks {
-    fn on_data(&mut self, topic: String, data_packets: Vec<DataPacket>) -> BoxFuture<'_, ()>;
-    fn on_forwarded_subscription(
-        &mut self,
+use crate::authentication::{authenticate, CredentialProvider};
+use crate::heartbeat::Heartbeat;
+use crate::reconnect::{ActiveRequests, Backoff};
+use crate::tls::{create_tls_stream, TlsOptions};
+
+/// An event from the server.
+#[derive(Debug)]
+pub enum ClientEvent {
+    /// Data published to a subscribed topic.
+    Data {
+        host: String,
+        user: String,
+        topic: String,
+        data_packets: Vec<DataPacket>,
+    },
+    /// Data sent directly to this client.
+    UnicastData {
+        host: String,
+        user: String,
+        client_id: String,
+        topic: String,
+        data_packets: Vec<DataPacket>,
+    },
+    /// A subscription to a topic matching a notification pattern. The count
+    /// is zero when the client has unsubscribed.
+    ForwardedSubscription {
+        host: String,
         user: String,
+        client_id: String,
         topic: String,
         count: u32,
-    ) -> BoxFuture<'_, ()>;
-    fn on_heartbeat(&mut self, count: u64) -> BoxFuture<'_, ()>;
+    },
+    Heartbeat {
+        count: u64,
+    },
+    /// An error that is not a reply to a request, for example when the
+    /// server drops messages because the client is too slow.
+    Error {
+        code: ErrorCode,
+        reason: String,
+    },
+    /// The connection was lost and will be reconnected.
+    Disconnected(io::Error),
+    /// An attempt to reconnect will be made after the delay.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// The connection has been made again, and the subscriptions and
+    /// notifications are being restored.
+    Connected {
+        client_id: String,
+    },
+    /// The connection has closed. This is the last event.
+    Closed(io::Error),
 }
 
 pub trait ClientProtocol {
@@ -45,188 +93,561 @@ pub trait ClientProtocol {
     fn add_notification(&mut self, topic: String) -> BoxFuture<'_, io::Result<()>>;
 }
 
-pub struct Client<S>
-where
-    S: AsyncRead + AsyncWrite + Send,
-{
-    callbacks: Box<dyn ClientCallbacks + Send>,
-    tx: Sender<Message>,
-    rx: Receiver<Message>,
-    stream: MessageSocket<S>,
+/// Callbacks for the events from the server, for clients which would rather
+/// not read the event stream. The events are passed on by
+/// [`ClientEvents::dispatch`].
+pub trait ClientCallbacks {
+    fn on_data(&mut self, topic: String, data_packets: Vec<DataPacket>) -> BoxFuture<'_, ()>;
+    fn on_forwarded_subscription(
+        &mut self,
+        user: String,
+        topic: String,
+        count: u32,
+    ) -> BoxFuture<'_, ()>;
+    fn on_heartbeat(&mut self, count: u64) -> BoxFuture<'_, ()>;
+
+    /// Data sent directly to this client is passed to `on_data` unless this
+    /// is overridden.
+    fn on_unicast_data(
+        &mut self,
+        _client_id: String,
+        topic: String,
+        data_packets: Vec<DataPacket>,
+    ) -> BoxFuture<'_, ()> {
+        self.on_data(topic, data_packets)
+    }
+    fn on_error(&mut self, _code: ErrorCode, _reason: String) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+    fn on_disconnected(&mut self, _error: io::Error) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+    fn on_reconnecting(&mut self, _attempt: u32, _delay: Duration) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+    fn on_connected(&mut self, _client_id: String) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Makes a new stream to the server, for reconnecting.
+pub type Connector =
+    Arc<dyn Fn() -> BoxFuture<'static, io::Result<Box<dyn MessageStream + Send>>> + Send + Sync>;
+
+/// A message to send to the server, with an optional channel for the reply.
+struct Request {
+    message: Message,
+    reply: Option<oneshot::Sender<io::Result<()>>>,
+}
+
+/// A handle to a connection to the server.
+///
+/// The connection is run by a spawned task, which ends when every handle has
+/// been dropped. Handles can be cloned and shared between tasks.
+#[derive(Clone)]
+pub struct Client {
+    // The server gives a new id when the client reconnects.
+    client_id: Arc<Mutex<String>>,
+    tx: Sender<Request>,
 }
 
-impl<S> Client<S>
-where
-    S: AsyncRead + AsyncWrite + Send + Unpin,
-{
-    pub async fn start(
+impl Client {
+    /// Authenticate over a connected stream, then spawn the task which runs
+    /// the connection. The events from the server are returned with the
+    /// handle.
+    pub async fn start<S>(
         stream: S,
-        callbacks: Box<dyn ClientCallbacks + Send>,
-        mode: &String,
-        username: &Option<String>,
-        password: &Option<String>,
-    ) -> io::Result<Self> {
-        let mut stream = MessageSocket::new(stream);
+        credentials: Arc<dyn CredentialProvider>,
+        heartbeat: Heartbeat,
+    ) -> io::Result<(Client, ClientEvents)>
+    where
+        S: MessageStream + Send + 'static,
+    {
+        Client::spawn(Box::new(stream), credentials, heartbeat, None).await
+    }
 
-        //let mut skt_reader = BufReader::new(skt_read_half);
-        let (tx, rx) = mpsc::channel::<Message>(32);
+    /// Connect and authenticate, then spawn the task which runs the
+    /// connection. When the connection is lost the connector is used to
+    /// connect again, waiting between attempts as given by the backoff, and
+    /// the acknowledged subscriptions and notifications are requested again.
+    pub async fn start_with_reconnect(
+        connector: Connector,
+        credentials: Arc<dyn CredentialProvider>,
+        heartbeat: Heartbeat,
+        backoff: Backoff,
+    ) -> io::Result<(Client, ClientEvents)> {
+        let stream = connector().await?;
+        Client::spawn(stream, credentials, heartbeat, Some((connector, backoff))).await
+    }
 
-        authenticate(&mut stream, mode, username, password).await?;
+    async fn spawn(
+        mut stream: Box<dyn MessageStream + Send>,
+        credentials: Arc<dyn CredentialProvider>,
+        heartbeat: Heartbeat,
+        reconnect: Option<(Connector, Backoff)>,
+    ) -> io::Result<(Client, ClientEvents)> {
+        let client_id = authenticate(stream.as_mut(), credentials.as_ref()).await?;
+        let client_id = Arc::new(Mutex::new(client_id));
 
-        let client = Client {
-            callbacks,
-            tx,
-            rx,
+        let (tx, rx) = mpsc::channel::<Request>(32);
+        // Events are never held up, so the connection cannot stall while the
+        // caller waits for a reply without reading them.
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let connection = Connection {
             stream,
+            rx,
+            event_tx,
+            // The authentication request was the first request.
+            request_count: 1,
+            pending: HashMap::new(),
+            client_id: client_id.clone(),
+            credentials,
+            heartbeat,
+            heartbeat_count: 0,
+            reconnect,
+            active: ActiveRequests::default(),
         };
+        tokio::spawn(connection.run());
 
-        Ok(client)
+        let client = Client { client_id, tx };
+        let events = ClientEvents { rx: event_rx };
+        Ok((client, events))
     }
 
-    fn send_message(&mut self, message: Message) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move {
-            self.tx
-                .send(message)
-                .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-            Ok(())
-        })
+    /// The id the server gave this client.
+    pub fn client_id(&self) -> String {
+        self.client_id.lock().unwrap().clone()
+    }
+
+    async fn send_message(&self, message: Message) -> io::Result<()> {
+        let request = Request {
+            message,
+            reply: None,
+        };
+        self.tx
+            .send(request)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "connection closed"))
+    }
+
+    /// Send a message and wait for the server to acknowledge it.
+    async fn send_request(&self, message: Message) -> io::Result<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let request = Request {
+            message,
+            reply: Some(reply_tx),
+        };
+        self.tx
+            .send(request)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "connection closed"))?;
+        reply_rx
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "connection closed"))?
     }
+}
 
-    async fn send_unicast_request(
+impl ClientProtocol for Client {
+    fn send(
         &mut self,
         client_id: String,
         topic: String,
         data_packets: Vec<DataPacket>,
-    ) -> io::Result<()> {
-        let message = Message::UnicastData {
+    ) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.send_message(Message::UnicastData {
             client_id,
             topic,
             data_packets,
-        };
-        self.send_message(message).await
+        }))
     }
 
-    async fn send_multicast_request(
+    fn publish(
         &mut self,
         topic: String,
         data_packets: Vec<DataPacket>,
-    ) -> io::Result<()> {
-        let message = Message::MulticastData {
+    ) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.send_message(Message::MulticastData {
             topic,
             data_packets,
-        };
-        self.send_message(message).await
+        }))
     }
 
-    async fn send_subscription_request(&mut self, topic: String, is_add: bool) -> io::Result<()> {
-        let message = Message::SubscriptionRequest { topic, is_add };
-        self.send_message(message).await
+    fn add_subscription(&mut self, topic: String) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.send_request(Message::SubscriptionRequest {
+            topic,
+            is_add: true,
+        }))
     }
 
-    async fn send_notification_request(&mut self, pattern: String, is_add: bool) -> io::Result<()> {
-        let message = Message::NotificationRequest { pattern, is_add };
-        self.send_message(message).await
+    fn remove_subscription(&mut self, topic: String) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.send_request(Message::SubscriptionRequest {
+            topic,
+            is_add: false,
+        }))
     }
 
-    async fn handle_message(&mut self, message: Message) {
-        match message {
-            Message::UnicastData {
-                client_id: _,
-                topic,
-                data_packets,
-            } => self.callbacks.on_data(topic, data_packets).await,
-            Message::MulticastData {
-                topic,
-                data_packets,
-            } => self.callbacks.on_data(topic, data_packets).await,
-            Message::ForwardedSubscriptionRequest {
-                host: _,
-                user: _,
-                client_id,
-                topic,
-                count,
-            } => {
-                self.callbacks
-                    .on_forwarded_subscription(client_id, topic, count)
-                    .await
+    fn add_notification(&mut self, pattern: String) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.send_request(Message::NotificationRequest {
+            pattern,
+            is_add: true,
+        }))
+    }
+
+    fn remove_notification(&mut self, pattern: String) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(self.send_request(Message::NotificationRequest {
+            pattern,
+            is_add: false,
+        }))
+    }
+}
+
+/// The events from the server, in the order they were received.
+pub struct ClientEvents {
+    rx: UnboundedReceiver<ClientEvent>,
+}
+
+impl ClientEvents {
+    /// Wait for the next event. This returns `None` after the connection has
+    /// closed and the last event has been read.
+    pub async fn recv(&mut self) -> Option<ClientEvent> {
+        self.rx.recv().await
+    }
+
+    /// Pass each event to the callbacks. Returns the error the connection
+    /// closed with, or `Ok` if it ended because every handle was dropped.
+    pub async fn dispatch(
+        mut self,
+        callbacks: &mut (dyn ClientCallbacks + Send),
+    ) -> io::Result<()> {
+        while let Some(event) = self.recv().await {
+            match event {
+                ClientEvent::Data {
+                    topic,
+                    data_packets,
+                    ..
+                } => callbacks.on_data(topic, data_packets).await,
+                ClientEvent::UnicastData {
+                    client_id,
+                    topic,
+                    data_packets,
+                    ..
+                } => {
+                    callbacks
+                        .on_unicast_data(client_id, topic, data_packets)
+                        .await
+                }
+                ClientEvent::ForwardedSubscription {
+                    user, topic, count, ..
+                } => {
+                    callbacks
+                        .on_forwarded_subscription(user, topic, count)
+                        .await
+                }
+                ClientEvent::Heartbeat { count } => callbacks.on_heartbeat(count).await,
+                ClientEvent::Error { code, reason } => callbacks.on_error(code, reason).await,
+                ClientEvent::Disconnected(error) => callbacks.on_disconnected(error).await,
+                ClientEvent::Reconnecting { attempt, delay } => {
+                    callbacks.on_reconnecting(attempt, delay).await
+                }
+                ClientEvent::Connected { client_id } => callbacks.on_connected(client_id).await,
+                ClientEvent::Closed(error) => return Err(error),
             }
-            Message::Heartbeat { count } => self.callbacks.on_heartbeat(count).await,
-            _ => todo!(),
-        };
+        }
+        Ok(())
+    }
+}
+
+impl Stream for ClientEvents {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
     }
+}
+
+/// A request waiting for the server to acknowledge it.
+enum Pending {
+    Request(oneshot::Sender<io::Result<()>>, Message),
+    /// A subscription or notification made again after reconnecting.
+    Restore(Message),
+}
+
+/// The state of the spawned task that runs a connection.
+struct Connection {
+    stream: Box<dyn MessageStream + Send>,
+    rx: Receiver<Request>,
+    event_tx: UnboundedSender<ClientEvent>,
+    // The server identifies requests by the number of messages received.
+    request_count: u64,
+    pending: HashMap<u64, Pending>,
+    client_id: Arc<Mutex<String>>,
+    credentials: Arc<dyn CredentialProvider>,
+    heartbeat: Heartbeat,
+    heartbeat_count: u64,
+    reconnect: Option<(Connector, Backoff)>,
+    active: ActiveRequests,
+}
+
+impl Connection {
+    async fn run(mut self) {
+        loop {
+            // Stop when every handle has been dropped.
+            let Some(error) = self.serve().await else {
+                return;
+            };
+
+            log::debug!("Connection closed: {error}");
+
+            // Requests waiting for a reply will never get one.
+            for (_, pending) in self.pending.drain() {
+                if let Pending::Request(reply, _) = pending {
+                    let _ = reply.send(Err(io::Error::new(error.kind(), error.to_string())));
+                }
+            }
+
+            if self.reconnect.is_none() {
+                let _ = self.event_tx.send(ClientEvent::Closed(error));
+                return;
+            }
+
+            let _ = self.event_tx.send(ClientEvent::Disconnected(error));
+            match self.reconnect().await {
+                Ok(true) => continue,
+                Ok(false) => return,
+                Err(error) => {
+                    let _ = self.event_tx.send(ClientEvent::Closed(error));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Handle requests and messages until the connection fails, returning
+    /// the error, or every handle has been dropped.
+    async fn serve(&mut self) -> Option<io::Error> {
+        let interval = self.heartbeat.interval;
+        let mut deadline = Instant::now() + interval;
+        // Any message from the server shows it is alive.
+        let mut last_received = Instant::now();
 
-    async fn process(&mut self) {
         loop {
             tokio::select! {
-                result = self.rx.recv() => {
-                    // Send a message to the server.
-                    let message = result.unwrap();
-                    self.stream.write(&message).await.unwrap();
+                request = self.rx.recv() => {
+                    let request = request?;
+                    if let Err(error) = self.write(request).await {
+                        return Some(error);
+                    }
                 }
                 result = self.stream.read() => {
-                    let message = result.unwrap();
-                    self.handle_message(message).await;
+                    last_received = Instant::now();
+                    match result {
+                        Ok(message) => self.handle_message(message),
+                        Err(error) => return Some(error),
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    deadline += interval;
+                    if self.heartbeat.max_missed > 0
+                        && last_received.elapsed() >= interval * self.heartbeat.max_missed
+                    {
+                        return Some(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "server missed heartbeats",
+                        ));
+                    }
+                    if let Err(error) = self.send_heartbeat().await {
+                        return Some(error);
+                    }
                 }
             }
         }
     }
-}
 
-impl<S> ClientProtocol for Client<S>
-where
-    S: AsyncRead + AsyncWrite + Send + Unpin,
-{
-    fn send(
-        &mut self,
-        client_id: String,
-        topic: String,
-        data_packets: Vec<DataPacket>,
-    ) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move {
-            self.send_unicast_request(client_id, topic, data_packets)
-                .await
-        })
+    async fn send_heartbeat(&mut self) -> io::Result<()> {
+        let message = Message::Heartbeat {
+            count: self.heartbeat_count,
+        };
+        self.stream.write(&message).await?;
+        self.heartbeat_count += 1;
+        // The server counts heartbeats as requests.
+        self.request_count += 1;
+        Ok(())
     }
 
-    fn publish(
-        &mut self,
-        topic: String,
-        data_packets: Vec<DataPacket>,
-    ) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move { self.send_multicast_request(topic, data_packets).await })
+    /// Try to connect until a connection is made, returning true, or the
+    /// attempts run out. Returns false if every handle is dropped while
+    /// waiting.
+    async fn reconnect(&mut self) -> io::Result<bool> {
+        let Some((connector, backoff)) = self.reconnect.clone() else {
+            return Ok(false);
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let delay = backoff.delay(attempt);
+            let _ = self
+                .event_tx
+                .send(ClientEvent::Reconnecting { attempt, delay });
+
+            if !self.wait(delay).await {
+                return Ok(false);
+            }
+
+            let error = match self.restart(&connector).await {
+                Ok(()) => return Ok(true),
+                Err(error) => error,
+            };
+            log::debug!("Failed to reconnect: {error}");
+
+            if backoff
+                .max_attempts
+                .is_some_and(|max_attempts| attempt >= max_attempts)
+            {
+                return Err(error);
+            }
+        }
     }
 
-    fn add_subscription(&mut self, topic: String) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move { self.send_subscription_request(topic, true).await })
+    /// Wait before reconnecting. Requests made while there is no connection
+    /// are failed, and data sent is discarded. Returns false if every handle
+    /// is dropped.
+    async fn wait(&mut self, delay: Duration) -> bool {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return true,
+                request = self.rx.recv() => {
+                    let Some(request) = request else {
+                        return false;
+                    };
+                    if let Some(reply) = request.reply {
+                        let _ = reply.send(Err(io::Error::new(
+                            io::ErrorKind::NotConnected,
+                            "reconnecting",
+                        )));
+                    }
+                }
+            }
+        }
     }
 
-    fn remove_subscription(&mut self, topic: String) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move { self.send_subscription_request(topic, false).await })
+    /// Connect and authenticate, then request the subscriptions and
+    /// notifications again.
+    async fn restart(&mut self, connector: &Connector) -> io::Result<()> {
+        let mut stream = connector().await?;
+        let client_id = authenticate(stream.as_mut(), self.credentials.as_ref()).await?;
+
+        // Forget requests restored by an attempt that failed partway.
+        self.pending.clear();
+        self.stream = stream;
+        self.request_count = 1;
+        *self.client_id.lock().unwrap() = client_id.clone();
+        let _ = self.event_tx.send(ClientEvent::Connected { client_id });
+
+        for message in self.active.restore() {
+            self.stream.write(&message).await?;
+            self.request_count += 1;
+            self.pending
+                .insert(self.request_count, Pending::Restore(message));
+        }
+        Ok(())
     }
 
-    fn add_notification(&mut self, pattern: String) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move { self.send_notification_request(pattern, true).await })
+    async fn write(&mut self, request: Request) -> io::Result<()> {
+        if let Err(error) = self.stream.write(&request.message).await {
+            if let Some(reply) = request.reply {
+                let _ = reply.send(Err(io::Error::new(error.kind(), error.to_string())));
+            }
+            return Err(error);
+        }
+        self.request_count += 1;
+        if let Some(reply) = request.reply {
+            self.pending
+                .insert(self.request_count, Pending::Request(reply, request.message));
+        }
+        Ok(())
     }
 
-    fn remove_notification(&mut self, pattern: String) -> BoxFuture<'_, io::Result<()>> {
-        Box::pin(async move { self.send_notification_request(pattern, false).await })
+    fn handle_message(&mut self, message: Message) {
+        let event = match message {
+            Message::ForwardedMulticastData {
+                host,
+                user,
+                topic,
+                data_packets,
+            } => ClientEvent::Data {
+                host,
+                user,
+                topic,
+                data_packets,
+            },
+            Message::ForwardedUnicastData {
+                host,
+                user,
+                client_id,
+                topic,
+                data_packets,
+            } => ClientEvent::UnicastData {
+                host,
+                user,
+                client_id,
+                topic,
+                data_packets,
+            },
+            Message::ForwardedSubscriptionRequest {
+                host,
+                user,
+                client_id,
+                topic,
+                count,
+            } => ClientEvent::ForwardedSubscription {
+                host,
+                user,
+                client_id,
+                topic,
+                count,
+            },
+            Message::Heartbeat { count } => ClientEvent::Heartbeat { count },
+            Message::Ack { request_id } => {
+                // Restored requests are already active.
+                if let Some(Pending::Request(reply, message)) = self.pending.remove(&request_id) {
+                    self.active.apply(&message);
+                    let _ = reply.send(Ok(()));
+                }
+                return;
+            }
+            Message::Error {
+                request_id,
+                code,
+                reason,
+            } => match self.pending.remove(&request_id) {
+                Some(Pending::Request(reply, _)) => {
+                    let _ = reply.send(Err(io::Error::new(code.into(), reason)));
+                    return;
+                }
+                Some(Pending::Restore(message)) => {
+                    // The request is no longer allowed, so it is not made on
+                    // later connections either.
+                    self.active.revert(&message);
+                    ClientEvent::Error { code, reason }
+                }
+                None => ClientEvent::Error { code, reason },
+            },
+            message => {
+                log::warn!("Ignoring unexpected message {:?}", message.message_type());
+                return;
+            }
+        };
+
+        // The caller may not be interested in events.
+        let _ = self.event_tx.send(event);
     }
 }
 
-pub async fn connect<S>(
-    host: &str,
-    port: u16,
-    tls: bool,
-    cafile: &Option<PathBuf>,
-    authentication_mode: &String,
-    username: &Option<String>,
-    password: &Option<String>,
-    callbacks: Box<dyn ClientCallbacks + Send>,
-) -> io::Result<Box<dyn ClientProtocol>>
-where
-    S: AsyncRead + AsyncWrite + Send + Unpin,
-{
+async fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
     let endpoint = format!("{}:{}", host, port);
 
     let addr = endpoint
@@ -235,23 +656,641 @@ where
         .ok_or(format!("failed to resolve {}", host))
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
-    let stream = TcpStream::connect(&addr).await?;
+    TcpStream::connect(&addr).await
+}
+
+fn socket_connector(host: String, port: u16, tls: Option<TlsOptions>) -> Connector {
+    Arc::new(move || {
+        let host = host.clone();
+        let tls = tls.clone();
+        Box::pin(async move {
+            let stream = connect_tcp(&host, port).await?;
+            let stream: Box<dyn MessageStream + Send> = match tls {
+                Some(options) => {
+                    let stream = create_tls_stream(&host, &options, stream).await?;
+                    Box::new(MessageSocket::new(stream))
+                }
+                None => Box::new(MessageSocket::new(stream)),
+            };
+            Ok(stream)
+        })
+    })
+}
+
+fn web_socket_connector(host: String, port: u16, tls: Option<TlsOptions>) -> Connector {
+    Arc::new(move || {
+        let host = host.clone();
+        let tls = tls.clone();
+        Box::pin(async move {
+            let stream = connect_tcp(&host, port).await?;
+            let stream: Box<dyn MessageStream + Send> = match tls {
+                Some(options) => {
+                    let stream = create_tls_stream(&host, &options, stream).await?;
+                    let url = format!("wss://{}:{}/", host, port);
+                    let (stream, _) = tokio_tungstenite::client_async(url, stream)
+                        .await
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    Box::new(MessageWebSocket::new(stream))
+                }
+                None => {
+                    let url = format!("ws://{}:{}/", host, port);
+                    let (stream, _) = tokio_tungstenite::client_async(url, stream)
+                        .await
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    Box::new(MessageWebSocket::new(stream))
+                }
+            };
+            Ok(stream)
+        })
+    })
+}
+
+#[cfg(unix)]
+fn unix_connector(path: PathBuf) -> Connector {
+    Arc::new(move || {
+        let path = path.clone();
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(&path).await?;
+            let stream: Box<dyn MessageStream + Send> = Box::new(MessageSocket::new(stream));
+            Ok(stream)
+        })
+    })
+}
 
-    let client = match tls {
-        true => {
-            let stream = create_tls_stream(host, cafile, stream).await?;
-            let client: Box<dyn ClientProtocol> = Box::from(
-                Client::start(stream, callbacks, authentication_mode, username, password).await?,
-            );
-            client
+async fn start_client(
+    connector: Connector,
+    credentials: Arc<dyn CredentialProvider>,
+    heartbeat: Heartbeat,
+    reconnect: Option<Backoff>,
+) -> io::Result<(Client, ClientEvents)> {
+    match reconnect {
+        Some(backoff) => {
+            Client::start_with_reconnect(connector, credentials, heartbeat, backoff).await
         }
-        false => {
-            let client = Box::new(
-                Client::start(stream, callbacks, authentication_mode, username, password).await?,
-            );
-            client
+        None => Client::spawn(connector().await?, credentials, heartbeat, None).await,
+    }
+}
+
+/// Connect to the socket endpoint of a server, using TLS when options are
+/// given. Heartbeats are exchanged with the server as given. If a backoff is
+/// given the connection is made again when it is lost.
+pub async fn connect(
+    host: &str,
+    port: u16,
+    tls: Option<TlsOptions>,
+    credentials: Arc<dyn CredentialProvider>,
+    heartbeat: Heartbeat,
+    reconnect: Option<Backoff>,
+) -> io::Result<(Client, ClientEvents)> {
+    let connector = socket_connector(host.into(), port, tls);
+    start_client(connector, credentials, heartbeat, reconnect).await
+}
+
+/// Connect to the web socket endpoint of a server, using TLS when options are
+/// given. Heartbeats are exchanged with the server as given. If a backoff is
+/// given the connection is made again when it is lost.
+pub async fn connect_web_socket(
+    host: &str,
+    port: u16,
+    tls: Option<TlsOptions>,
+    credentials: Arc<dyn CredentialProvider>,
+    heartbeat: Heartbeat,
+    reconnect: Option<Backoff>,
+) -> io::Result<(Client, ClientEvents)> {
+    let connector = web_socket_connector(host.into(), port, tls);
+    start_client(connector, credentials, heartbeat, reconnect).await
+}
+
+/// Connect to a server listening on a Unix socket at the path, as for
+/// [`connect`].
+#[cfg(unix)]
+pub async fn connect_unix(
+    path: impl AsRef<Path>,
+    credentials: Arc<dyn CredentialProvider>,
+    heartbeat: Heartbeat,
+    reconnect: Option<Backoff>,
+) -> io::Result<(Client, ClientEvents)> {
+    let connector = unix_connector(path.as_ref().into());
+    start_client(connector, credentials, heartbeat, reconnect).await
+}
+
+#[cfg(test)]
+mod test {
+    use crate::authentication::NullCredentials;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_reply_to_requests_and_stream_events() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let mut server = MessageSocket::new(server_stream);
+
+        let server_task = tokio::spawn(async move {
+            let Message::AuthenticationRequest { .. } = server.read().await.unwrap() else {
+                panic!("expected authentication request");
+            };
+            server
+                .write(&Message::AuthenticationResponse {
+                    client_id: "c1".into(),
+                })
+                .await
+                .unwrap();
+
+            // The subscription is request 2, and fails.
+            let Message::SubscriptionRequest { .. } = server.read().await.unwrap() else {
+                panic!("expected subscription request");
+            };
+            server
+                .write(&Message::Error {
+                    request_id: 2,
+                    code: ErrorCode::NotAuthorized,
+                    reason: "not authorized".into(),
+                })
+                .await
+                .unwrap();
+
+            // The notification is request 3, and succeeds.
+            let Message::NotificationRequest { .. } = server.read().await.unwrap() else {
+                panic!("expected notification request");
+            };
+            server
+                .write(&Message::ForwardedMulticastData {
+                    host: "host".into(),
+                    user: "user".into(),
+                    topic: "LSE.VOD".into(),
+                    data_packets: Vec::new(),
+                })
+                .await
+                .unwrap();
+            server.write(&Message::Ack { request_id: 3 }).await.unwrap();
+        });
+
+        let (mut client, mut events) = Client::start(
+            MessageSocket::new(client_stream),
+            Arc::new(NullCredentials {}),
+            Heartbeat::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.client_id(), "c1");
+
+        let error = client.add_subscription("LSE.VOD".into()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        client.add_notification("LSE.*".into()).await.unwrap();
+
+        let Some(ClientEvent::Data { topic, .. }) = events.recv().await else {
+            panic!("expected data");
+        };
+        assert_eq!(topic, "LSE.VOD");
+
+        server_task.await.unwrap();
+        let Some(ClientEvent::Closed(_)) = events.recv().await else {
+            panic!("expected the connection to close");
+        };
+    }
+
+    #[derive(Default)]
+    struct RecordingCallbacks {
+        calls: Vec<String>,
+    }
+
+    impl ClientCallbacks for RecordingCallbacks {
+        fn on_data(&mut self, topic: String, _data_packets: Vec<DataPacket>) -> BoxFuture<'_, ()> {
+            self.calls.push(format!("data {topic}"));
+            Box::pin(async {})
+        }
+
+        fn on_forwarded_subscription(
+            &mut self,
+            user: String,
+            topic: String,
+            count: u32,
+        ) -> BoxFuture<'_, ()> {
+            self.calls
+                .push(format!("subscription {user} {topic} {count}"));
+            Box::pin(async {})
         }
-    };
 
-    Ok(client)
+        fn on_heartbeat(&mut self, count: u64) -> BoxFuture<'_, ()> {
+            self.calls.push(format!("heartbeat {count}"));
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn should_dispatch_events_to_callbacks() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let mut server = MessageSocket::new(server_stream);
+
+        let server_task = tokio::spawn(async move {
+            server.read().await.unwrap();
+            server
+                .write(&Message::AuthenticationResponse {
+                    client_id: "c1".into(),
+                })
+                .await
+                .unwrap();
+            server
+                .write(&Message::ForwardedSubscriptionRequest {
+                    host: "host".into(),
+                    user: "user".into(),
+                    client_id: "c2".into(),
+                    topic: "LSE.VOD".into(),
+                    count: 1,
+                })
+                .await
+                .unwrap();
+            server
+                .write(&Message::ForwardedUnicastData {
+                    host: "host".into(),
+                    user: "user".into(),
+                    client_id: "c2".into(),
+                    topic: "LSE.VOD".into(),
+                    data_packets: Vec::new(),
+                })
+                .await
+                .unwrap();
+            server
+                .write(&Message::Heartbeat { count: 0 })
+                .await
+                .unwrap();
+        });
+
+        let (_client, events) = Client::start(
+            MessageSocket::new(client_stream),
+            Arc::new(NullCredentials {}),
+            Heartbeat::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut callbacks = RecordingCallbacks::default();
+        server_task.await.unwrap();
+        let error = events.dispatch(&mut callbacks).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            callbacks.calls,
+            vec!["subscription user LSE.VOD 1", "data LSE.VOD", "heartbeat 0"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_count_heartbeats_as_requests() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let mut server = MessageSocket::new(server_stream);
+
+        let server_task = tokio::spawn(async move {
+            server.read().await.unwrap();
+            server
+                .write(&Message::AuthenticationResponse {
+                    client_id: "c1".into(),
+                })
+                .await
+                .unwrap();
+
+            // Count every message as the server does, answering subscriptions
+            // with an ack and notifications with an error.
+            let mut request_count = 1;
+            let mut heartbeats = 0;
+            let mut replies = 0;
+            while replies < 3 {
+                let message = server.read().await.unwrap();
+                request_count += 1;
+                let reply = match message {
+                    Message::Heartbeat { .. } => {
+                        heartbeats += 1;
+                        continue;
+                    }
+                    Message::SubscriptionRequest { .. } => Message::Ack {
+                        request_id: request_count,
+                    },
+                    Message::NotificationRequest { .. } => Message::Error {
+                        request_id: request_count,
+                        code: ErrorCode::NotAuthorized,
+                        reason: "not authorized".into(),
+                    },
+                    message => panic!("unexpected message {:?}", message.message_type()),
+                };
+                server.write(&reply).await.unwrap();
+                replies += 1;
+            }
+            heartbeats
+        });
+
+        let (mut client, _events) = Client::start(
+            MessageSocket::new(client_stream),
+            Arc::new(NullCredentials {}),
+            Heartbeat {
+                interval: Duration::from_secs(1),
+                max_missed: 0,
+            },
+        )
+        .await
+        .unwrap();
+
+        // A reply matched to the wrong request would leave the caller waiting.
+        let timeout = Duration::from_secs(10);
+        client.add_subscription("LSE.VOD".into()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let error = tokio::time::timeout(timeout, client.add_notification("LSE.*".into()))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        tokio::time::timeout(timeout, client.add_subscription("LSE.BP".into()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(server_task.await.unwrap() >= 3);
+    }
+
+    #[tokio::test]
+    async fn should_restore_subscriptions_after_reconnecting() {
+        let (first_client, first_server) = tokio::io::duplex(4096);
+        let (second_client, second_server) = tokio::io::duplex(4096);
+        let streams = Arc::new(Mutex::new(vec![second_client, first_client]));
+
+        let connector: Connector = Arc::new(move || {
+            let stream = streams.lock().unwrap().pop();
+            Box::pin(async move {
+                let stream = stream.ok_or(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "no server",
+                ))?;
+                Ok(Box::new(MessageSocket::new(stream)) as Box<dyn MessageStream + Send>)
+            })
+        });
+
+        let server_task = tokio::spawn(async move {
+            let mut server = MessageSocket::new(first_server);
+            server.read().await.unwrap();
+            server
+                .write(&Message::AuthenticationResponse {
+                    client_id: "c1".into(),
+                })
+                .await
+                .unwrap();
+            for request_id in 2..4 {
+                server.read().await.unwrap();
+                server.write(&Message::Ack { request_id }).await.unwrap();
+            }
+            drop(server);
+
+            let mut server = MessageSocket::new(second_server);
+            server.read().await.unwrap();
+            server
+                .write(&Message::AuthenticationResponse {
+                    client_id: "c2".into(),
+                })
+                .await
+                .unwrap();
+            let mut restored = vec![server.read().await.unwrap(), server.read().await.unwrap()];
+            restored.sort_by_key(|message| format!("{:?}", message));
+            restored
+        });
+
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            max_attempts: Some(2),
+        };
+        let (mut client, mut events) = Client::start_with_reconnect(
+            connector,
+            Arc::new(NullCredentials {}),
+            Heartbeat::default(),
+            backoff,
+        )
+        .await
+        .unwrap();
+
+        client.add_subscription("LSE.VOD".into()).await.unwrap();
+        client.add_notification("LSE.*".into()).await.unwrap();
+
+        let Some(ClientEvent::Disconnected(_)) = events.recv().await else {
+            panic!("expected the connection to be lost");
+        };
+        let Some(ClientEvent::Reconnecting { attempt: 1, .. }) = events.recv().await else {
+            panic!("expected to reconnect");
+        };
+        let Some(ClientEvent::Connected { client_id }) = events.recv().await else {
+            panic!("expected to connect");
+        };
+        assert_eq!(client_id, "c2");
+        assert_eq!(client.client_id(), "c2");
+
+        assert_eq!(
+            server_task.await.unwrap(),
+            vec![
+                Message::NotificationRequest {
+                    pattern: "LSE.*".into(),
+                    is_add: true,
+                },
+                Message::SubscriptionRequest {
+                    topic: "LSE.VOD".into(),
+                    is_add: true,
+                },
+            ]
+        );
+
+        // The second server has gone, and there are no more servers.
+        let Some(ClientEvent::Disconnected(_)) = events.recv().await else {
+            panic!("expected the connection to be lost");
+        };
+        for _ in 0..2 {
+            let Some(ClientEvent::Reconnecting { .. }) = events.recv().await else {
+                panic!("expected to reconnect");
+            };
+        }
+        let Some(ClientEvent::Closed(error)) = events.recv().await else {
+            panic!("expected the connection to close");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    /// A stream which fails to write after a number of messages.
+    struct FailingStream {
+        inner: MessageSocket<tokio::io::DuplexStream>,
+        writes: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl MessageStream for FailingStream {
+        async fn read(&mut self) -> io::Result<Message> {
+            self.inner.read().await
+        }
+
+        async fn write(&mut self, message: &Message) -> io::Result<()> {
+            if self.writes == 0 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "write failed"));
+            }
+            self.writes -= 1;
+            self.inner.write(message).await
+        }
+    }
+
+    async fn accept(server: &mut MessageSocket<tokio::io::DuplexStream>, client_id: &str) {
+        server.read().await.unwrap();
+        server
+            .write(&Message::AuthenticationResponse {
+                client_id: client_id.into(),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_restore_subscriptions_after_a_failed_reconnect() {
+        let (first_client, first_server) = tokio::io::duplex(4096);
+        let (second_client, second_server) = tokio::io::duplex(4096);
+        let (third_client, third_server) = tokio::io::duplex(4096);
+        let (fourth_client, fourth_server) = tokio::io::duplex(4096);
+        // The second connection fails after authenticating and restoring one
+        // request.
+        let streams: Vec<Box<dyn MessageStream + Send>> = vec![
+            Box::new(MessageSocket::new(fourth_client)),
+            Box::new(MessageSocket::new(third_client)),
+            Box::new(FailingStream {
+                inner: MessageSocket::new(second_client),
+                writes: 2,
+            }),
+            Box::new(MessageSocket::new(first_client)),
+        ];
+        let streams = Arc::new(Mutex::new(streams));
+
+        let connector: Connector = Arc::new(move || {
+            let stream = streams.lock().unwrap().pop();
+            Box::pin(async move {
+                stream.ok_or(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "no server",
+                ))
+            })
+        });
+
+        let (handled_tx, handled_rx) = oneshot::channel();
+        let server_task = tokio::spawn(async move {
+            let mut server = MessageSocket::new(first_server);
+            accept(&mut server, "c1").await;
+            for request_id in 2..4 {
+                server.read().await.unwrap();
+                server.write(&Message::Ack { request_id }).await.unwrap();
+            }
+            drop(server);
+
+            let mut second = MessageSocket::new(second_server);
+            accept(&mut second, "c2").await;
+            second.read().await.unwrap();
+
+            // The server rejects the first request restored on the third
+            // connection, so only the second is restored on the fourth.
+            let mut server = MessageSocket::new(third_server);
+            accept(&mut server, "c3").await;
+            let rejected = server.read().await.unwrap();
+            let accepted = server.read().await.unwrap();
+            server
+                .write(&Message::Error {
+                    request_id: 2,
+                    code: ErrorCode::NotAuthorized,
+                    reason: "not authorized".into(),
+                })
+                .await
+                .unwrap();
+            server.write(&Message::Ack { request_id: 3 }).await.unwrap();
+            // Wait for the client to handle the replies before closing.
+            handled_rx.await.unwrap();
+            drop(server);
+
+            let mut server = MessageSocket::new(fourth_server);
+            accept(&mut server, "c4").await;
+            let restored = server.read().await.unwrap();
+            drop(second);
+            (rejected, accepted, restored)
+        });
+
+        let backoff = Backoff {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            max_attempts: Some(2),
+        };
+        let (mut client, mut events) = Client::start_with_reconnect(
+            connector,
+            Arc::new(NullCredentials {}),
+            Heartbeat::default(),
+            backoff,
+        )
+        .await
+        .unwrap();
+
+        client.add_subscription("LSE.VOD".into()).await.unwrap();
+        client.add_notification("LSE.*".into()).await.unwrap();
+
+        let mut handled_tx = Some(handled_tx);
+        let mut connected = Vec::new();
+        while connected.len() < 3 {
+            match events.recv().await {
+                Some(ClientEvent::Connected { client_id }) => connected.push(client_id),
+                Some(ClientEvent::Error { code, .. }) => {
+                    assert_eq!(code, ErrorCode::NotAuthorized);
+                    let _ = handled_tx.take().unwrap().send(());
+                }
+                Some(ClientEvent::Closed(error)) => panic!("closed: {error}"),
+                Some(_) => {}
+                None => panic!("expected to connect"),
+            }
+        }
+        assert_eq!(connected, vec!["c2", "c3", "c4"]);
+
+        let (rejected, accepted, restored) = server_task.await.unwrap();
+        assert_ne!(rejected, accepted);
+        assert_eq!(restored, accepted);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_close_when_the_server_misses_heartbeats() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let mut server = MessageSocket::new(server_stream);
+
+        let server_task = tokio::spawn(async move {
+            server.read().await.unwrap();
+            server
+                .write(&Message::AuthenticationResponse {
+                    client_id: "c1".into(),
+                })
+                .await
+                .unwrap();
+
+            // The server stops sending, but the client keeps sending.
+            for expected in 0..2 {
+                let Message::Heartbeat { count } = server.read().await.unwrap() else {
+                    panic!("expected heartbeat");
+                };
+                assert_eq!(count, expected);
+            }
+            server
+        });
+
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(10),
+            max_missed: 3,
+        };
+        let (_client, mut events) = Client::start(
+            MessageSocket::new(client_stream),
+            Arc::new(NullCredentials {}),
+            heartbeat,
+        )
+        .await
+        .unwrap();
+
+        let started = Instant::now();
+        let Some(ClientEvent::Closed(error)) = events.recv().await else {
+            panic!("expected the connection to close");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+
+        server_task.await.unwrap();
+    }
 }
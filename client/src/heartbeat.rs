@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+/// How often the client sends heartbeats to the server, and how long it waits
+/// to hear from the server before treating the connection as lost.
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    /// The interval between heartbeats. This should match the heartbeat
+    /// interval of the server.
+    pub interval: Duration,
+    /// Close the connection when nothing has been received from the server
+    /// for this many intervals. Zero never closes the connection.
+    pub max_missed: u32,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(30),
+            max_missed: 3,
+        }
+    }
+}
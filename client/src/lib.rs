@@ -0,0 +1,26 @@
+//! A client for the squawkbus message bus.
+//!
+//! A connection is made with [`connect`], [`connect_web_socket`] or
+//! [`connect_unix`], which return a [`Client`] handle for making requests and a stream of
+//! [`ClientEvent`]s from the server. Given a [`Backoff`], a lost connection
+//! is made again and its subscriptions and notifications restored.
+
+pub mod authentication;
+pub mod client;
+pub mod heartbeat;
+pub mod reconnect;
+pub mod tls;
+
+pub use authentication::{
+    BasicCredentials, CertificateCredentials, CredentialProvider, NullCredentials, PeerCredentials,
+    TokenCredentials,
+};
+#[cfg(unix)]
+pub use client::connect_unix;
+pub use client::{
+    connect, connect_web_socket, Client, ClientCallbacks, ClientEvent, ClientEvents,
+    ClientProtocol, Connector,
+};
+pub use heartbeat::Heartbeat;
+pub use reconnect::Backoff;
+pub use tls::TlsOptions;
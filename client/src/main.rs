@@ -1,17 +1,15 @@
 use std::error::Error;
 use std::net::ToSocketAddrs;
 
+use client::authentication::create_credential_provider;
+use client::tls::{create_tls_stream, TlsOptions};
 use protocol::communicate;
-use tls::create_tls_stream;
 
 use options::Options;
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, UnixStream};
 
-mod authentication;
-mod client;
 mod options;
 mod protocol;
-mod tls;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -21,6 +19,18 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let options = Options::load();
 
+    let credentials = create_credential_provider(
+        &options.authentication_mode,
+        &options.username,
+        &options.password,
+    )?;
+
+    if let Some(path) = options.unix_socket {
+        let socket = UnixStream::connect(path).await?;
+        communicate(socket, credentials.as_ref()).await;
+        return Ok(());
+    }
+
     let endpoint = format!("{}:{}", options.host.as_str(), options.port);
 
     let addr = endpoint
@@ -31,23 +41,15 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let socket = TcpStream::connect(&addr).await?;
     match options.tls {
         true => {
-            let stream = create_tls_stream(options.host.as_str(), &options.cafile, socket).await?;
-            communicate(
-                stream,
-                &options.authentication_mode,
-                &options.username,
-                &options.password,
-            )
-            .await;
+            let tls_options = TlsOptions {
+                cafile: options.cafile,
+                client_certificate: options.certfile.zip(options.keyfile),
+            };
+            let stream = create_tls_stream(options.host.as_str(), &tls_options, socket).await?;
+            communicate(stream, credentials.as_ref()).await;
         }
         false => {
-            communicate(
-                socket,
-                &options.authentication_mode,
-                &options.username,
-                &options.password,
-            )
-            .await;
+            communicate(socket, credentials.as_ref()).await;
         }
     }
 
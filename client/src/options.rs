@@ -25,6 +25,10 @@ pub struct Options {
     #[argh(option, short = 'p', default = "default_port()")]
     pub port: u16,
 
+    /// connect to a unix socket at this path instead of the host and port
+    #[argh(option, short = 'u')]
+    pub unix_socket: Option<PathBuf>,
+
     /// use tls
     #[argh(switch, short = 't')]
     pub tls: bool,
@@ -33,6 +37,14 @@ pub struct Options {
     #[argh(option, short = 'c')]
     pub cafile: Option<PathBuf>,
 
+    /// client certificate file, given with the key file
+    #[argh(option)]
+    pub certfile: Option<PathBuf>,
+
+    /// client key file, given with the certificate file
+    #[argh(option)]
+    pub keyfile: Option<PathBuf>,
+
     /// authentication mode
     #[argh(option, short = 'm', default = "default_authentication_mode()")]
     pub authentication_mode: String,
@@ -41,13 +53,21 @@ pub struct Options {
     #[argh(option, short = 'U')]
     pub username: Option<String>,
 
-    /// password
+    /// password, or the token for jwt authentication
     #[argh(option, short = 'P')]
     pub password: Option<String>,
 }
 
 impl Options {
     pub fn load() -> Self {
-        argh::from_env()
+        let options: Options = argh::from_env();
+        // A client certificate needs its key, and a key its certificate.
+        if options.certfile.is_some() != options.keyfile.is_some() {
+            let command = std::env::args().next().unwrap_or_default();
+            eprintln!("--certfile and --keyfile must be given together.");
+            eprintln!("Run {command} --help for more information.");
+            std::process::exit(1);
+        }
+        options
     }
 }
@@ -3,18 +3,14 @@ use std::collections::{HashMap, HashSet};
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
 
 use common::{
-    messages::{DataPacket, Message},
+    messages::{DataPacket, Message, ReplayFrom},
     MessageSocket, MessageStream,
 };
 
-use crate::authentication::authenticate;
+use client::authentication::{authenticate, CredentialProvider};
 
-pub async fn communicate<S>(
-    stream: S,
-    mode: &String,
-    username: &Option<String>,
-    password: &Option<String>,
-) where
+pub async fn communicate<S>(stream: S, credentials: &dyn CredentialProvider)
+where
     S: AsyncRead + AsyncWrite + Unpin + Send,
 {
     println!("connected");
@@ -24,9 +20,7 @@ pub async fn communicate<S>(
     let stdin = tokio::io::stdin();
     let mut stdin_reader = BufReader::new(stdin);
 
-    let client_id = authenticate(&mut stream, mode, username, password)
-        .await
-        .unwrap();
+    let client_id = authenticate(&mut stream, credentials).await.unwrap();
     println!("Authenticted as {client_id}");
 
     loop {
@@ -35,6 +29,7 @@ pub async fn communicate<S>(
         println!("Enter request:");
         println!("\tpublish <topic> <entitlements> <message>");
         println!("\tsubscribe <topic>");
+        println!("\treplay <topic> (sequence | timestamp) <value>");
         println!("\tnotify <pattern>");
 
         tokio::select! {
@@ -54,6 +49,10 @@ pub async fn communicate<S>(
             result = stream.read() => {
                 let message = result.unwrap();
                 println!("Received message {message:?}");
+                // The server closes connections which send nothing.
+                if let Message::Heartbeat { count } = message {
+                    stream.write(&Message::Heartbeat { count }).await.unwrap();
+                }
             }
         }
     }
@@ -64,8 +63,9 @@ fn parse_message(line: &str) -> Result<Message, &'static str> {
     match args[0] {
         "publish" => handle_publish(args),
         "subscribe" => handle_subscribe(args),
+        "replay" => handle_replay(args),
         "notify" => handle_notify(args),
-        _ => Err("usage: publish/subscribe/notify"),
+        _ => Err("usage: publish/subscribe/replay/notify"),
     }
 }
 
@@ -114,6 +114,22 @@ fn handle_subscribe(args: Vec<&str>) -> Result<Message, &'static str> {
     Ok(message)
 }
 
+fn handle_replay(args: Vec<&str>) -> Result<Message, &'static str> {
+    const USAGE: &str = "usage: replay <topic> (sequence | timestamp) <value>";
+    if args.len() != 4 {
+        return Err(USAGE);
+    }
+    let topic = args[1].to_string();
+    let value: u64 = args[3].parse().map_err(|_| USAGE)?;
+    let from = match args[2] {
+        "sequence" => ReplayFrom::Sequence(value),
+        "timestamp" => ReplayFrom::Timestamp(value),
+        _ => return Err(USAGE),
+    };
+    let message = Message::ReplaySubscriptionRequest { topic, from };
+    Ok(message)
+}
+
 fn handle_notify(args: Vec<&str>) -> Result<Message, &'static str> {
     if args.len() != 2 {
         return Err("usage: subscribe <topic>");
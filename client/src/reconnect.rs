@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use common::messages::Message;
+
+/// How long to wait between attempts to reconnect. The delay starts at the
+/// initial delay and doubles with each attempt, up to the maximum delay.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after this many attempts. `None` tries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before an attempt, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+/// The subscriptions and notifications the server has acknowledged, with the
+/// number of requests for each, as the server counts them.
+#[derive(Debug, Default)]
+pub(crate) struct ActiveRequests {
+    subscriptions: HashMap<String, u32>,
+    notifications: HashMap<String, u32>,
+}
+
+fn update(counts: &mut HashMap<String, u32>, key: &str, is_add: bool) {
+    if is_add {
+        *counts.entry(key.to_string()).or_default() += 1;
+        return;
+    }
+
+    let Some(count) = counts.get_mut(key) else {
+        return;
+    };
+    *count -= 1;
+    if *count == 0 {
+        counts.remove(key);
+    }
+}
+
+impl ActiveRequests {
+    /// Record a request the server has acknowledged.
+    pub fn apply(&mut self, message: &Message) {
+        match message {
+            Message::SubscriptionRequest { topic, is_add } => {
+                update(&mut self.subscriptions, topic, *is_add)
+            }
+            Message::NotificationRequest { pattern, is_add } => {
+                update(&mut self.notifications, pattern, *is_add)
+            }
+            _ => {}
+        }
+    }
+
+    /// Forget a request the server rejected when it was made again.
+    pub fn revert(&mut self, message: &Message) {
+        match message {
+            Message::SubscriptionRequest { topic, is_add } => {
+                update(&mut self.subscriptions, topic, !*is_add)
+            }
+            Message::NotificationRequest { pattern, is_add } => {
+                update(&mut self.notifications, pattern, !*is_add)
+            }
+            _ => {}
+        }
+    }
+
+    /// The requests which make the same subscriptions and notifications on a
+    /// new connection.
+    pub fn restore(&self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for (pattern, count) in &self.notifications {
+            for _ in 0..*count {
+                messages.push(Message::NotificationRequest {
+                    pattern: pattern.clone(),
+                    is_add: true,
+                });
+            }
+        }
+        for (topic, count) in &self.subscriptions {
+            for _ in 0..*count {
+                messages.push(Message::SubscriptionRequest {
+                    topic: topic.clone(),
+                    is_add: true,
+                });
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_back_off_to_the_maximum_delay() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
+        assert_eq!(backoff.delay(5), Duration::from_secs(16));
+        assert_eq!(backoff.delay(6), Duration::from_secs(30));
+        assert_eq!(backoff.delay(100), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn should_restore_request_counts() {
+        let subscribe = |topic: &str, is_add| Message::SubscriptionRequest {
+            topic: topic.into(),
+            is_add,
+        };
+
+        let mut active = ActiveRequests::default();
+        active.apply(&subscribe("LSE.VOD", true));
+        active.apply(&subscribe("LSE.VOD", true));
+        active.apply(&subscribe("LSE.BT", true));
+        active.apply(&subscribe("LSE.BT", false));
+        active.apply(&Message::NotificationRequest {
+            pattern: "LSE.*".into(),
+            is_add: true,
+        });
+
+        assert_eq!(
+            active.restore(),
+            vec![
+                Message::NotificationRequest {
+                    pattern: "LSE.*".into(),
+                    is_add: true,
+                },
+                subscribe("LSE.VOD", true),
+                subscribe("LSE.VOD", true),
+            ]
+        );
+
+        active.revert(&subscribe("LSE.VOD", true));
+        assert_eq!(active.subscriptions.get("LSE.VOD"), Some(&1));
+    }
+}
@@ -1,44 +1,69 @@
 use std::fs::File;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use pki_types::ServerName;
+use pki_types::{CertificateDer, PrivateKeyDer, ServerName};
 use tokio::net::TcpStream;
 use tokio_rustls::{client::TlsStream, rustls, TlsConnector};
 
+/// The files used to make a TLS connection.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// Trust the certificates in this file rather than the well known roots.
+    pub cafile: Option<PathBuf>,
+    /// A certificate and key file to present to the server, for servers
+    /// which authenticate clients by their certificate.
+    pub client_certificate: Option<(PathBuf, PathBuf)>,
+}
+
 pub async fn create_tls_stream(
     host: &str,
-    cafile: &Option<PathBuf>,
+    options: &TlsOptions,
     stream: TcpStream,
 ) -> io::Result<TlsStream<TcpStream>> {
-    let (tls_connector, domain) = create_tls_connector(host, cafile);
+    let (tls_connector, domain) = create_tls_connector(host, options)?;
     tls_connector.connect(domain, stream).await
 }
 
 pub fn create_tls_connector<'a>(
     host: &str,
-    cafile: &Option<PathBuf>,
-) -> (TlsConnector, ServerName<'a>) {
+    options: &TlsOptions,
+) -> io::Result<(TlsConnector, ServerName<'a>)> {
     let mut root_cert_store = rustls::RootCertStore::empty();
-    if let Some(cafile) = cafile {
-        let mut pem = io::BufReader::new(File::open(cafile).expect("Should open cert file"));
-        for cert in rustls_pemfile::certs(&mut pem) {
-            root_cert_store.add(cert.unwrap()).unwrap();
+    if let Some(cafile) = &options.cafile {
+        for cert in load_certs(cafile)? {
+            root_cert_store
+                .add(cert)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
         }
     } else {
         root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
     }
 
-    let config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_cert_store)
-        .with_no_client_auth(); // i guess this was previously the default?
+    let builder = rustls::ClientConfig::builder().with_root_certificates(root_cert_store);
+    let config = match &options.client_certificate {
+        Some((certfile, keyfile)) => builder
+            .with_client_auth_cert(load_certs(certfile)?, load_key(keyfile)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => builder.with_no_client_auth(),
+    };
     let connector = TlsConnector::from(Arc::new(config));
 
     let domain = pki_types::ServerName::try_from(host)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid dns name"))
-        .unwrap()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid dns name"))?
         .to_owned();
 
-    (connector, domain)
+    Ok((connector, domain))
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    rustls_pemfile::certs(&mut io::BufReader::new(File::open(path)?)).collect()
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut io::BufReader::new(File::open(path)?))?.ok_or(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "no private key found",
+    ))
 }
@@ -0,0 +1,56 @@
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use wildmatch::WildMatch;
+
+use common::TopicIndex;
+
+const EXCHANGES: [&str; 4] = ["NYSE", "NASDAQ", "LSE", "XETRA"];
+
+/// A typical market data load: a literal subscription per instrument, plus a
+/// wildcard per exchange and a few broad patterns.
+fn patterns(instruments: usize) -> Vec<String> {
+    let mut patterns = Vec::new();
+    for exchange in EXCHANGES {
+        for instrument in 0..instruments {
+            patterns.push(format!("{exchange}.SYM{instrument}"));
+        }
+        patterns.push(format!("{exchange}.*"));
+        patterns.push(format!("{exchange}.SYM1*"));
+    }
+    patterns.push("*.SYM0".to_string());
+    patterns.push("*".to_string());
+    patterns
+}
+
+fn bench_matches(c: &mut Criterion) {
+    for instruments in [1_000, 10_000] {
+        let patterns = patterns(instruments);
+        let topic = format!("LSE.SYM{}", instruments / 2);
+
+        let linear: Vec<(String, WildMatch)> = patterns
+            .iter()
+            .map(|pattern| (pattern.clone(), WildMatch::new(pattern)))
+            .collect();
+
+        let mut index = TopicIndex::new();
+        for pattern in &patterns {
+            index.insert(pattern, ());
+        }
+
+        let mut group = c.benchmark_group(format!("{} patterns", patterns.len()));
+        group.bench_function("linear", |b| {
+            b.iter(|| {
+                linear
+                    .iter()
+                    .filter(|(_, wildmatch)| wildmatch.matches(black_box(&topic)))
+                    .count()
+            })
+        });
+        group.bench_function("index", |b| {
+            b.iter(|| index.matches(black_box(&topic)).len())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_matches);
+criterion_main!(benches);
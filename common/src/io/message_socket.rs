@@ -1,15 +1,18 @@
 use async_trait::async_trait;
 use std::io::Cursor;
 
-use tokio::io::{
-    self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
-};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 
 use crate::{Serializable, message_stream::MessageStream, messages::Message};
 
+/// Reads and writes length prefixed frames.
+///
+/// Reading is cancel safe: bytes received for a partial frame are kept until
+/// the rest arrives, so a read can be raced against other futures.
 pub struct MessageSocket<T> {
-    reader: BufReader<ReadHalf<T>>,
+    reader: ReadHalf<T>,
     writer: WriteHalf<T>,
+    buffer: Vec<u8>,
 }
 
 impl<T> MessageSocket<T>
@@ -17,9 +20,27 @@ where
     T: AsyncRead + AsyncWrite + Unpin,
 {
     pub fn new(stream: T) -> MessageSocket<T> {
-        let (read_half, writer) = tokio::io::split(stream);
-        let reader = BufReader::new(read_half);
-        MessageSocket { reader, writer }
+        let (reader, writer) = tokio::io::split(stream);
+        MessageSocket {
+            reader,
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Remove the next frame from the buffer, if it has been received.
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        let len_buf: [u8; 4] = self.buffer.get(..4)?.try_into().unwrap();
+        let len = u32::from_be_bytes(len_buf) as usize;
+        if self.buffer.len() < 4 + len {
+            return None;
+        }
+
+        log::trace!("Reading a frame of {len} bytes.");
+
+        let frame = self.buffer[4..4 + len].to_vec();
+        self.buffer.drain(..4 + len);
+        Some(frame)
     }
 }
 
@@ -29,20 +50,19 @@ where
     T: AsyncRead + AsyncWrite + Unpin + Send,
 {
     async fn read(&mut self) -> io::Result<Message> {
-        // Read the frame length.
-        let mut len_buf = [0_u8; 4];
-        self.reader.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf);
-
-        log::trace!("Reading a frame of {len} bytes.");
-
-        // Read the frame with the known length.
-        let mut buf: Vec<u8> = vec![0; len as usize];
-        self.reader.read_exact(&mut buf).await?;
-        let mut cursor = Cursor::new(buf);
+        loop {
+            if let Some(frame) = self.take_frame() {
+                // Parse the message.
+                let mut cursor = Cursor::new(frame);
+                return Message::deserialize(&mut cursor);
+            }
 
-        // Parse the message.
-        Message::deserialize(&mut cursor)
+            // Only the buffer is changed while waiting, so the read can be
+            // cancelled without losing data.
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+        }
     }
 
     async fn write(&mut self, message: &Message) -> io::Result<()> {
@@ -58,3 +78,31 @@ where
         self.writer.write_all(cursor.get_ref().as_slice()).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_keep_partial_frames_when_cancelled() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut writer = MessageSocket::new(client);
+        let mut reader = MessageSocket::new(server);
+
+        let message = Message::Heartbeat { count: 42 };
+        let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        (message.size() as u32).serialize(&mut cursor).unwrap();
+        message.serialize(&mut cursor).unwrap();
+        let frame = cursor.into_inner();
+
+        // Send part of the frame, and give up waiting for the rest.
+        writer.writer.write_all(&frame[..3]).await.unwrap();
+        let result = tokio::time::timeout(Duration::from_millis(10), reader.read()).await;
+        assert!(result.is_err());
+
+        writer.writer.write_all(&frame[3..]).await.unwrap();
+        assert_eq!(reader.read().await.unwrap(), message);
+    }
+}
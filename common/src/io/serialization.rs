@@ -148,6 +148,33 @@ impl Serializable for Vec<u8> {
     }
 }
 
+impl Serializable for Vec<String> {
+    fn serialize(&self, writer: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        (self.len() as u32).serialize(writer)?;
+        for value in self {
+            value.serialize(writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut Cursor<Vec<u8>>) -> io::Result<Self> {
+        let len = u32::deserialize(reader)?;
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(String::deserialize(reader)?);
+        }
+        Ok(values)
+    }
+
+    fn size(&self) -> usize {
+        let mut len = size_of::<u32>();
+        for value in self {
+            len += value.size();
+        }
+        len
+    }
+}
+
 impl Serializable for HashSet<i32> {
     fn serialize(&self, writer: &mut Cursor<Vec<u8>>) -> io::Result<()> {
         (self.len() as u32).serialize(writer)?;
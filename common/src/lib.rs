@@ -1,4 +1,7 @@
 pub mod messages;
 
+pub mod topic_index;
+pub use topic_index::TopicIndex;
+
 pub mod io;
 pub use io::*;
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::io::{self, Cursor, ErrorKind};
+
+use crate::io::Serializable;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[repr(u8)]
+pub enum ErrorCode {
+    SlowConsumer = 1,
+    NotAuthorized = 2,
+    AuthenticationFailed = 3,
+    NotFound = 4,
+    InvalidRequest = 5,
+    Internal = 6,
+}
+
+impl TryFrom<u8> for ErrorCode {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(ErrorCode::SlowConsumer),
+            2 => Ok(ErrorCode::NotAuthorized),
+            3 => Ok(ErrorCode::AuthenticationFailed),
+            4 => Ok(ErrorCode::NotFound),
+            5 => Ok(ErrorCode::InvalidRequest),
+            6 => Ok(ErrorCode::Internal),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ErrorCode> for u8 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::SlowConsumer => 1,
+            ErrorCode::NotAuthorized => 2,
+            ErrorCode::AuthenticationFailed => 3,
+            ErrorCode::NotFound => 4,
+            ErrorCode::InvalidRequest => 5,
+            ErrorCode::Internal => 6,
+        }
+    }
+}
+
+/// Map the kind of a failed request to the code sent to the client.
+impl From<ErrorKind> for ErrorCode {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::PermissionDenied => ErrorCode::NotAuthorized,
+            ErrorKind::NotFound => ErrorCode::NotFound,
+            ErrorKind::InvalidInput | ErrorKind::InvalidData | ErrorKind::Unsupported => {
+                ErrorCode::InvalidRequest
+            }
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Map a code received from the server to the kind of error for the caller.
+impl From<ErrorCode> for ErrorKind {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::SlowConsumer => ErrorKind::Other,
+            ErrorCode::NotAuthorized => ErrorKind::PermissionDenied,
+            ErrorCode::AuthenticationFailed => ErrorKind::PermissionDenied,
+            ErrorCode::NotFound => ErrorKind::NotFound,
+            ErrorCode::InvalidRequest => ErrorKind::InvalidInput,
+            ErrorCode::Internal => ErrorKind::Other,
+        }
+    }
+}
+
+impl Serializable for ErrorCode {
+    fn serialize(&self, writer: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        let byte: u8 = (*self).into();
+        byte.serialize(writer)?;
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut Cursor<Vec<u8>>) -> io::Result<ErrorCode> {
+        let byte = u8::deserialize(reader)?;
+        ErrorCode::try_from(byte).map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid"))
+    }
+
+    fn size(&self) -> usize {
+        1
+    }
+}
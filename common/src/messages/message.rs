@@ -2,7 +2,9 @@ use std::io::{self, Cursor};
 
 use crate::io::Serializable;
 
+use super::error_code::ErrorCode;
 use super::message_type::MessageType;
+use super::replay_from::ReplayFrom;
 
 use super::DataPacket;
 
@@ -55,6 +57,27 @@ pub enum Message {
     Heartbeat {
         count: u64,
     },
+    /// The reply to a request which failed. Request ids are not sent with
+    /// requests: both ends count every message the client sends, heartbeats
+    /// and data included, from 1 for the authentication request. An error
+    /// which is not a reply has a request id of 0.
+    Error {
+        request_id: u64,
+        code: ErrorCode,
+        reason: String,
+    },
+    /// The reply to a subscription or notification request which succeeded,
+    /// with the request id counted as for an error.
+    Ack {
+        request_id: u64,
+    },
+    ReplaySubscriptionRequest {
+        topic: String,
+        from: ReplayFrom,
+    },
+    FederationRequest {
+        broker_id: String,
+    },
 }
 
 impl Message {
@@ -72,6 +95,10 @@ impl Message {
             Message::SubscriptionRequest { .. } => MessageType::SubscriptionRequest,
             Message::UnicastData { .. } => MessageType::UnicastData,
             Message::Heartbeat { .. } => MessageType::Heartbeat,
+            Message::Error { .. } => MessageType::Error,
+            Message::Ack { .. } => MessageType::Ack,
+            Message::ReplaySubscriptionRequest { .. } => MessageType::ReplaySubscriptionRequest,
+            Message::FederationRequest { .. } => MessageType::FederationRequest,
         }
     }
 }
@@ -163,6 +190,29 @@ impl Serializable for Message {
                 let count = u64::deserialize(reader)?;
                 Ok(Message::Heartbeat { count })
             }
+            Ok(MessageType::Error) => {
+                let request_id = u64::deserialize(reader)?;
+                let code = ErrorCode::deserialize(reader)?;
+                let reason = String::deserialize(reader)?;
+                Ok(Message::Error {
+                    request_id,
+                    code,
+                    reason,
+                })
+            }
+            Ok(MessageType::Ack) => {
+                let request_id = u64::deserialize(reader)?;
+                Ok(Message::Ack { request_id })
+            }
+            Ok(MessageType::ReplaySubscriptionRequest) => {
+                let topic = String::deserialize(reader)?;
+                let from = ReplayFrom::deserialize(reader)?;
+                Ok(Message::ReplaySubscriptionRequest { topic, from })
+            }
+            Ok(MessageType::FederationRequest) => {
+                let broker_id = String::deserialize(reader)?;
+                Ok(Message::FederationRequest { broker_id })
+            }
             Err(error) => Err(error),
         }
     }
@@ -254,6 +304,29 @@ impl Serializable for Message {
                 count.serialize(writer)?;
                 Ok(())
             }
+            Message::Error {
+                request_id,
+                code,
+                reason,
+            } => {
+                request_id.serialize(writer)?;
+                code.serialize(writer)?;
+                reason.serialize(writer)?;
+                Ok(())
+            }
+            Message::Ack { request_id } => {
+                request_id.serialize(writer)?;
+                Ok(())
+            }
+            Message::ReplaySubscriptionRequest { topic, from } => {
+                topic.serialize(writer)?;
+                from.serialize(writer)?;
+                Ok(())
+            }
+            Message::FederationRequest { broker_id } => {
+                broker_id.serialize(writer)?;
+                Ok(())
+            }
         }
     }
 
@@ -303,6 +376,14 @@ impl Serializable for Message {
                     data_packets,
                 } => client_id.size() + topic.size() + data_packets.size(),
                 Message::Heartbeat { count } => count.size(),
+                Message::Error {
+                    request_id,
+                    code,
+                    reason,
+                } => request_id.size() + code.size() + reason.size(),
+                Message::Ack { request_id } => request_id.size(),
+                Message::ReplaySubscriptionRequest { topic, from } => topic.size() + from.size(),
+                Message::FederationRequest { broker_id } => broker_id.size(),
             }
     }
 }
@@ -484,4 +565,64 @@ mod test_message {
         let round_trip = Message::deserialize(&mut cursor).unwrap();
         assert_eq!(initial, round_trip);
     }
+
+    #[test]
+    fn should_roundtrip_error() {
+        let initial = Message::Error {
+            request_id: 12,
+            code: ErrorCode::NotAuthorized,
+            reason: "not authorized".into(),
+        };
+
+        let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        initial.serialize(&mut cursor).expect("should serialize");
+
+        assert_eq!(initial.size(), cursor.position() as usize);
+
+        cursor.rewind().expect("should rewind");
+        let round_trip = Message::deserialize(&mut cursor).unwrap();
+        assert_eq!(initial, round_trip);
+    }
+
+    #[test]
+    fn should_roundtrip_ack() {
+        let initial = Message::Ack { request_id: 12 };
+
+        let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        initial.serialize(&mut cursor).expect("should serialize");
+
+        cursor.rewind().expect("should rewind");
+        let round_trip = Message::deserialize(&mut cursor).unwrap();
+        assert_eq!(initial, round_trip);
+    }
+
+    #[test]
+    fn should_roundtrip_replay_subscription_request() {
+        let initial = Message::ReplaySubscriptionRequest {
+            topic: "LSE.*".into(),
+            from: ReplayFrom::Timestamp(1700000000000),
+        };
+
+        let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        initial.serialize(&mut cursor).expect("should serialize");
+        assert_eq!(cursor.get_ref().len(), initial.size());
+
+        cursor.rewind().expect("should rewind");
+        let round_trip = Message::deserialize(&mut cursor).unwrap();
+        assert_eq!(initial, round_trip);
+    }
+
+    #[test]
+    fn should_roundtrip_federation_request() {
+        let initial = Message::FederationRequest {
+            broker_id: "67e55044-10b1-426f-9247-bb680e5fe0c8".into(),
+        };
+
+        let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        initial.serialize(&mut cursor).expect("should serialize");
+
+        cursor.rewind().expect("should rewind");
+        let round_trip = Message::deserialize(&mut cursor).unwrap();
+        assert_eq!(initial, round_trip);
+    }
 }
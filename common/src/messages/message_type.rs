@@ -15,12 +15,16 @@ pub enum MessageType {
     ForwardedMulticastData = 8,
     ForwardedUnicastData = 9,
     Heartbeat = 10,
+    Error = 11,
+    Ack = 12,
+    ReplaySubscriptionRequest = 13,
+    FederationRequest = 14,
 }
 
 impl TryFrom<u8> for MessageType {
     type Error = ();
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
+    fn try_from(value: u8) -> Result<Self, ()> {
         match value {
             1 => Ok(MessageType::AuthenticationRequest),
             2 => Ok(MessageType::AuthenticationResponse),
@@ -32,6 +36,10 @@ impl TryFrom<u8> for MessageType {
             8 => Ok(MessageType::ForwardedMulticastData),
             9 => Ok(MessageType::ForwardedUnicastData),
             10 => Ok(MessageType::Heartbeat),
+            11 => Ok(MessageType::Error),
+            12 => Ok(MessageType::Ack),
+            13 => Ok(MessageType::ReplaySubscriptionRequest),
+            14 => Ok(MessageType::FederationRequest),
             _ => Err(()),
         }
     }
@@ -50,6 +58,10 @@ impl Into<u8> for MessageType {
             MessageType::ForwardedMulticastData => 8,
             MessageType::ForwardedUnicastData => 9,
             MessageType::Heartbeat => 10,
+            MessageType::Error => 11,
+            MessageType::Ack => 12,
+            MessageType::ReplaySubscriptionRequest => 13,
+            MessageType::FederationRequest => 14,
         }
     }
 }
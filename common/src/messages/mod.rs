@@ -1,8 +1,14 @@
 mod data_packet;
 pub use data_packet::DataPacket;
 
+mod error_code;
+pub use error_code::ErrorCode;
+
 mod message_type;
 pub use message_type::MessageType;
 
 mod message;
 pub use message::Message;
+
+mod replay_from;
+pub use replay_from::ReplayFrom;
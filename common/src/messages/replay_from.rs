@@ -0,0 +1,40 @@
+use std::io::{self, Cursor};
+
+use crate::io::Serializable;
+
+/// Where a replay subscription starts reading the journal.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ReplayFrom {
+    /// The first journal sequence number to replay.
+    Sequence(u64),
+    /// The earliest time to replay, in milliseconds since the Unix epoch.
+    Timestamp(u64),
+}
+
+impl Serializable for ReplayFrom {
+    fn serialize(&self, writer: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        match self {
+            ReplayFrom::Sequence(sequence) => {
+                1u8.serialize(writer)?;
+                sequence.serialize(writer)?;
+            }
+            ReplayFrom::Timestamp(timestamp) => {
+                2u8.serialize(writer)?;
+                timestamp.serialize(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut Cursor<Vec<u8>>) -> io::Result<ReplayFrom> {
+        match u8::deserialize(reader)? {
+            1 => Ok(ReplayFrom::Sequence(u64::deserialize(reader)?)),
+            2 => Ok(ReplayFrom::Timestamp(u64::deserialize(reader)?)),
+            _ => Err(io::Error::other("invalid")),
+        }
+    }
+
+    fn size(&self) -> usize {
+        1 + 8
+    }
+}
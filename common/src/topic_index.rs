@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use wildmatch::WildMatch;
+
+/// A map from topic patterns to values, which can find the patterns matching
+/// a topic without testing every one.
+///
+/// Literal patterns are kept in a hash map. Wildcard patterns are kept in a
+/// trie of their leading literal dot separated segments, so `NYSE.*` is only
+/// tested against topics starting with `NYSE.`. Every candidate is checked
+/// with `WildMatch`, so the results are the same as a linear scan.
+pub struct TopicIndex<V> {
+    exact: HashMap<String, V>,
+    root: Node<V>,
+    wildcard_count: usize,
+}
+
+struct Node<V> {
+    children: HashMap<String, Node<V>>,
+    patterns: HashMap<String, (WildMatch, V)>,
+}
+
+impl<V> Node<V> {
+    fn new() -> Self {
+        Node {
+            children: HashMap::new(),
+            patterns: HashMap::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && self.patterns.is_empty()
+    }
+
+    fn find(&self, path: &[&str]) -> Option<&Node<V>> {
+        match path.split_first() {
+            Some((segment, rest)) => self.children.get(*segment)?.find(rest),
+            None => Some(self),
+        }
+    }
+
+    fn find_mut(&mut self, path: &[&str]) -> Option<&mut Node<V>> {
+        match path.split_first() {
+            Some((segment, rest)) => self.children.get_mut(*segment)?.find_mut(rest),
+            None => Some(self),
+        }
+    }
+
+    fn find_or_insert(&mut self, path: &[&str]) -> &mut Node<V> {
+        match path.split_first() {
+            Some((segment, rest)) => self
+                .children
+                .entry(segment.to_string())
+                .or_insert_with(Node::new)
+                .find_or_insert(rest),
+            None => self,
+        }
+    }
+
+    fn remove(&mut self, path: &[&str], pattern: &str) -> Option<V> {
+        match path.split_first() {
+            Some((segment, rest)) => {
+                let child = self.children.get_mut(*segment)?;
+                let value = child.remove(rest, pattern);
+                if child.is_empty() {
+                    self.children.remove(*segment);
+                }
+                value
+            }
+            None => self.patterns.remove(pattern).map(|(_, value)| value),
+        }
+    }
+
+    fn collect<'a>(&'a self, entries: &mut Vec<(&'a str, &'a V)>) {
+        for (pattern, (_, value)) in &self.patterns {
+            entries.push((pattern.as_str(), value));
+        }
+        for child in self.children.values() {
+            child.collect(entries);
+        }
+    }
+}
+
+fn is_literal(pattern: &str) -> bool {
+    !pattern.contains(['*', '?'])
+}
+
+/// The leading literal segments of a wildcard pattern, each of which must be
+/// followed by a dot.
+fn literal_prefix(pattern: &str) -> Vec<&str> {
+    let mut segments: Vec<&str> = pattern.split('.').collect();
+    segments.pop();
+    segments
+        .into_iter()
+        .take_while(|segment| is_literal(segment))
+        .collect()
+}
+
+impl<V> TopicIndex<V> {
+    pub fn new() -> Self {
+        TopicIndex {
+            exact: HashMap::new(),
+            root: Node::new(),
+            wildcard_count: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcard_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.root.is_empty()
+    }
+
+    pub fn contains_key(&self, pattern: &str) -> bool {
+        self.get(pattern).is_some()
+    }
+
+    pub fn get(&self, pattern: &str) -> Option<&V> {
+        if is_literal(pattern) {
+            return self.exact.get(pattern);
+        }
+        let node = self.root.find(&literal_prefix(pattern))?;
+        node.patterns.get(pattern).map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, pattern: &str) -> Option<&mut V> {
+        if is_literal(pattern) {
+            return self.exact.get_mut(pattern);
+        }
+        let node = self.root.find_mut(&literal_prefix(pattern))?;
+        node.patterns.get_mut(pattern).map(|(_, value)| value)
+    }
+
+    /// Get the value for the pattern, inserting one if it is not present.
+    pub fn get_or_insert_with<F>(&mut self, pattern: &str, default: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        if is_literal(pattern) {
+            return self
+                .exact
+                .entry(pattern.to_string())
+                .or_insert_with(default);
+        }
+        let node = self.root.find_or_insert(&literal_prefix(pattern));
+        if !node.patterns.contains_key(pattern) {
+            self.wildcard_count += 1;
+        }
+        let (_, value) = node
+            .patterns
+            .entry(pattern.to_string())
+            .or_insert_with(|| (WildMatch::new(pattern), default()));
+        value
+    }
+
+    pub fn insert(&mut self, pattern: &str, value: V) -> Option<V> {
+        if is_literal(pattern) {
+            return self.exact.insert(pattern.to_string(), value);
+        }
+        let node = self.root.find_or_insert(&literal_prefix(pattern));
+        let previous = node
+            .patterns
+            .insert(pattern.to_string(), (WildMatch::new(pattern), value));
+        if previous.is_none() {
+            self.wildcard_count += 1;
+        }
+        previous.map(|(_, value)| value)
+    }
+
+    pub fn remove(&mut self, pattern: &str) -> Option<V> {
+        if is_literal(pattern) {
+            return self.exact.remove(pattern);
+        }
+        let value = self.root.remove(&literal_prefix(pattern), pattern);
+        if value.is_some() {
+            self.wildcard_count -= 1;
+        }
+        value
+    }
+
+    /// All the patterns and their values, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        let mut entries = Vec::new();
+        self.root.collect(&mut entries);
+        self.exact
+            .iter()
+            .map(|(pattern, value)| (pattern.as_str(), value))
+            .chain(entries)
+    }
+
+    /// Find the patterns which match the topic.
+    pub fn matches(&self, topic: &str) -> Vec<(&str, &V)> {
+        let mut found = Vec::new();
+
+        if let Some((pattern, value)) = self.exact.get_key_value(topic) {
+            found.push((pattern.as_str(), value));
+        }
+
+        // A pattern stored below a segment can only match a topic with
+        // another segment after it, so the last segment is not followed.
+        let mut segments: Vec<&str> = topic.split('.').collect();
+        segments.pop();
+
+        let mut node = &self.root;
+        let mut path = segments.iter();
+        loop {
+            for (pattern, (wildmatch, value)) in &node.patterns {
+                if wildmatch.matches(topic) {
+                    found.push((pattern.as_str(), value));
+                }
+            }
+
+            let Some(child) = path.next().and_then(|segment| node.children.get(*segment)) else {
+                break;
+            };
+            node = child;
+        }
+
+        found
+    }
+}
+
+impl<V> Default for TopicIndex<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PATTERNS: [&str; 14] = [
+        "*",
+        "NYSE.*",
+        "NYSE.IBM",
+        "NYSE.I*",
+        "NYSE.?BM",
+        "N?SE.IBM",
+        "*.IBM",
+        "NYSE.*.BID",
+        "NYSE.IBM.*",
+        "NYSE",
+        "NYSE.",
+        ".*",
+        "LSE.VOD",
+        "LSE.V*D",
+    ];
+
+    const TOPICS: [&str; 11] = [
+        "NYSE.IBM",
+        "NYSE.IBM.BID",
+        "NYSE.MSFT",
+        "NASE.IBM",
+        "NYSE",
+        "NYSE.",
+        "LSE.VOD",
+        "LSE.VD",
+        ".IBM",
+        "",
+        "*.IBM",
+    ];
+
+    #[test]
+    fn should_match_like_wildmatch() {
+        let mut index = TopicIndex::new();
+        for pattern in PATTERNS {
+            index.insert(pattern, pattern.len());
+        }
+        assert_eq!(index.len(), PATTERNS.len());
+
+        for topic in TOPICS {
+            let mut actual: Vec<&str> = index
+                .matches(topic)
+                .into_iter()
+                .map(|(pattern, _)| pattern)
+                .collect();
+            actual.sort();
+
+            let mut expected: Vec<&str> = PATTERNS
+                .into_iter()
+                .filter(|pattern| WildMatch::new(pattern).matches(topic))
+                .collect();
+            expected.sort();
+
+            assert_eq!(actual, expected, "topic \"{topic}\"");
+        }
+    }
+
+    #[test]
+    fn should_remove_patterns() {
+        let mut index = TopicIndex::new();
+        for pattern in PATTERNS {
+            index.insert(pattern, ());
+        }
+        for pattern in PATTERNS {
+            assert!(index.remove(pattern).is_some(), "pattern \"{pattern}\"");
+            assert!(!index.contains_key(pattern));
+        }
+        assert!(index.is_empty());
+        assert_eq!(index.len(), 0);
+        assert!(index.root.children.is_empty());
+    }
+}
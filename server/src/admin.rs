@@ -0,0 +1,419 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{RwLock, oneshot};
+
+use crate::authentication::{AuthenticationManager, PeerInfo};
+use crate::clients::ClientManager;
+use crate::events::ClientEvent;
+use crate::notifications::NotificationManager;
+use crate::options::AdminOption;
+use crate::publishing::PublisherManager;
+use crate::subscriptions::SubscriptionManager;
+
+/// Asks for the authorizations and authentication to be reloaded, as SIGHUP
+/// does, and receives the outcome.
+pub type ResetRequest = oneshot::Sender<io::Result<()>>;
+
+#[derive(Debug, Serialize)]
+pub struct ClientView {
+    pub id: String,
+    pub host: String,
+    pub user: String,
+    pub groups: Vec<String>,
+    pub attributes: HashMap<String, String>,
+    pub transport: String,
+    /// Milliseconds since the Unix epoch.
+    pub connected_at: u64,
+    /// The peer broker, if the client is a federation link.
+    pub peer: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionView {
+    pub pattern: String,
+    /// The subscribers and their request counts.
+    pub subscribers: HashMap<String, u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationView {
+    pub pattern: String,
+    /// The listeners and their request counts.
+    pub listeners: HashMap<String, u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublisherView {
+    pub topic: String,
+    pub publishers: Vec<String>,
+}
+
+/// A request from the admin endpoint to the hub.
+pub enum AdminRequest {
+    Clients(oneshot::Sender<Vec<ClientView>>),
+    Subscriptions(oneshot::Sender<Vec<SubscriptionView>>),
+    Notifications(oneshot::Sender<Vec<NotificationView>>),
+    Publishers(oneshot::Sender<Vec<PublisherView>>),
+    Disconnect(String, oneshot::Sender<io::Result<()>>),
+}
+
+impl AdminRequest {
+    /// Answer the request from the state of the hub. The admin endpoint may
+    /// have given up waiting, so a reply that cannot be sent is dropped.
+    pub fn reply(
+        self,
+        client_manager: &ClientManager,
+        subscription_manager: &SubscriptionManager,
+        notification_manager: &NotificationManager,
+        publisher_manager: &PublisherManager,
+    ) {
+        match self {
+            AdminRequest::Clients(reply) => {
+                let mut clients: Vec<ClientView> = client_manager
+                    .iter()
+                    .map(|client| ClientView {
+                        id: client.id.clone(),
+                        host: client.host.clone(),
+                        user: client.user.clone(),
+                        groups: client.groups.clone(),
+                        attributes: client.attributes.clone(),
+                        transport: client.transport.to_string(),
+                        connected_at: client
+                            .connected_at
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |duration| duration.as_millis() as u64),
+                        peer: client.peer.clone(),
+                    })
+                    .collect();
+                clients.sort_by_key(|client| client.connected_at);
+                let _ = reply.send(clients);
+            }
+            AdminRequest::Subscriptions(reply) => {
+                let mut subscriptions: Vec<SubscriptionView> = subscription_manager
+                    .iter()
+                    .map(|(pattern, subscribers)| SubscriptionView {
+                        pattern: pattern.to_string(),
+                        subscribers: subscribers.clone(),
+                    })
+                    .collect();
+                subscriptions.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+                let _ = reply.send(subscriptions);
+            }
+            AdminRequest::Notifications(reply) => {
+                let mut notifications: Vec<NotificationView> = notification_manager
+                    .iter()
+                    .map(|(pattern, listeners)| NotificationView {
+                        pattern: pattern.to_string(),
+                        listeners: listeners.clone(),
+                    })
+                    .collect();
+                notifications.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+                let _ = reply.send(notifications);
+            }
+            AdminRequest::Publishers(reply) => {
+                let mut publishers: Vec<PublisherView> = publisher_manager
+                    .iter()
+                    .map(|(topic, publishers)| {
+                        let mut publishers: Vec<String> = publishers.iter().cloned().collect();
+                        publishers.sort();
+                        PublisherView {
+                            topic: topic.to_string(),
+                            publishers,
+                        }
+                    })
+                    .collect();
+                publishers.sort_by(|a, b| a.topic.cmp(&b.topic));
+                let _ = reply.send(publishers);
+            }
+            AdminRequest::Disconnect(client_id, reply) => {
+                let _ = reply.send(client_manager.disconnect(&client_id));
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AdminState {
+    hub: Sender<ClientEvent>,
+    reset: Sender<ResetRequest>,
+    authentication_manager: Arc<RwLock<AuthenticationManager>>,
+    users: Arc<HashSet<String>>,
+}
+
+/// A failed admin request, answered with a status code and a description.
+struct AdminError(StatusCode, String);
+
+impl From<io::Error> for AdminError {
+    fn from(error: io::Error) -> Self {
+        let status = match error.kind() {
+            io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        AdminError(status, error.to_string())
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let AdminError(status, reason) = self;
+        let mut response = (status, reason).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"squawkbus\""),
+            );
+        }
+        response
+    }
+}
+
+/// Serve read-only JSON views of the hub, and the admin actions, over HTTP.
+///
+/// Both the views and the actions require HTTP basic credentials, or a bearer
+/// token, checked by the authentication manager, for one of the configured
+/// admin users.
+pub async fn start_admin_listener(
+    option: AdminOption,
+    hub: Sender<ClientEvent>,
+    reset: Sender<ResetRequest>,
+    authentication_manager: Arc<RwLock<AuthenticationManager>>,
+) -> io::Result<()> {
+    log::info!(
+        "Listening on address {} for admin requests.",
+        option.endpoint
+    );
+
+    let state = AdminState {
+        hub,
+        reset,
+        authentication_manager,
+        users: Arc::new(option.users.into_iter().collect()),
+    };
+
+    let app = Router::new()
+        .route("/clients", get(get_clients))
+        .route("/clients/{id}/disconnect", post(disconnect_client))
+        .route("/subscriptions", get(get_subscriptions))
+        .route("/notifications", get(get_notifications))
+        .route("/publishers", get(get_publishers))
+        .route("/reload", post(reload))
+        .with_state(state);
+
+    let listener = TcpListener::bind(&option.endpoint).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+}
+
+async fn query<T>(
+    hub: &Sender<ClientEvent>,
+    request: impl FnOnce(oneshot::Sender<T>) -> AdminRequest,
+) -> Result<T, AdminError> {
+    let (tx, rx) = oneshot::channel();
+    hub.send(ClientEvent::OnAdmin(request(tx)))
+        .await
+        .map_err(|e| AdminError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+    rx.await
+        .map_err(|e| AdminError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))
+}
+
+async fn authorize(
+    state: &AdminState,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<String, AdminError> {
+    // The credentials are given as in the authentication request: base64
+    // encoded for basic authentication, or a bearer token.
+    let (methods, credentials) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value
+                .strip_prefix("Basic ")
+                .map(|credentials| (["basic", "ldap"].as_slice(), credentials))
+                .or_else(|| {
+                    value
+                        .strip_prefix("Bearer ")
+                        .map(|credentials| (["jwt"].as_slice(), credentials))
+                })
+        })
+        .ok_or(AdminError(
+            StatusCode::UNAUTHORIZED,
+            "credentials required".into(),
+        ))?;
+
+    // Failures are counted against the host, as for other clients.
+    let peer = PeerInfo {
+        host: addr.ip().to_string(),
+        ..Default::default()
+    };
+    let result = state
+        .authentication_manager
+        .read()
+        .await
+        .authenticate_credentials(methods, credentials.as_bytes(), &peer)
+        .await;
+    // The lock is released before waiting to answer a failure.
+    let identity = match result {
+        Ok(identity) => identity,
+        Err(failure) => {
+            let error = failure.wait().await;
+            return Err(AdminError(StatusCode::UNAUTHORIZED, error.to_string()));
+        }
+    };
+
+    // Admin users may also be given as a group, as "@admins".
+    let is_admin = state.users.contains(&identity.user)
+        || identity
+            .groups
+            .iter()
+            .any(|group| state.users.contains(&format!("@{group}")));
+    let user = identity.user;
+    if !is_admin {
+        log::warn!("Rejected admin request from \"{user}\".");
+        return Err(AdminError(
+            StatusCode::FORBIDDEN,
+            format!("\"{user}\" is not an admin user"),
+        ));
+    }
+
+    Ok(user)
+}
+
+async fn get_clients(
+    State(state): State<AdminState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ClientView>>, AdminError> {
+    authorize(&state, addr, &headers).await?;
+    Ok(Json(query(&state.hub, AdminRequest::Clients).await?))
+}
+
+async fn get_subscriptions(
+    State(state): State<AdminState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SubscriptionView>>, AdminError> {
+    authorize(&state, addr, &headers).await?;
+    Ok(Json(query(&state.hub, AdminRequest::Subscriptions).await?))
+}
+
+async fn get_notifications(
+    State(state): State<AdminState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<NotificationView>>, AdminError> {
+    authorize(&state, addr, &headers).await?;
+    Ok(Json(query(&state.hub, AdminRequest::Notifications).await?))
+}
+
+async fn get_publishers(
+    State(state): State<AdminState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<PublisherView>>, AdminError> {
+    authorize(&state, addr, &headers).await?;
+    Ok(Json(query(&state.hub, AdminRequest::Publishers).await?))
+}
+
+async fn disconnect_client(
+    State(state): State<AdminState>,
+    Path(client_id): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AdminError> {
+    let user = authorize(&state, addr, &headers).await?;
+    log::info!("Admin \"{user}\" requested client {client_id} be disconnected.");
+    query(&state.hub, |reply| {
+        AdminRequest::Disconnect(client_id, reply)
+    })
+    .await??;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn reload(
+    State(state): State<AdminState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AdminError> {
+    let user = authorize(&state, addr, &headers).await?;
+    log::info!("Admin \"{user}\" requested a reload.");
+    let (tx, rx) = oneshot::channel();
+    state
+        .reset
+        .send(tx)
+        .await
+        .map_err(|e| AdminError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+    rx.await
+        .map_err(|e| AdminError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))??;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clients::Transport;
+    use crate::outbound::{self, OverflowPolicy};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_view_and_disconnect_clients() {
+        let mut client_manager = ClientManager::new();
+        let (tx, mut rx) = outbound::channel(10, OverflowPolicy::Disconnect);
+        client_manager.handle_connect("c1", "host1".into(), "tom".into(), Transport::TlsSocket, tx);
+
+        let subscription_manager = SubscriptionManager::new();
+        let notification_manager = NotificationManager::new();
+        let publisher_manager = PublisherManager::new();
+
+        let (reply, clients) = oneshot::channel();
+        AdminRequest::Clients(reply).reply(
+            &client_manager,
+            &subscription_manager,
+            &notification_manager,
+            &publisher_manager,
+        );
+        let clients = clients.await.unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].id, "c1");
+        assert_eq!(clients[0].user, "tom");
+        assert_eq!(clients[0].transport, "socket+tls");
+
+        let (reply, result) = oneshot::channel();
+        AdminRequest::Disconnect("c2".into(), reply).reply(
+            &client_manager,
+            &subscription_manager,
+            &notification_manager,
+            &publisher_manager,
+        );
+        assert_eq!(
+            result.await.unwrap().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let (reply, result) = oneshot::channel();
+        AdminRequest::Disconnect("c1".into(), reply).reply(
+            &client_manager,
+            &subscription_manager,
+            &notification_manager,
+            &publisher_manager,
+        );
+        result.await.unwrap().unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::{Error, ErrorKind, Result};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo};
+
+/// The part of a client certificate that names the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CertificateUser {
+    CommonName,
+    /// The first subject alternative name of the given type.
+    SanEmail,
+    SanDns,
+    SanUri,
+}
+
+impl FromStr for CertificateUser {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cn" => Ok(CertificateUser::CommonName),
+            "san-email" => Ok(CertificateUser::SanEmail),
+            "san-dns" => Ok(CertificateUser::SanDns),
+            "san-uri" => Ok(CertificateUser::SanUri),
+            _ => Err(Error::new(
+                ErrorKind::Other,
+                format!("invalid certificate user \"{s}\""),
+            )),
+        }
+    }
+}
+
+/// Authenticates clients by the certificate they presented when the TLS
+/// connection was made. The certificate has already been verified against
+/// the client CA bundle, so only the user name is taken from it.
+#[derive(Clone)]
+pub struct CertificateAuthenticator {
+    user: CertificateUser,
+}
+
+impl CertificateAuthenticator {
+    pub fn new(user: CertificateUser) -> CertificateAuthenticator {
+        CertificateAuthenticator { user }
+    }
+
+    fn user_name(&self, certificate: &X509Certificate) -> Option<String> {
+        if self.user == CertificateUser::CommonName {
+            return certificate
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|name| name.as_str().ok())
+                .map(String::from);
+        }
+
+        let san = certificate.subject_alternative_name().ok()??;
+        san.value
+            .general_names
+            .iter()
+            .find_map(|name| match (self.user, name) {
+                (CertificateUser::SanEmail, GeneralName::RFC822Name(name))
+                | (CertificateUser::SanDns, GeneralName::DNSName(name))
+                | (CertificateUser::SanUri, GeneralName::URI(name)) => Some(name.to_string()),
+                _ => None,
+            })
+    }
+}
+
+#[async_trait]
+impl Authenticator for CertificateAuthenticator {
+    fn name(&self) -> &str {
+        "tls"
+    }
+
+    async fn authenticate(&self, _credentials: &[u8], peer: &PeerInfo) -> Result<Identity> {
+        let Some(certificate) = peer.certificates.first() else {
+            log::info!("Failed to authenticate client without a certificate.");
+            return Err(Error::new(ErrorKind::Other, "no client certificate"));
+        };
+
+        let (_, certificate) = X509Certificate::from_der(certificate)
+            .map_err(|e| Error::new(ErrorKind::Other, format!("invalid certificate: {}", e)))?;
+
+        match self.user_name(&certificate) {
+            Some(user) => {
+                log::info!("Authenticated as \"{}\".", user);
+                let mut identity = Identity::new(user);
+                identity
+                    .attributes
+                    .insert("subject".into(), certificate.subject().to_string());
+                Ok(identity)
+            }
+            None => {
+                log::info!(
+                    "Failed to authenticate with certificate for \"{}\".",
+                    certificate.subject()
+                );
+                Err(Error::new(
+                    ErrorKind::Other,
+                    "no user name in client certificate",
+                ))
+            }
+        }
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rcgen::{CertificateParams, DnType, KeyPair, SanType};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_take_the_user_from_the_certificate() {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "tom");
+        params.subject_alt_names = vec![
+            SanType::DnsName("tom.example.com".try_into().unwrap()),
+            SanType::Rfc822Name("tom@example.com".try_into().unwrap()),
+        ];
+        let certificate = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+        let peer = PeerInfo {
+            host: "127.0.0.1".into(),
+            certificates: vec![certificate.der().clone()],
+            ..Default::default()
+        };
+
+        let peer = &peer;
+        let authenticate = |user| async move {
+            CertificateAuthenticator::new(user)
+                .authenticate(&[], peer)
+                .await
+                .map(|identity| identity.user)
+        };
+        assert_eq!(
+            authenticate(CertificateUser::CommonName).await.unwrap(),
+            "tom"
+        );
+        assert_eq!(
+            authenticate(CertificateUser::SanEmail).await.unwrap(),
+            "tom@example.com"
+        );
+        assert_eq!(
+            authenticate(CertificateUser::SanDns).await.unwrap(),
+            "tom.example.com"
+        );
+        assert!(authenticate(CertificateUser::SanUri).await.is_err());
+
+        assert!(
+            CertificateAuthenticator::new(CertificateUser::CommonName)
+                .authenticate(&[], &PeerInfo::default())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -7,19 +7,23 @@ use async_trait::async_trait;
 use htpasswd_verify::Htpasswd;
 use http_auth_basic::Credentials;
 
-use crate::authentication::traits::Authenticator;
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo, basic_user};
 
 #[derive(Clone)]
 pub struct HtpasswdAuthenticator {
     path: PathBuf,
     data: HashMap<String, String>,
+    groups_path: Option<PathBuf>,
+    groups: HashMap<String, Vec<String>>,
 }
 
 impl HtpasswdAuthenticator {
-    pub fn new(path: &PathBuf) -> Result<Self> {
+    pub fn new(path: &PathBuf, groups_path: Option<&PathBuf>) -> Result<Self> {
         Ok(HtpasswdAuthenticator {
             path: path.clone(),
             data: load_htpasswd(path)?,
+            groups_path: groups_path.cloned(),
+            groups: load_groups(groups_path)?,
         })
     }
 
@@ -28,7 +32,7 @@ impl HtpasswdAuthenticator {
             return false;
         };
         let encoded = Htpasswd::from(value.as_str());
-        return encoded.check(username, password);
+        encoded.check(username, password)
     }
 }
 
@@ -47,13 +51,48 @@ fn load_htpasswd(path: &PathBuf) -> Result<HashMap<String, String>> {
     Ok(data)
 }
 
+/// Reads an Apache style groups file, where each line names a group
+/// followed by its members: `traders: tom dick`. The result maps each user
+/// to their groups.
+fn load_groups(path: Option<&PathBuf>) -> Result<HashMap<String, Vec<String>>> {
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+
+    let Some(path) = path else {
+        return Ok(groups);
+    };
+
+    let contents = read_to_string(path)?;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (group, users) = line.split_once(':').ok_or_else(|| {
+            Error::new(ErrorKind::Other, format!("invalid group entry \"{line}\""))
+        })?;
+        for user in users.split_whitespace() {
+            groups
+                .entry(user.to_string())
+                .or_default()
+                .push(group.trim().to_string());
+        }
+    }
+
+    Ok(groups)
+}
+
 #[async_trait]
 impl Authenticator for HtpasswdAuthenticator {
     fn name(&self) -> &str {
         "basic"
     }
 
-    async fn authenticate(&self, credentials: &[u8]) -> Result<String> {
+    fn claimed_user(&self, credentials: &[u8]) -> Option<String> {
+        basic_user(credentials)
+    }
+
+    async fn authenticate(&self, credentials: &[u8], _peer: &PeerInfo) -> Result<Identity> {
         let credentials = String::from_utf8(credentials.into())
             .map_err(|e| Error::new(ErrorKind::Other, format!("invalid credentials: {}", e)))?;
         let credentials = Credentials::decode(credentials)
@@ -63,7 +102,11 @@ impl Authenticator for HtpasswdAuthenticator {
         match is_valid {
             true => {
                 log::info!("Authenticated as \"{}\".", credentials.user_id.as_str());
-                Ok(credentials.user_id)
+                let mut identity = Identity::new(credentials.user_id);
+                if let Some(groups) = self.groups.get(&identity.user) {
+                    identity.groups = groups.clone();
+                }
+                Ok(identity)
             }
             false => {
                 log::info!(
@@ -79,7 +122,37 @@ impl Authenticator for HtpasswdAuthenticator {
     }
 
     async fn reset(&mut self) -> Result<()> {
-        self.data = load_htpasswd(&self.path)?;
+        // Keep both files as they were unless both can be read.
+        let data = load_htpasswd(&self.path)?;
+        self.groups = load_groups(self.groups_path.as_ref())?;
+        self.data = data;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn should_read_the_groups_of_each_user() {
+        let path = std::env::temp_dir().join(format!("{}.groups", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            "# Desks\n\
+            traders: tom dick\n\
+            \n\
+            risk: dick\n",
+        )
+        .unwrap();
+        let groups = load_groups(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(groups.get("tom").unwrap(), &vec!["traders"]);
+        assert_eq!(groups.get("dick").unwrap(), &vec!["traders", "risk"]);
+        assert!(!groups.contains_key("harry"));
+        assert!(load_groups(None).unwrap().is_empty());
+    }
+}
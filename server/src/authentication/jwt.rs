@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo};
+
+/// The configuration of the JWT authenticator, read from a YAML file.
+///
+/// ```yaml
+/// keys:
+/// - algorithm: HS256
+///   secret: "a shared secret"
+/// - algorithm: RS256
+///   kid: "2024-01"
+///   key_file: rsa-public.pem
+/// jwks_file: jwks.json
+/// issuer: https://auth.example.com
+/// audience: squawkbus
+/// user_claim: preferred_username
+/// groups_claim: groups
+/// leeway_seconds: 30
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JwtConfig {
+    #[serde(default)]
+    keys: Vec<KeyConfig>,
+    jwks_file: Option<PathBuf>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    #[serde(default = "default_user_claim")]
+    user_claim: String,
+    /// A claim holding the list of groups the user belongs to.
+    #[serde(default = "default_groups_claim")]
+    groups_claim: String,
+    #[serde(default)]
+    leeway_seconds: u64,
+}
+
+fn default_user_claim() -> String {
+    "sub".into()
+}
+
+fn default_groups_claim() -> String {
+    "groups".into()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyConfig {
+    algorithm: Algorithm,
+    kid: Option<String>,
+    /// The shared secret for HMAC algorithms.
+    secret: Option<String>,
+    /// A PEM file holding the public key for other algorithms.
+    key_file: Option<PathBuf>,
+}
+
+/// A key which can verify the signature of a token.
+struct VerificationKey {
+    algorithm: Algorithm,
+    kid: Option<String>,
+    key: DecodingKey,
+}
+
+impl VerificationKey {
+    fn matches(&self, algorithm: Algorithm, kid: &Option<String>) -> bool {
+        self.algorithm == algorithm
+            && match (&self.kid, kid) {
+                (Some(expected), Some(kid)) => expected == kid,
+                _ => true,
+            }
+    }
+}
+
+/// Authenticates clients presenting a signed JSON web token as the
+/// credentials. The user is taken from a claim of the token.
+pub struct JwtAuthenticator {
+    path: PathBuf,
+    config: JwtConfig,
+    keys: Vec<VerificationKey>,
+}
+
+impl JwtAuthenticator {
+    pub fn new(path: &PathBuf) -> Result<Self> {
+        let (config, keys) = load_config(path)?;
+        Ok(JwtAuthenticator {
+            path: path.clone(),
+            config,
+            keys,
+        })
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = self.config.leeway_seconds;
+        validation.set_required_spec_claims(&["exp"]);
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        validation
+    }
+
+    fn verify(&self, token: &str) -> Result<Identity> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| Error::new(ErrorKind::Other, format!("invalid token: {}", e)))?;
+
+        let mut error = Error::new(
+            ErrorKind::Other,
+            format!("no key for algorithm {:?}", header.alg),
+        );
+        for key in self.keys.iter() {
+            if !key.matches(header.alg, &header.kid) {
+                continue;
+            }
+
+            let claims = match jsonwebtoken::decode::<HashMap<String, Value>>(
+                token,
+                &key.key,
+                &self.validation(header.alg),
+            ) {
+                Ok(data) => data.claims,
+                Err(e) => {
+                    error = Error::new(ErrorKind::Other, format!("invalid token: {}", e));
+                    continue;
+                }
+            };
+
+            return match claims.get(&self.config.user_claim) {
+                Some(Value::String(user)) => Ok(self.identity(user, &claims)),
+                _ => Err(Error::new(
+                    ErrorKind::Other,
+                    format!("token has no \"{}\" claim", self.config.user_claim),
+                )),
+            };
+        }
+
+        Err(error)
+    }
+
+    /// The groups are taken from the groups claim, and the other string
+    /// claims become attributes.
+    fn identity(&self, user: &str, claims: &HashMap<String, Value>) -> Identity {
+        let mut identity = Identity::new(user);
+        for (name, value) in claims {
+            match value {
+                Value::Array(groups) if *name == self.config.groups_claim => {
+                    identity.groups = groups
+                        .iter()
+                        .filter_map(|group| group.as_str().map(String::from))
+                        .collect();
+                }
+                Value::String(value) if *name != self.config.user_claim => {
+                    identity.attributes.insert(name.clone(), value.clone());
+                }
+                _ => {}
+            }
+        }
+        identity
+    }
+}
+
+fn load_config(path: &PathBuf) -> Result<(JwtConfig, Vec<VerificationKey>)> {
+    let file = fs::File::open(path)?;
+    let config: JwtConfig =
+        serde_yaml_ng::from_reader(file).map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+    // Key files are relative to the configuration file.
+    let directory = path.parent().unwrap_or(Path::new("."));
+
+    let mut keys = Vec::new();
+    for key in config.keys.iter() {
+        keys.push(load_key(key, directory)?);
+    }
+    if let Some(jwks_file) = &config.jwks_file {
+        let contents = fs::read_to_string(directory.join(jwks_file))?;
+        let jwks: JwkSet =
+            serde_json::from_str(&contents).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        for jwk in jwks.keys.iter() {
+            keys.push(load_jwk(jwk)?);
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(Error::new(ErrorKind::Other, "no keys configured"));
+    }
+
+    Ok((config, keys))
+}
+
+fn load_key(config: &KeyConfig, directory: &Path) -> Result<VerificationKey> {
+    let key = match config.algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let secret = config
+                .secret
+                .as_ref()
+                .ok_or_else(|| Error::new(ErrorKind::Other, "missing secret"))?;
+            DecodingKey::from_secret(secret.as_bytes())
+        }
+        algorithm => {
+            let key_file = config
+                .key_file
+                .as_ref()
+                .ok_or_else(|| Error::new(ErrorKind::Other, "missing key_file"))?;
+            let pem = fs::read(directory.join(key_file))?;
+            match algorithm {
+                Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+                _ => DecodingKey::from_rsa_pem(&pem),
+            }
+            .map_err(|e| Error::new(ErrorKind::Other, format!("invalid key: {}", e)))?
+        }
+    };
+
+    Ok(VerificationKey {
+        algorithm: config.algorithm,
+        kid: config.kid.clone(),
+        key,
+    })
+}
+
+fn load_jwk(jwk: &Jwk) -> Result<VerificationKey> {
+    // Keys without an algorithm use the usual one for their type.
+    let algorithm = match (&jwk.common.key_algorithm, &jwk.algorithm) {
+        (Some(algorithm), _) => Algorithm::from_str(&algorithm.to_string())
+            .map_err(|e| Error::new(ErrorKind::Other, format!("invalid key: {}", e)))?,
+        (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+        (None, AlgorithmParameters::EllipticCurve(params)) => match params.curve {
+            EllipticCurve::P384 => Algorithm::ES384,
+            _ => Algorithm::ES256,
+        },
+        (None, AlgorithmParameters::OctetKeyPair(_)) => Algorithm::EdDSA,
+        (None, AlgorithmParameters::OctetKey(_)) => Algorithm::HS256,
+    };
+
+    let key = DecodingKey::from_jwk(jwk)
+        .map_err(|e| Error::new(ErrorKind::Other, format!("invalid key: {}", e)))?;
+
+    Ok(VerificationKey {
+        algorithm,
+        kid: jwk.common.key_id.clone(),
+        key,
+    })
+}
+
+#[async_trait]
+impl Authenticator for JwtAuthenticator {
+    fn name(&self) -> &str {
+        "jwt"
+    }
+
+    async fn authenticate(&self, credentials: &[u8], _peer: &PeerInfo) -> Result<Identity> {
+        let token = std::str::from_utf8(credentials)
+            .map_err(|e| Error::new(ErrorKind::Other, format!("invalid credentials: {}", e)))?;
+
+        match self.verify(token.trim()) {
+            Ok(identity) => {
+                log::info!("Authenticated as \"{}\".", identity.user);
+                Ok(identity)
+            }
+            Err(error) => {
+                log::info!("Failed to authenticate with token: {}", error);
+                Err(error)
+            }
+        }
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        (self.config, self.keys) = load_config(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    async fn authenticate(authenticator: &JwtAuthenticator, claims: Value) -> Result<Identity> {
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        authenticator
+            .authenticate(token.as_bytes(), &PeerInfo::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn should_validate_tokens() {
+        let path = std::env::temp_dir().join(format!("{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            "keys:\n\
+            - algorithm: HS256\n  secret: secret\n\
+            issuer: auth\n\
+            audience: squawkbus\n\
+            user_claim: name\n",
+        )
+        .unwrap();
+        let authenticator = JwtAuthenticator::new(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        let identity = authenticate(
+            &authenticator,
+            json!({"name": "tom", "iss": "auth", "aud": "squawkbus", "exp": exp,
+                "groups": ["traders"]}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(identity.user, "tom");
+        assert_eq!(identity.groups, vec!["traders"]);
+        assert_eq!(identity.attributes.get("iss").unwrap(), "auth");
+
+        // Expired.
+        let claims = json!({"name": "tom", "iss": "auth", "aud": "squawkbus", "exp": exp - 3600});
+        assert!(authenticate(&authenticator, claims).await.is_err());
+        // The wrong audience.
+        let claims = json!({"name": "tom", "iss": "auth", "aud": "other", "exp": exp});
+        assert!(authenticate(&authenticator, claims).await.is_err());
+        // No user claim.
+        let claims = json!({"sub": "tom", "iss": "auth", "aud": "squawkbus", "exp": exp});
+        assert!(authenticate(&authenticator, claims).await.is_err());
+
+        // Signed with another key.
+        let forged = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &json!({"name": "tom", "iss": "auth", "aud": "squawkbus", "exp": exp}),
+            &EncodingKey::from_secret(b"guess"),
+        )
+        .unwrap();
+        assert!(
+            authenticator
+                .authenticate(forged.as_bytes(), &PeerInfo::default())
+                .await
+                .is_err()
+        );
+
+        // A misspelled setting is an error, rather than disabling a check.
+        fs::write(
+            &path,
+            "keys:\n\
+            - algorithm: HS256\n  secret: secret\n\
+            audeince: squawkbus\n",
+        )
+        .unwrap();
+        assert!(JwtAuthenticator::new(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
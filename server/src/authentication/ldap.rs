@@ -1,20 +1,358 @@
+use std::fs;
 use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use http_auth_basic::Credentials;
-use ldap3::{LdapConnAsync, LdapConnSettings};
+use ldap_rustls::{Certificate, ClientConfig, RootCertStore};
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, dn_escape, ldap_escape};
+use serde::Deserialize;
 
-use crate::authentication::traits::Authenticator;
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo, basic_user};
+use crate::tls::load_certs;
 
-#[derive(Clone)]
+/// The configuration of the LDAP authenticator, read from an optional YAML
+/// file.
+///
+/// ```yaml
+/// starttls: true
+/// cafile: ldap-ca.pem
+/// connect_timeout_seconds: 10
+/// bind_dn: "uid={user},ou=people,dc=example,dc=com"
+/// groups:
+///   base: ou=groups,dc=example,dc=com
+///   filter: "(member={dn})"
+///   attribute: cn
+/// pool_size: 4
+/// ```
+///
+/// Instead of a bind DN template, the DN can be found by searching with a
+/// service account.
+///
+/// ```yaml
+/// search:
+///   base: ou=people,dc=example,dc=com
+///   filter: "(uid={user})"
+///   bind_dn: cn=squawkbus,ou=services,dc=example,dc=com
+///   password: secret
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LdapConfig {
+    /// Whether ldap:// connections are upgraded with StartTLS. Connections
+    /// to ldaps:// urls always use TLS.
+    #[serde(default = "default_starttls")]
+    starttls: bool,
+    /// The CA bundle which verifies the server, in place of the system
+    /// certificates.
+    cafile: Option<PathBuf>,
+    #[serde(default = "default_connect_timeout_seconds")]
+    connect_timeout_seconds: u64,
+    /// The DN to bind as, where "{user}" is replaced by the user name.
+    bind_dn: Option<String>,
+    search: Option<UserSearch>,
+    groups: Option<GroupSearch>,
+    /// The most idle connections to keep for later requests.
+    #[serde(default = "default_pool_size")]
+    pool_size: usize,
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        LdapConfig {
+            starttls: default_starttls(),
+            cafile: None,
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            bind_dn: None,
+            search: None,
+            groups: None,
+            pool_size: default_pool_size(),
+        }
+    }
+}
+
+/// Finds the DN of the user with a service account.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserSearch {
+    base: String,
+    /// The filter for the user entry, where "{user}" is replaced by the user
+    /// name.
+    #[serde(default = "default_user_filter")]
+    filter: String,
+    bind_dn: String,
+    password: String,
+}
+
+fn default_user_filter() -> String {
+    "(uid={user})".into()
+}
+
+/// Finds the groups of the user, as well as those given by `memberOf`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupSearch {
+    base: String,
+    /// The filter for the group entries, where "{dn}" is replaced by the DN
+    /// of the user, and "{user}" by the user name.
+    #[serde(default = "default_group_filter")]
+    filter: String,
+    /// The attribute holding the name of the group.
+    #[serde(default = "default_group_attribute")]
+    attribute: String,
+}
+
+fn default_group_filter() -> String {
+    "(member={dn})".into()
+}
+
+fn default_group_attribute() -> String {
+    "cn".into()
+}
+
+fn load_config(path: &Option<PathBuf>) -> Result<(LdapConfig, LdapConnSettings)> {
+    let mut config = match path {
+        Some(path) => {
+            let file = fs::File::open(path)?;
+            serde_yaml_ng::from_reader(file).map_err(|e| Error::new(ErrorKind::Other, e))?
+        }
+        None => LdapConfig::default(),
+    };
+
+    if config.bind_dn.is_some() && config.search.is_some() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "only one of bind_dn and search may be given",
+        ));
+    }
+    if config.connect_timeout_seconds == 0 {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "connect_timeout_seconds must be greater than zero",
+        ));
+    }
+
+    let mut settings = LdapConnSettings::new()
+        .set_starttls(config.starttls)
+        .set_conn_timeout(Duration::from_secs(config.connect_timeout_seconds));
+
+    if let Some(cafile) = &config.cafile {
+        // The CA bundle is relative to the configuration file.
+        let directory = path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .unwrap_or(Path::new("."));
+        let cafile = directory.join(cafile);
+        let mut root_cert_store = RootCertStore::empty();
+        for cert in load_certs(&cafile)? {
+            root_cert_store
+                .add(&Certificate(cert.to_vec()))
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        }
+        let tls_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_cert_store)
+            .with_no_client_auth();
+        settings = settings.set_config(Arc::new(tls_config));
+        config.cafile = Some(cafile);
+    }
+
+    Ok((config, settings))
+}
+
+/// Authenticates basic credentials by binding to an LDAP server as the user.
+/// Connections are kept for reuse, as each bind replaces the identity of the
+/// connection.
 pub struct LdapAuthenticator {
     url: String,
+    path: Option<PathBuf>,
+    config: LdapConfig,
+    settings: LdapConnSettings,
+    pool: Mutex<Vec<Ldap>>,
 }
 
 impl LdapAuthenticator {
-    pub fn new(url: String) -> LdapAuthenticator {
-        LdapAuthenticator { url }
+    pub fn new(url: String, path: Option<&PathBuf>) -> Result<LdapAuthenticator> {
+        let path = path.cloned();
+        let (config, settings) = load_config(&path)?;
+        Ok(LdapAuthenticator {
+            url,
+            path,
+            config,
+            settings,
+            pool: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn connect(&self) -> Result<Ldap> {
+        let (conn, ldap) = LdapConnAsync::with_settings(self.settings.clone(), &self.url).await?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    /// Take an idle connection, or make a new one. The flag is set if the
+    /// connection was idle, when the server may have since closed it.
+    async fn checkout(&self) -> Result<(Ldap, bool)> {
+        let idle = self.pool.lock().unwrap().pop();
+        match idle {
+            Some(ldap) => Ok((ldap, true)),
+            None => Ok((self.connect().await?, false)),
+        }
     }
+
+    async fn release(&self, mut ldap: Ldap) {
+        {
+            let mut pool = self.pool.lock().unwrap();
+            if !ldap.is_closed() && pool.len() < self.config.pool_size {
+                pool.push(ldap);
+                return;
+            }
+        }
+        if let Err(error) = ldap.unbind().await {
+            log::trace!("Failed to unbind from LDAP server: {error}");
+        }
+    }
+
+    /// Bind as the user. The identity is returned if the directory accepts
+    /// the password, and errors are left for problems with the connection or
+    /// the configuration.
+    async fn login(&self, ldap: &mut Ldap, user: &str, password: &str) -> Result<Option<Identity>> {
+        let dn = match (&self.config.bind_dn, &self.config.search) {
+            (Some(template), _) => template.replace("{user}", &dn_escape(user)),
+            (None, Some(search)) => match find_user(ldap, search, user).await? {
+                Some(dn) => dn,
+                None => return Ok(None),
+            },
+            (None, None) => user.to_string(),
+        };
+
+        if ldap.simple_bind(&dn, password).await?.rc != 0 {
+            return Ok(None);
+        }
+
+        // The groups are only visible once bound. A directory without
+        // memberOf leaves the user without groups.
+        let mut groups = member_of(ldap, &dn).await.unwrap_or_else(|error| {
+            log::debug!("Failed to find groups for \"{dn}\": {error}");
+            Vec::new()
+        });
+        if let Some(search) = &self.config.groups {
+            match search_groups(ldap, search, user, &dn).await {
+                Ok(found) => {
+                    for group in found {
+                        if !groups.contains(&group) {
+                            groups.push(group);
+                        }
+                    }
+                }
+                Err(error) => log::warn!("Failed to search for groups of \"{dn}\": {error}"),
+            }
+        }
+
+        let mut identity = Identity::new(user);
+        identity.groups = groups;
+        identity.attributes.insert("dn".into(), dn);
+        Ok(Some(identity))
+    }
+
+    async fn pooled_login(&self, user: &str, password: &str) -> Result<Option<Identity>> {
+        let (mut ldap, is_idle) = self.checkout().await?;
+        let mut result = self.login(&mut ldap, user, password).await;
+        if result.is_err() && is_idle {
+            // Try again in case the server closed the idle connection.
+            ldap = self.connect().await?;
+            result = self.login(&mut ldap, user, password).await;
+        }
+        if result.is_ok() {
+            self.release(ldap).await;
+        }
+        result
+    }
+}
+
+/// Search for the DN of the user as the service account. No DN is found if
+/// there is no entry for the user, or several.
+async fn find_user(ldap: &mut Ldap, search: &UserSearch, user: &str) -> Result<Option<String>> {
+    ldap.simple_bind(&search.bind_dn, &search.password)
+        .await?
+        .success()?;
+
+    let filter = search.filter.replace("{user}", &ldap_escape(user));
+    let (mut entries, _) = ldap
+        .search(&search.base, Scope::Subtree, &filter, vec!["1.1"])
+        .await?
+        .success()?;
+
+    match entries.len() {
+        1 => Ok(Some(SearchEntry::construct(entries.remove(0)).dn)),
+        _ => Ok(None),
+    }
+}
+
+/// Finds the groups of the bound user from the `memberOf` attribute of
+/// their entry. A group is named by the value of the first part of its
+/// distinguished name, so "cn=traders,ou=groups,dc=example,dc=com" is
+/// "traders".
+async fn member_of(ldap: &mut Ldap, user_dn: &str) -> Result<Vec<String>> {
+    let (entries, _) = ldap
+        .search(user_dn, Scope::Base, "(objectClass=*)", vec!["memberOf"])
+        .await?
+        .success()?;
+
+    let groups = entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .flat_map(|entry| entry.attrs.get("memberOf").cloned().unwrap_or_default())
+        .filter_map(|dn| {
+            let (_, value) = dn.split(',').next()?.split_once('=')?;
+            Some(value.trim().to_string())
+        })
+        .collect();
+
+    Ok(groups)
+}
+
+async fn search_groups(
+    ldap: &mut Ldap,
+    search: &GroupSearch,
+    user: &str,
+    user_dn: &str,
+) -> Result<Vec<String>> {
+    let filter = search
+        .filter
+        .replace("{dn}", &ldap_escape(user_dn))
+        .replace("{user}", &ldap_escape(user));
+    let (entries, _) = ldap
+        .search(
+            &search.base,
+            Scope::Subtree,
+            &filter,
+            vec![search.attribute.as_str()],
+        )
+        .await?
+        .success()?;
+
+    let groups = entries
+        .into_iter()
+        .map(SearchEntry::construct)
+        .filter_map(|entry| entry.attrs.get(&search.attribute)?.first().cloned())
+        .collect();
+
+    Ok(groups)
 }
 
 #[async_trait]
@@ -23,30 +361,31 @@ impl Authenticator for LdapAuthenticator {
         "ldap"
     }
 
-    async fn authenticate(&self, credentials: &[u8]) -> Result<String> {
+    fn claimed_user(&self, credentials: &[u8]) -> Option<String> {
+        basic_user(credentials)
+    }
+
+    async fn authenticate(&self, credentials: &[u8], _peer: &PeerInfo) -> Result<Identity> {
         let credentials = String::from_utf8(credentials.into())
             .map_err(|e| Error::new(ErrorKind::Other, format!("invalid credentials: {}", e)))?;
         let credentials = Credentials::decode(credentials)
             .map_err(|e| Error::new(ErrorKind::Other, format!("invalid credentials: {}", e)))?;
 
-        let (conn, mut ldap) = LdapConnAsync::with_settings(
-            LdapConnSettings::new()
-                .set_starttls(true)
-                .set_no_tls_verify(true),
-            &self.url,
-        )
-        .await?;
-        ldap3::drive!(conn);
-
-        // Attempts a simple bind using the passed in values of username and Password
-        let result = ldap
-            .simple_bind(&credentials.user_id, &credentials.password)
-            .await?
-            .success();
-        ldap.unbind().await?;
+        // A bind without a password is anonymous, and always succeeds.
+        let result = match credentials.password.is_empty() {
+            true => Ok(None),
+            false => {
+                self.pooled_login(&credentials.user_id, &credentials.password)
+                    .await
+            }
+        };
 
-        match result.is_err() {
-            true => {
+        match result {
+            Ok(Some(identity)) => {
+                log::info!("Authenticated as \"{}\".", identity.user);
+                Ok(identity)
+            }
+            Ok(None) => {
                 log::info!(
                     "Failed to authenticate as \"{}\".",
                     credentials.user_id.as_str()
@@ -56,14 +395,62 @@ impl Authenticator for LdapAuthenticator {
                     format!("invalid user \"{}\"", credentials.user_id),
                 ))
             }
-            false => {
-                log::info!("Authenticated as \"{}\".", credentials.user_id.as_str());
-                Ok(credentials.user_id)
+            Err(error) => {
+                log::warn!(
+                    "Failed to authenticate as \"{}\" with LDAP: {}",
+                    credentials.user_id.as_str(),
+                    error
+                );
+                Err(error)
             }
         }
     }
 
     async fn reset(&mut self) -> Result<()> {
+        (self.config, self.settings) = load_config(&self.path)?;
+        // The connections were made with the old settings.
+        self.pool.get_mut().unwrap().clear();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_read_the_configuration() {
+        let (config, _) = load_config(&None).unwrap();
+        assert!(config.starttls);
+        assert_eq!(config.pool_size, 4);
+
+        let path = std::env::temp_dir().join(format!("{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            "starttls: false\n\
+            search:\n  base: ou=people,dc=example,dc=com\n  \
+            bind_dn: cn=squawkbus,dc=example,dc=com\n  password: secret\n\
+            groups:\n  base: ou=groups,dc=example,dc=com\n",
+        )
+        .unwrap();
+        let (config, _) = load_config(&Some(path.clone())).unwrap();
+        assert!(!config.starttls);
+        assert_eq!(config.search.unwrap().filter, "(uid={user})");
+        let groups = config.groups.unwrap();
+        assert_eq!(groups.filter, "(member={dn})");
+        assert_eq!(groups.attribute, "cn");
+
+        fs::write(
+            &path,
+            "bind_dn: \"uid={user},dc=example,dc=com\"\n\
+            search:\n  base: dc=example,dc=com\n  \
+            bind_dn: cn=squawkbus,dc=example,dc=com\n  password: secret\n",
+        )
+        .unwrap();
+        assert!(load_config(&Some(path.clone())).is_err());
+
+        fs::write(&path, "connect_timeout_seconds: 0\n").unwrap();
+        assert!(load_config(&Some(path.clone())).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::metrics::METRICS;
+use crate::options::LockoutOption;
+
+/// The most users or hosts to remember failures for before forgetting
+/// those which have expired.
+const MAX_TRACKED: usize = 10_000;
+
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until.is_some_and(|until| now < until)
+    }
+}
+
+/// The failures of each user or host.
+struct FailureCounts {
+    kind: &'static str,
+    entries: HashMap<String, Failures>,
+}
+
+impl FailureCounts {
+    fn new(kind: &'static str) -> Self {
+        FailureCounts {
+            kind,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn is_locked(&self, key: &str, now: Instant) -> bool {
+        self.entries
+            .get(key)
+            .is_some_and(|failures| failures.is_locked(now))
+    }
+
+    /// Count a failure, returning the number of recent failures.
+    fn fail(&mut self, key: &str, max_failures: u32, lockout: Duration, now: Instant) -> u32 {
+        if self.entries.len() >= MAX_TRACKED {
+            self.entries.retain(|_, failures| {
+                failures.is_locked(now) || now - failures.last_failure < lockout
+            });
+        }
+
+        let failures = self.entries.entry(key.to_string()).or_insert(Failures {
+            count: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        // Failures are forgotten after the lockout period.
+        if now - failures.last_failure >= lockout {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last_failure = now;
+
+        if max_failures > 0 && failures.count >= max_failures {
+            log::warn!(
+                "Locking out {} \"{}\" for {} seconds after {} failed authentication attempts.",
+                self.kind,
+                key,
+                lockout.as_secs(),
+                failures.count
+            );
+            METRICS.authentication_locked_out(self.kind);
+            failures.locked_until = Some(now + lockout);
+            failures.count = 0;
+        }
+
+        failures.count
+    }
+}
+
+/// Tracks failed authentication by user and by host, to slow down and then
+/// lock out password guessing. The state is shared by the authentication
+/// managers of all the listeners.
+#[derive(Clone)]
+pub struct LockoutManager {
+    option: LockoutOption,
+    failures: Arc<Mutex<(FailureCounts, FailureCounts)>>,
+}
+
+impl LockoutManager {
+    pub fn new(option: LockoutOption) -> Self {
+        LockoutManager {
+            option,
+            failures: Arc::new(Mutex::new((
+                FailureCounts::new("user"),
+                FailureCounts::new("host"),
+            ))),
+        }
+    }
+
+    fn lockout(&self) -> Duration {
+        Duration::from_secs(self.option.lockout_seconds)
+    }
+
+    /// Refuse to authenticate a user or host which is locked out.
+    pub fn check(&self, host: &str, user: Option<&str>) -> Result<()> {
+        let now = Instant::now();
+        let (users, hosts) = &*self.failures.lock().unwrap();
+        let is_locked =
+            hosts.is_locked(host, now) || user.is_some_and(|user| users.is_locked(user, now));
+        match is_locked {
+            true => {
+                log::info!(
+                    "Refused authentication as \"{}\" from \"{}\" while locked out.",
+                    user.unwrap_or_default(),
+                    host
+                );
+                Err(Error::new(
+                    ErrorKind::Other,
+                    "too many failed authentication attempts",
+                ))
+            }
+            false => Ok(()),
+        }
+    }
+
+    /// Count a failure, returning how long to wait before answering.
+    pub fn fail(&self, host: &str, user: Option<&str>) -> Duration {
+        let now = Instant::now();
+        let lockout = self.lockout();
+        let (users, hosts) = &mut *self.failures.lock().unwrap();
+        let mut count = hosts.fail(host, self.option.max_host_failures, lockout, now);
+        if let Some(user) = user {
+            count = count.max(users.fail(user, self.option.max_failures, lockout, now));
+        }
+
+        let delay = self
+            .option
+            .delay_milliseconds
+            .saturating_mul(1 << count.saturating_sub(1).min(16))
+            .min(self.option.max_delay_milliseconds);
+        Duration::from_millis(delay)
+    }
+
+    /// Forget the failures of a user who has authenticated.
+    pub fn succeed(&self, user: &str) {
+        let (users, _) = &mut *self.failures.lock().unwrap();
+        users.entries.remove(user);
+    }
+}
+
+impl Default for LockoutManager {
+    fn default() -> Self {
+        LockoutManager::new(LockoutOption::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn should_lock_out_after_repeated_failures() {
+        let lockout_manager = LockoutManager::new(LockoutOption {
+            max_failures: 3,
+            max_host_failures: 5,
+            lockout_seconds: 60,
+            delay_milliseconds: 100,
+            max_delay_milliseconds: 300,
+        });
+
+        let delays: Vec<u128> = (0..2)
+            .map(|_| lockout_manager.fail("h1", Some("tom")).as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200]);
+        assert!(lockout_manager.check("h1", Some("tom")).is_ok());
+
+        // The third failure locks out the user, wherever they connect from.
+        assert_eq!(lockout_manager.fail("h1", Some("tom")).as_millis(), 300);
+        assert!(lockout_manager.check("h2", Some("tom")).is_err());
+        assert!(lockout_manager.check("h1", Some("dick")).is_ok());
+
+        // Two more failures lock out the host, for any user.
+        lockout_manager.fail("h1", Some("dick"));
+        lockout_manager.fail("h1", None);
+        assert!(lockout_manager.check("h1", Some("dick")).is_err());
+        assert!(lockout_manager.check("h2", Some("dick")).is_ok());
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(lockout_manager.check("h1", Some("tom")).is_ok());
+
+        // Success forgets the failures of the user.
+        lockout_manager.fail("h2", Some("tom"));
+        lockout_manager.fail("h2", Some("tom"));
+        lockout_manager.succeed("tom");
+        assert_eq!(lockout_manager.fail("h2", Some("tom")).as_millis(), 300);
+        assert!(lockout_manager.check("h2", Some("tom")).is_ok());
+    }
+}
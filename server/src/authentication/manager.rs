@@ -1,64 +1,278 @@
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind, Result};
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::Mutex;
+use tokio::sync::RwLock;
 
 use common::MessageStream;
 use common::messages::Message;
 
+use crate::authentication::certificate::CertificateAuthenticator;
 use crate::authentication::htpasswd::HtpasswdAuthenticator;
+use crate::authentication::jwt::JwtAuthenticator;
 use crate::authentication::ldap::LdapAuthenticator;
+use crate::authentication::lockout::LockoutManager;
 use crate::authentication::null::NullAuthenticator;
-use crate::authentication::traits::Authenticator;
+use crate::authentication::peer::PeerCredentialsAuthenticator;
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo};
+use crate::metrics::METRICS;
 use crate::options::AuthenticationOption;
 
+/// Authenticators are read locked while authenticating, so requests do not
+/// wait for each other, and write locked to reset.
+type SharedAuthenticator = Arc<RwLock<dyn Authenticator + Send + Sync>>;
+
+fn create_authenticator(option: &AuthenticationOption) -> Result<SharedAuthenticator> {
+    Ok(match option {
+        AuthenticationOption::None => Arc::new(RwLock::new(NullAuthenticator {})),
+        AuthenticationOption::Basic(path, groups_path) => Arc::new(RwLock::new(
+            HtpasswdAuthenticator::new(path, groups_path.as_ref())?,
+        )),
+        AuthenticationOption::Ldap(url, path) => Arc::new(RwLock::new(LdapAuthenticator::new(
+            url.clone(),
+            path.as_ref(),
+        )?)),
+        AuthenticationOption::Jwt(path) => Arc::new(RwLock::new(JwtAuthenticator::new(path)?)),
+        AuthenticationOption::Certificate(user) => {
+            Arc::new(RwLock::new(CertificateAuthenticator::new(*user)))
+        }
+        AuthenticationOption::PeerCredentials => {
+            Arc::new(RwLock::new(PeerCredentialsAuthenticator {}))
+        }
+    })
+}
+
+/// A failed authentication. The failure is answered after the delay, which
+/// the caller waits for once it no longer holds the manager, so a client
+/// guessing passwords does not keep it locked.
+#[derive(Debug)]
+pub struct AuthenticationFailure {
+    pub error: Error,
+    pub delay: Duration,
+}
+
+impl AuthenticationFailure {
+    /// Wait for the delay, returning the error to answer with.
+    pub async fn wait(self) -> Error {
+        tokio::time::sleep(self.delay).await;
+        self.error
+    }
+}
+
+impl From<Error> for AuthenticationFailure {
+    fn from(error: Error) -> Self {
+        AuthenticationFailure {
+            error,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Dispatches each authentication request to the authenticator for its
+/// method.
 #[derive(Clone)]
 pub struct AuthenticationManager {
-    pub authenticator: Arc<Mutex<dyn Authenticator + Send>>,
+    pub authenticators: HashMap<String, SharedAuthenticator>,
+    lockout_manager: LockoutManager,
 }
 
 impl AuthenticationManager {
-    pub fn new(option: &AuthenticationOption) -> Result<Self> {
-        Ok(match option {
-            AuthenticationOption::None => AuthenticationManager {
-                authenticator: Arc::new(Mutex::new(NullAuthenticator {})),
-            },
-            AuthenticationOption::Basic(path) => AuthenticationManager {
-                authenticator: Arc::new(Mutex::new(HtpasswdAuthenticator::new(&path)?)),
-            },
-            AuthenticationOption::Ldap(url) => AuthenticationManager {
-                authenticator: Arc::new(Mutex::new(LdapAuthenticator::new(url.clone()))),
-            },
+    pub fn new(options: &[AuthenticationOption], lockout_manager: LockoutManager) -> Result<Self> {
+        let mut authenticators = HashMap::new();
+        for option in options {
+            let method = option.method().to_string();
+            if authenticators.contains_key(&method) {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("authentication method {method} given more than once"),
+                ));
+            }
+            authenticators.insert(method, create_authenticator(option)?);
+        }
+        Ok(AuthenticationManager {
+            authenticators,
+            lockout_manager,
         })
     }
 
-    pub async fn authenticate(&self, stream: &mut impl MessageStream) -> Result<String> {
+    pub async fn authenticate(
+        &self,
+        stream: &mut impl MessageStream,
+        peer: &PeerInfo,
+    ) -> std::result::Result<Identity, AuthenticationFailure> {
         let message = stream.read().await?;
+        METRICS.message_received(&message);
         let Message::AuthenticationRequest {
             method,
             credentials,
         } = message
+        else {
+            METRICS.authentication_failed("unknown");
+            return Err(Error::new(ErrorKind::Other, "expected authentication request").into());
+        };
+
+        let Some(auth) = self.authenticators.get(&method) else {
+            METRICS.authentication_failed(method.as_str());
+            let msg = std::format!("invalid method {}", method.as_str());
+            return Err(Error::new(ErrorKind::Other, msg).into());
+        };
+
+        self.authenticate_with(auth, &credentials, peer).await
+    }
+
+    /// Check credentials presented outside the message protocol, as by the
+    /// admin endpoint. The first of the methods with an authenticator is
+    /// used. There is no fallback to no authentication, so the credentials
+    /// are always checked.
+    pub async fn authenticate_credentials(
+        &self,
+        methods: &[&str],
+        credentials: &[u8],
+        peer: &PeerInfo,
+    ) -> std::result::Result<Identity, AuthenticationFailure> {
+        let Some(auth) = methods
+            .iter()
+            .find_map(|method| self.authenticators.get(*method))
         else {
             return Err(Error::new(
                 ErrorKind::Other,
-                "expected authentication request",
-            ));
+                format!("no authentication method for {}", methods.join(" or ")),
+            )
+            .into());
         };
 
-        let auth = self.authenticator.clone();
-        let auth = auth.lock().await;
+        self.authenticate_with(auth, credentials, peer).await
+    }
 
-        if method.as_str() != auth.name() {
-            let msg = std::format!("invalid method {}", method.as_str());
-            return Err(Error::new(ErrorKind::Other, msg));
+    /// Users and hosts which are locked out are refused without checking
+    /// their credentials. Failures are counted, and given a delay.
+    async fn authenticate_with(
+        &self,
+        auth: &SharedAuthenticator,
+        credentials: &[u8],
+        peer: &PeerInfo,
+    ) -> std::result::Result<Identity, AuthenticationFailure> {
+        let auth = auth.read().await;
+        let user = auth.claimed_user(credentials);
+
+        if let Err(error) = self.lockout_manager.check(&peer.host, user.as_deref()) {
+            METRICS.authentication_failed(auth.name());
+            return Err(error.into());
+        }
+
+        match auth.authenticate(credentials, peer).await {
+            Ok(identity) => {
+                self.lockout_manager.succeed(&identity.user);
+                Ok(identity)
+            }
+            Err(error) => {
+                METRICS.authentication_failed(auth.name());
+                let delay = self.lockout_manager.fail(&peer.host, user.as_deref());
+                Err(AuthenticationFailure { error, delay })
+            }
         }
+    }
+
+    /// Reset each authenticator. One which fails keeps its previous state,
+    /// and the others are still reset.
+    pub async fn reset(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for (method, auth) in self.authenticators.iter() {
+            if let Err(error) = auth.write().await.reset().await {
+                errors.push(format!("{method}: {error}"));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::Other, errors.join("; "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use common::MessageSocket;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_dispatch_to_the_authenticator_for_the_method() {
+        let manager = AuthenticationManager::new(
+            &[
+                AuthenticationOption::None,
+                AuthenticationOption::PeerCredentials,
+            ],
+            LockoutManager::default(),
+        )
+        .unwrap();
+
+        let authenticate = |method: &str| {
+            let manager = manager.clone();
+            let request = Message::AuthenticationRequest {
+                method: method.into(),
+                credentials: Vec::new(),
+            };
+            async move {
+                let (client_stream, server_stream) = tokio::io::duplex(1024);
+                MessageSocket::new(client_stream)
+                    .write(&request)
+                    .await
+                    .unwrap();
+                manager
+                    .authenticate(&mut MessageSocket::new(server_stream), &PeerInfo::default())
+                    .await
+            }
+        };
 
-        auth.authenticate(&credentials).await
+        assert_eq!(authenticate("none").await.unwrap().user, "nobody");
+        // Without the credentials of a Unix socket.
+        assert!(authenticate("unix").await.is_err());
+        assert!(authenticate("jwt").await.is_err());
+
+        // Credentials given outside the protocol are never accepted by the
+        // "none" method.
+        assert!(
+            manager
+                .authenticate_credentials(&["basic"], b"garbage", &PeerInfo::default())
+                .await
+                .is_err()
+        );
+
+        assert!(
+            AuthenticationManager::new(
+                &[AuthenticationOption::None, AuthenticationOption::None],
+                LockoutManager::default()
+            )
+            .is_err()
+        );
     }
 
-    pub async fn reset(&mut self) -> Result<()> {
-        let auth = self.authenticator.clone();
-        let mut auth = auth.lock().await;
-        auth.reset().await
+    #[tokio::test(start_paused = true)]
+    async fn should_leave_the_caller_to_wait_after_a_failure() {
+        // The password for tom is "password".
+        let path = std::env::temp_dir().join(format!("{}.htpasswd", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "tom:$apr1$xxxxxxxx$dxHfLAsjHkDRmG83UXe8K0\n").unwrap();
+        let manager = AuthenticationManager::new(
+            &[AuthenticationOption::Basic(path.clone(), None)],
+            LockoutManager::default(),
+        )
+        .unwrap();
+
+        // "tom:wrong"
+        let started = tokio::time::Instant::now();
+        let failure = manager
+            .authenticate_credentials(&["basic"], b"dG9tOndyb25n", &PeerInfo::default())
+            .await
+            .unwrap_err();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(failure.delay, Duration::from_millis(250));
+
+        // The manager can be reset while the failure is waiting.
+        manager.reset().await.unwrap();
+        failure.wait().await;
+        assert_eq!(started.elapsed(), Duration::from_millis(250));
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
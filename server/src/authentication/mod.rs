@@ -1,7 +1,17 @@
 pub mod manager;
 pub use manager::AuthenticationManager;
 
+mod certificate;
+pub use certificate::CertificateUser;
+
 mod htpasswd;
+mod jwt;
 mod ldap;
+
+mod lockout;
+pub use lockout::LockoutManager;
+
 mod null;
+mod peer;
 mod traits;
+pub use traits::{Identity, PeerInfo};
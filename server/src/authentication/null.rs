@@ -2,7 +2,7 @@ use std::io::Result;
 
 use async_trait::async_trait;
 
-use crate::authentication::traits::Authenticator;
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo};
 
 #[derive(Clone)]
 pub struct NullAuthenticator {}
@@ -13,8 +13,8 @@ impl Authenticator for NullAuthenticator {
         "none"
     }
 
-    async fn authenticate(&self, _credentials: &[u8]) -> Result<String> {
-        Ok(String::from("nobody"))
+    async fn authenticate(&self, _credentials: &[u8], _peer: &PeerInfo) -> Result<Identity> {
+        Ok(Identity::new("nobody"))
     }
 
     async fn reset(&mut self) -> Result<()> {
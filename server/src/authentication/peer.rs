@@ -0,0 +1,46 @@
+use std::io::{Error, ErrorKind, Result};
+
+use async_trait::async_trait;
+use nix::unistd::{Uid, User};
+
+use crate::authentication::traits::{Authenticator, Identity, PeerInfo};
+
+/// Authenticates clients connected by a Unix socket as the user running the
+/// client process, taken from the credentials of the socket.
+#[derive(Clone)]
+pub struct PeerCredentialsAuthenticator {}
+
+#[async_trait]
+impl Authenticator for PeerCredentialsAuthenticator {
+    fn name(&self) -> &str {
+        "unix"
+    }
+
+    async fn authenticate(&self, _credentials: &[u8], peer: &PeerInfo) -> Result<Identity> {
+        let Some(uid) = peer.uid else {
+            log::info!("Failed to authenticate client without peer credentials.");
+            return Err(Error::new(ErrorKind::Other, "no peer credentials"));
+        };
+
+        match User::from_uid(Uid::from_raw(uid)) {
+            Ok(Some(user)) => {
+                log::info!("Authenticated as \"{}\".", user.name);
+                let mut identity = Identity::new(user.name);
+                identity.attributes.insert("uid".into(), uid.to_string());
+                Ok(identity)
+            }
+            Ok(None) => {
+                log::info!("Failed to authenticate unknown user id {}.", uid);
+                Err(Error::new(
+                    ErrorKind::Other,
+                    format!("no user with id {}", uid),
+                ))
+            }
+            Err(error) => Err(Error::new(ErrorKind::Other, error)),
+        }
+    }
+
+    async fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
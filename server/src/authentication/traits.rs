@@ -1,10 +1,67 @@
+use std::collections::HashMap;
 use std::io::Result;
 
 use async_trait::async_trait;
+use http_auth_basic::Credentials;
+use pki_types::CertificateDer;
+
+/// What the transport knows about a client before it authenticates.
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    pub host: String,
+    /// The certificate chain presented by a TLS client, leaf first. This is
+    /// empty when the client did not present a certificate.
+    pub certificates: Vec<CertificateDer<'static>>,
+    /// The user id of the process at the other end of a Unix socket.
+    pub uid: Option<u32>,
+}
+
+/// Who an authenticated client is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Identity {
+    pub user: String,
+    /// The groups the user belongs to, which authorizations can name as
+    /// `@group`.
+    pub groups: Vec<String>,
+    /// Anything else the authenticator knows about the user.
+    pub attributes: HashMap<String, String>,
+}
+
+impl Identity {
+    pub fn new(user: impl Into<String>) -> Self {
+        Identity {
+            user: user.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for Identity {
+    fn from(user: &str) -> Self {
+        Identity::new(user)
+    }
+}
+
+impl From<String> for Identity {
+    fn from(user: String) -> Self {
+        Identity::new(user)
+    }
+}
 
 #[async_trait]
 pub trait Authenticator {
     fn name(&self) -> &str;
-    async fn authenticate(&self, credentials: &[u8]) -> Result<String>;
+    /// The user the credentials claim to be, before they are checked. This
+    /// lets failures be counted against the user.
+    fn claimed_user(&self, _credentials: &[u8]) -> Option<String> {
+        None
+    }
+    async fn authenticate(&self, credentials: &[u8], peer: &PeerInfo) -> Result<Identity>;
     async fn reset(&mut self) -> Result<()>;
 }
+
+/// The user named by basic credentials.
+pub fn basic_user(credentials: &[u8]) -> Option<String> {
+    let credentials = String::from_utf8(credentials.into()).ok()?;
+    Some(Credentials::decode(credentials).ok()?.user_id)
+}
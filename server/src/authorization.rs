@@ -2,8 +2,10 @@ use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, ErrorKind, Result};
 use std::path::Path;
+use std::sync::Mutex;
 
 use bitflags::bitflags;
+use common::TopicIndex;
 use serde::{Deserialize, Serialize};
 use wildmatch::WildMatch;
 
@@ -30,33 +32,200 @@ pub struct AuthorizationSpec {
     pub roles: Role,
 }
 
+/// The most entitlement results to remember before starting again.
+const MAX_CACHED_ENTITLEMENTS: usize = 100_000;
+
+/// The entitlements of a user with some groups, by topic.
+type TopicEntitlements = HashMap<String, HashSet<i32>>;
+
+/// Entitlements which have already been resolved, by role, user, groups and
+/// topic.
+struct EntitlementCache {
+    entries: HashMap<Role, HashMap<String, HashMap<Vec<String>, TopicEntitlements>>>,
+    len: usize,
+}
+
+impl EntitlementCache {
+    fn new() -> Self {
+        EntitlementCache {
+            entries: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    fn get(
+        &self,
+        user_name: &str,
+        groups: &[String],
+        topic: &str,
+        role: Role,
+    ) -> Option<&HashSet<i32>> {
+        self.entries
+            .get(&role)?
+            .get(user_name)?
+            .get(groups)?
+            .get(topic)
+    }
+
+    fn insert(
+        &mut self,
+        user_name: &str,
+        groups: &[String],
+        topic: &str,
+        role: Role,
+        entitlements: HashSet<i32>,
+    ) {
+        if self.len >= MAX_CACHED_ENTITLEMENTS {
+            log::debug!("Entitlement cache is full; clearing.");
+            self.clear();
+        }
+
+        self.entries
+            .entry(role)
+            .or_default()
+            .entry(user_name.to_string())
+            .or_default()
+            .entry(groups.to_vec())
+            .or_default()
+            .insert(topic.to_string(), entitlements);
+        self.len += 1;
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.len = 0;
+    }
+}
+
+impl AuthorizationSpec {
+    /// A user pattern starting with "@" names a group rather than a user.
+    fn matches(&self, user_name: &str, groups: &[String]) -> bool {
+        if self.user_pattern.to_string().starts_with('@') {
+            groups
+                .iter()
+                .any(|group| self.user_pattern.matches(&format!("@{group}")))
+        } else {
+            self.user_pattern.matches(user_name)
+        }
+    }
+}
+
 pub struct AuthorizationManager {
     specs: Vec<AuthorizationSpec>,
+    /// The indices of the specs for each topic pattern.
+    topics: TopicIndex<Vec<usize>>,
+    cache: Mutex<EntitlementCache>,
 }
 
 impl AuthorizationManager {
     pub fn new(specs: Vec<AuthorizationSpec>) -> Self {
-        AuthorizationManager { specs }
+        let topics = index_topics(&specs);
+        AuthorizationManager {
+            specs,
+            topics,
+            cache: Mutex::new(EntitlementCache::new()),
+        }
+    }
+
+    /// A copy of the current authorizations, for use away from the hub.
+    pub fn snapshot(&self) -> Self {
+        AuthorizationManager::new(self.specs.clone())
     }
 
     pub fn reset(&mut self, specs: Vec<AuthorizationSpec>) {
-        self.specs = specs
+        self.topics = index_topics(&specs);
+        self.specs = specs;
+        self.cache.get_mut().unwrap().clear();
+    }
+
+    /// The specs for the topic which grant the role to the user, or to one
+    /// of the groups they authenticated with.
+    fn matching_specs(
+        &self,
+        user_name: &str,
+        groups: &[String],
+        topic: &str,
+        role: Role,
+    ) -> impl Iterator<Item = &AuthorizationSpec> {
+        self.topics
+            .matches(topic)
+            .into_iter()
+            .flat_map(|(_pattern, indices)| indices.iter().map(|index| &self.specs[*index]))
+            .filter(move |spec| spec.roles.contains(role) && spec.matches(user_name, groups))
     }
 
-    pub fn entitlements(&self, user_name: &str, topic: &str, role: Role) -> HashSet<i32> {
+    /// The entitlements the user, with the groups of their session, has for
+    /// the topic in the role. Results are cached until the authorizations are
+    /// reset.
+    pub fn entitlements(
+        &self,
+        user_name: &str,
+        groups: &[String],
+        topic: &str,
+        role: Role,
+    ) -> HashSet<i32> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(entitlements) = cache.get(user_name, groups, topic, role) {
+            return entitlements.clone();
+        }
+
         let mut entitlements = HashSet::new();
 
-        for spec in &self.specs {
-            if spec.roles.contains(role)
-                && spec.user_pattern.matches(user_name)
-                && spec.topic_pattern.matches(topic)
-            {
-                entitlements.extend(spec.entitlements.iter());
-            }
+        for spec in self.matching_specs(user_name, groups, topic, role) {
+            entitlements.extend(spec.entitlements.iter());
         }
 
+        cache.insert(user_name, groups, topic, role, entitlements.clone());
+
         entitlements
     }
+
+    /// Check if the user has the role for the topic or topic pattern. A
+    /// pattern must be covered by the pattern of a single spec, so a grant
+    /// of "A?" does not authorize "A*".
+    pub fn is_authorized(
+        &self,
+        user_name: &str,
+        groups: &[String],
+        topic: &str,
+        role: Role,
+    ) -> bool {
+        self.matching_specs(user_name, groups, topic, role)
+            .any(|spec| pattern_covers(&spec.topic_pattern.to_string(), topic))
+    }
+}
+
+/// Whether every topic matched by the requested pattern is also matched by
+/// the granted pattern. A "*" in the granted pattern stands for any part of
+/// the request, wildcards included, and a "?" for one character or "?".
+/// Requests this cannot show to be covered are refused.
+fn pattern_covers(granted: &str, requested: &str) -> bool {
+    let requested: Vec<char> = requested.chars().collect();
+    // Whether the granted pattern so far covers each prefix of the request.
+    let mut covered = vec![false; requested.len() + 1];
+    covered[0] = true;
+    for granted in granted.chars() {
+        let mut next = vec![false; requested.len() + 1];
+        for j in 0..=requested.len() {
+            next[j] = match granted {
+                '*' => covered[j] || (j > 0 && next[j - 1]),
+                '?' => j > 0 && requested[j - 1] != '*' && covered[j - 1],
+                c => j > 0 && requested[j - 1] == c && covered[j - 1],
+            };
+        }
+        covered = next;
+    }
+    covered[requested.len()]
+}
+
+fn index_topics(specs: &[AuthorizationSpec]) -> TopicIndex<Vec<usize>> {
+    let mut topics = TopicIndex::new();
+    for (index, spec) in specs.iter().enumerate() {
+        topics
+            .get_or_insert_with(&spec.topic_pattern.to_string(), Vec::new)
+            .push(index);
+    }
+    topics
 }
 
 fn load_authorizations_from_file<P>(
@@ -153,35 +322,154 @@ mod test {
         ];
         let entitlements_manager = AuthorizationManager::new(user_entitlements_spec);
 
-        let actual = entitlements_manager.entitlements("nobody", "PUB.foo", Role::Subscriber);
+        let actual = entitlements_manager.entitlements("nobody", &[], "PUB.foo", Role::Subscriber);
         let expected: HashSet<i32> = HashSet::from([0]);
         assert_eq!(actual, expected);
 
-        let actual = entitlements_manager.entitlements("nobody", "PUB.foo", Role::Publisher);
+        let actual = entitlements_manager.entitlements("nobody", &[], "PUB.foo", Role::Publisher);
         let expected: HashSet<i32> = HashSet::from([0]);
         assert_eq!(actual, expected);
 
-        let actual = entitlements_manager.entitlements("nobody", "PUB.foo", Role::Notifier);
+        let actual = entitlements_manager.entitlements("nobody", &[], "PUB.foo", Role::Notifier);
         let expected: HashSet<i32> = HashSet::from([0]);
         assert_eq!(actual, expected);
 
-        let actual = entitlements_manager.entitlements("joe", "TSCO.LSE", Role::Subscriber);
+        let actual = entitlements_manager.entitlements("joe", &[], "TSCO.LSE", Role::Subscriber);
         let expected: HashSet<i32> = HashSet::from([1, 2]);
         assert_eq!(actual, expected);
 
-        let actual = entitlements_manager.entitlements("joe", "TSCO.LSE", Role::Notifier);
+        let actual = entitlements_manager.entitlements("joe", &[], "TSCO.LSE", Role::Notifier);
         let expected: HashSet<i32> = HashSet::from([1, 2]);
         assert_eq!(actual, expected);
 
-        let actual = entitlements_manager.entitlements("joe", "TSCO.LSE", Role::Publisher);
+        let actual = entitlements_manager.entitlements("joe", &[], "TSCO.LSE", Role::Publisher);
         assert!(actual.is_empty());
 
-        let actual = entitlements_manager.entitlements("joe", "IBM.NSE", Role::Subscriber);
+        let actual = entitlements_manager.entitlements("joe", &[], "IBM.NSE", Role::Subscriber);
         let expected: HashSet<i32> = HashSet::from([3, 4]);
         assert_eq!(actual, expected);
 
-        let actual = entitlements_manager.entitlements("joe", "MSFT.NDAQ", Role::Subscriber);
+        let actual = entitlements_manager.entitlements("joe", &[], "MSFT.NDAQ", Role::Subscriber);
         let expected: HashSet<i32> = HashSet::from([]);
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn is_authorized() {
+        let user_entitlements_spec = vec![
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("*"),
+                topic_pattern: WildMatch::new("PUB.*"),
+                entitlements: HashSet::from([0]),
+                roles: Role::Subscriber | Role::Notifier | Role::Publisher,
+            },
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("joe"),
+                topic_pattern: WildMatch::new("*.LSE"),
+                entitlements: HashSet::from([1, 2]),
+                roles: Role::Subscriber,
+            },
+        ];
+        let authorization_manager = AuthorizationManager::new(user_entitlements_spec);
+
+        assert!(authorization_manager.is_authorized("nobody", &[], "PUB.foo", Role::Notifier));
+        assert!(authorization_manager.is_authorized("nobody", &[], "PUB.*", Role::Subscriber));
+        assert!(!authorization_manager.is_authorized("nobody", &[], "*", Role::Subscriber));
+        assert!(!authorization_manager.is_authorized("nobody", &[], "TSCO.LSE", Role::Subscriber));
+        assert!(authorization_manager.is_authorized("joe", &[], "TSCO.LSE", Role::Subscriber));
+        assert!(authorization_manager.is_authorized("joe", &[], "*.LSE", Role::Subscriber));
+        assert!(!authorization_manager.is_authorized("joe", &[], "TSCO.LSE", Role::Notifier));
+    }
+
+    #[test]
+    fn should_only_authorize_patterns_covered_by_a_spec() {
+        let authorization_manager = AuthorizationManager::new(vec![
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("*"),
+                topic_pattern: WildMatch::new("A?"),
+                entitlements: HashSet::new(),
+                roles: Role::Subscriber,
+            },
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("*"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::new(),
+                roles: Role::Subscriber,
+            },
+        ]);
+
+        assert!(authorization_manager.is_authorized("joe", &[], "AB", Role::Subscriber));
+        assert!(authorization_manager.is_authorized("joe", &[], "A?", Role::Subscriber));
+        // "A*" would also match "ABC".
+        assert!(!authorization_manager.is_authorized("joe", &[], "A*", Role::Subscriber));
+        assert!(!authorization_manager.is_authorized("joe", &[], "?B", Role::Subscriber));
+
+        assert!(authorization_manager.is_authorized("joe", &[], "LSE.*", Role::Subscriber));
+        assert!(authorization_manager.is_authorized("joe", &[], "LSE.V*", Role::Subscriber));
+        assert!(authorization_manager.is_authorized("joe", &[], "LSE.?OD", Role::Subscriber));
+        assert!(!authorization_manager.is_authorized("joe", &[], "LSE*", Role::Subscriber));
+        assert!(!authorization_manager.is_authorized("joe", &[], "*", Role::Subscriber));
+    }
+
+    #[test]
+    fn should_clear_cached_entitlements_on_reset() {
+        let mut authorization_manager = AuthorizationManager::new(vec![AuthorizationSpec {
+            user_pattern: WildMatch::new("*"),
+            topic_pattern: WildMatch::new("LSE.*"),
+            entitlements: HashSet::from([1]),
+            roles: Role::Subscriber,
+        }]);
+
+        let actual = authorization_manager.entitlements("joe", &[], "LSE.VOD", Role::Subscriber);
+        assert_eq!(actual, HashSet::from([1]));
+        // Served from the cache.
+        let actual = authorization_manager.entitlements("joe", &[], "LSE.VOD", Role::Subscriber);
+        assert_eq!(actual, HashSet::from([1]));
+
+        authorization_manager.reset(vec![AuthorizationSpec {
+            user_pattern: WildMatch::new("*"),
+            topic_pattern: WildMatch::new("LSE.*"),
+            entitlements: HashSet::from([2]),
+            roles: Role::Subscriber,
+        }]);
+
+        let actual = authorization_manager.entitlements("joe", &[], "LSE.VOD", Role::Subscriber);
+        assert_eq!(actual, HashSet::from([2]));
+    }
+
+    #[test]
+    fn should_authorize_groups() {
+        let authorization_manager = AuthorizationManager::new(vec![
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("@traders"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::from([1]),
+                roles: Role::Subscriber,
+            },
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("joe"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::from([2]),
+                roles: Role::Subscriber,
+            },
+        ]);
+        let traders = ["traders".to_string()];
+
+        let actual = authorization_manager.entitlements("joe", &[], "LSE.VOD", Role::Subscriber);
+        assert_eq!(actual, HashSet::from([2]));
+        assert!(!authorization_manager.is_authorized("tom", &[], "LSE.VOD", Role::Subscriber));
+
+        // The groups are those of each session, so two sessions of the same
+        // user may differ.
+        let actual =
+            authorization_manager.entitlements("joe", &traders, "LSE.VOD", Role::Subscriber);
+        assert_eq!(actual, HashSet::from([1, 2]));
+        let actual = authorization_manager.entitlements("joe", &[], "LSE.VOD", Role::Subscriber);
+        assert_eq!(actual, HashSet::from([2]));
+
+        let groups = ["risk".to_string(), "traders".to_string()];
+        assert!(authorization_manager.is_authorized("tom", &groups, "LSE.VOD", Role::Subscriber));
+        // A user is not a group.
+        assert!(!authorization_manager.is_authorized("@traders", &[], "LSE.VOD", Role::Subscriber));
+    }
 }
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use wildmatch::WildMatch;
+
+use common::messages::{DataPacket, Message};
+
+use crate::{
+    authorization::{AuthorizationManager, Role},
+    clients::ClientManager,
+    events::ServerEvent,
+    publishing::{get_authorized_data, get_authorized_entitlements},
+};
+
+/// The last data published on a topic.
+struct Image {
+    host: String,
+    user: String,
+    /// The groups of the publisher's session, for its entitlements.
+    groups: Vec<String>,
+    data_packets: Vec<DataPacket>,
+}
+
+/// Keeps the last value published to topics matching the configured
+/// patterns, so it can be sent to new subscribers.
+pub struct CacheManager {
+    patterns: Vec<WildMatch>,
+    images: HashMap<String, Image>,
+}
+
+impl CacheManager {
+    pub fn new(patterns: &[String]) -> CacheManager {
+        CacheManager {
+            patterns: patterns
+                .iter()
+                .map(|pattern| WildMatch::new(pattern))
+                .collect(),
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn is_cached(&self, topic: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(topic))
+    }
+
+    pub fn handle_multicast_data(
+        &mut self,
+        host: &str,
+        user: &str,
+        groups: &[String],
+        topic: &str,
+        data_packets: &[DataPacket],
+    ) {
+        if !self.is_cached(topic) {
+            return;
+        }
+
+        log::trace!("Caching topic \"{topic}\".");
+
+        self.images.insert(
+            topic.to_string(),
+            Image {
+                host: host.to_string(),
+                user: user.to_string(),
+                groups: groups.to_vec(),
+                data_packets: data_packets.to_vec(),
+            },
+        );
+    }
+
+    /// Send the cached images for topics matching a new subscription.
+    pub fn send_images(
+        &self,
+        subscriber_id: &str,
+        pattern: &str,
+        client_manager: &ClientManager,
+        entitlements_manager: &AuthorizationManager,
+    ) {
+        let Some(subscriber) = client_manager.get(subscriber_id) else {
+            log::trace!("Subscriber {subscriber_id} is not known; skipping.");
+            return;
+        };
+
+        let pattern = WildMatch::new(pattern);
+
+        for (topic, image) in &self.images {
+            if !pattern.matches(topic) {
+                continue;
+            }
+
+            // The image is filtered exactly as if it had just been published.
+            let publisher_entitlements = entitlements_manager.entitlements(
+                image.user.as_str(),
+                &image.groups,
+                topic,
+                Role::Publisher,
+            );
+            let subscriber_entitlements = entitlements_manager.entitlements(
+                subscriber.user.as_str(),
+                &subscriber.groups,
+                topic,
+                Role::Subscriber,
+            );
+            let Some(entitlements) =
+                get_authorized_entitlements(&publisher_entitlements, &subscriber_entitlements)
+            else {
+                log::trace!(
+                    "Publisher {} is not authorized to send cached topic \"{}\" to subscriber {}; skipping.",
+                    image.user,
+                    topic,
+                    subscriber.user,
+                );
+                continue;
+            };
+
+            let auth_data_packets = get_authorized_data(&image.data_packets, &entitlements);
+            if auth_data_packets.is_empty() {
+                log::trace!(
+                    "No authorized packets in cached topic \"{}\" for {}; skipping.",
+                    topic,
+                    subscriber.user
+                );
+                continue;
+            }
+
+            log::trace!("Sending cached topic \"{topic}\" to subscriber {subscriber_id}.");
+
+            let message = Message::ForwardedMulticastData {
+                host: image.host.clone(),
+                user: image.user.clone(),
+                topic: topic.clone(),
+                data_packets: auth_data_packets,
+            };
+            subscriber.send(ServerEvent::OnMessage(message));
+        }
+    }
+
+    /// Forget the images of topics which no longer have publishers.
+    pub fn handle_stale_topics(&mut self, topics: &[String]) {
+        for topic in topics {
+            if self.images.remove(topic).is_some() {
+                log::debug!("Removed cached image for stale topic \"{topic}\".");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use wildmatch::WildMatch;
+
+    use crate::authorization::AuthorizationSpec;
+    use crate::clients::Transport;
+    use crate::outbound::{self, OverflowPolicy};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_filter_images_by_entitlements() {
+        let authorization_manager = AuthorizationManager::new(vec![
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("harry"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::from([1, 2]),
+                roles: Role::Publisher,
+            },
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("dick"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::from([1]),
+                roles: Role::Subscriber,
+            },
+        ]);
+
+        let mut client_manager = ClientManager::new();
+        let (subscriber_tx, mut subscriber_rx) = outbound::channel(10, OverflowPolicy::Disconnect);
+        client_manager.handle_connect(
+            "s1",
+            "host2".into(),
+            "dick".into(),
+            Transport::Socket,
+            subscriber_tx,
+        );
+
+        let level1 = DataPacket::new(HashSet::from([1]), HashMap::new(), "level 1".into());
+        let level2 = DataPacket::new(HashSet::from([2]), HashMap::new(), "level 2".into());
+
+        let mut cache_manager = CacheManager::new(&["LSE.*".to_string()]);
+        cache_manager.handle_multicast_data(
+            "host1",
+            "harry",
+            &[],
+            "LSE.VOD",
+            &[level1.clone(), level2.clone()],
+        );
+        cache_manager.handle_multicast_data(
+            "host1",
+            "harry",
+            &[],
+            "NYSE.IBM",
+            std::slice::from_ref(&level1),
+        );
+        assert!(!cache_manager.images.contains_key("NYSE.IBM"));
+
+        cache_manager.send_images("s1", "LSE.*", &client_manager, &authorization_manager);
+
+        let actual = subscriber_rx.recv().await.unwrap();
+        let expected = ServerEvent::OnMessage(Message::ForwardedMulticastData {
+            host: "host1".into(),
+            user: "harry".into(),
+            topic: "LSE.VOD".into(),
+            data_packets: vec![level1],
+        });
+        assert_eq!(actual, expected);
+
+        cache_manager.handle_stale_topics(&["LSE.VOD".to_string()]);
+        assert!(cache_manager.images.is_empty());
+    }
+}
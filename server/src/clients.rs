@@ -1,17 +1,112 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
+use std::time::SystemTime;
 
-use tokio::sync::mpsc::Sender;
-
+use crate::authentication::Identity;
+use crate::authorization::AuthorizationManager;
+use crate::caching::CacheManager;
 use crate::events::ServerEvent;
+use crate::metrics::METRICS;
 use crate::notifications::NotificationManager;
+use crate::outbound::{Delivery, OutboundSender, OverflowPolicy};
 use crate::publishing::PublisherManager;
 use crate::subscriptions::SubscriptionManager;
 
+/// How a client is connected to the broker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Socket,
+    TlsSocket,
+    WebSocket,
+    TlsWebSocket,
+    Unix,
+    /// A federation link made by this broker.
+    Federation,
+    TlsFederation,
+}
+
+impl Transport {
+    /// The name of the transport, without TLS.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::Socket | Transport::TlsSocket => "socket",
+            Transport::WebSocket | Transport::TlsWebSocket => "web-socket",
+            Transport::Unix => "unix",
+            Transport::Federation | Transport::TlsFederation => "federation",
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        matches!(
+            self,
+            Transport::TlsSocket | Transport::TlsWebSocket | Transport::TlsFederation
+        )
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.is_tls() {
+            true => write!(f, "{}+tls", self.name()),
+            false => write!(f, "{}", self.name()),
+        }
+    }
+}
+
 pub struct Client {
-    pub tx: Sender<ServerEvent>,
+    pub id: String,
+    pub tx: OutboundSender,
     pub host: String,
     pub user: String,
+    pub groups: Vec<String>,
+    pub attributes: HashMap<String, String>,
+    pub transport: Transport,
+    pub connected_at: SystemTime,
+    /// The broker at the other end, if the client is a federation link.
+    pub peer: Option<String>,
+}
+
+impl Client {
+    pub fn is_federated(&self) -> bool {
+        self.peer.is_some()
+    }
+
+    /// Queue an event for the client without waiting.
+    pub fn send(&self, event: ServerEvent) {
+        match self.tx.send(event) {
+            Delivery::Queued => {}
+            Delivery::Overflowed(OverflowPolicy::Disconnect, dropped) => {
+                log::warn!(
+                    "Outbound queue for client {} ({}@{}) is full; disconnecting after {} dropped messages.",
+                    self.id,
+                    self.user,
+                    self.host,
+                    dropped
+                )
+            }
+            Delivery::Overflowed(policy, 1) => {
+                log::warn!(
+                    "Outbound queue for client {} ({}@{}) is full; applying policy {}.",
+                    self.id,
+                    self.user,
+                    self.host,
+                    policy
+                )
+            }
+            Delivery::Overflowed(policy, dropped) => {
+                log::debug!(
+                    "Outbound queue for client {} is full; applied policy {} ({} dropped).",
+                    self.id,
+                    policy,
+                    dropped
+                )
+            }
+            Delivery::Closed => {
+                log::trace!("Outbound queue for client {} is closed; skipping.", self.id)
+            }
+        }
+    }
 }
 
 pub struct ClientManager {
@@ -29,12 +124,44 @@ impl ClientManager {
         &mut self,
         client_id: &str,
         host: String,
-        user: String,
-        tx: Sender<ServerEvent>,
+        identity: Identity,
+        transport: Transport,
+        tx: OutboundSender,
     ) {
-        log::debug!("Connected to {user}@{host} as client {client_id}.");
-        self.clients
-            .insert(client_id.into(), Client { host, user, tx });
+        log::debug!(
+            "Connected to {}@{host} by {transport} as client {client_id}.",
+            identity.user
+        );
+        METRICS.client_connected(transport);
+        self.clients.insert(
+            client_id.into(),
+            Client {
+                id: client_id.into(),
+                host,
+                user: identity.user,
+                groups: identity.groups,
+                attributes: identity.attributes,
+                transport,
+                connected_at: SystemTime::now(),
+                tx,
+                peer: None,
+            },
+        );
+    }
+
+    /// Mark the client as a federation link to another broker.
+    pub fn handle_federation(&mut self, client_id: &str, peer: String) -> io::Result<()> {
+        let client = self.clients.get_mut(client_id).ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("unknown client {client_id}"),
+        ))?;
+        log::info!(
+            "Client {client_id} ({}@{}) is a federation link to broker {peer}.",
+            client.user,
+            client.host
+        );
+        client.peer = Some(peer);
+        Ok(())
     }
 
     pub async fn handle_close(
@@ -43,25 +170,49 @@ impl ClientManager {
         subscription_manager: &mut SubscriptionManager,
         notification_manager: &mut NotificationManager,
         publisher_manager: &mut PublisherManager,
+        cache_manager: &mut CacheManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
         log::debug!("Closing client {client_id}.");
 
         subscription_manager
-            .handle_close(client_id, self, notification_manager)
+            .handle_close(client_id, self, notification_manager, authorization_manager)
             .await?;
 
         notification_manager.handle_close(client_id).await?;
 
         publisher_manager
-            .handle_close(client_id, self, subscription_manager)
+            .handle_close(client_id, self, subscription_manager, cache_manager)
             .await?;
 
-        self.clients.remove(client_id);
+        if let Some(client) = self.clients.remove(client_id) {
+            METRICS.client_disconnected(client.transport);
+        }
 
         Ok(())
     }
 
+    /// Close the connection to a client. The client is removed when its
+    /// interactor sees the queue has closed, exactly as if it had gone away.
+    pub fn disconnect(&self, client_id: &str) -> io::Result<()> {
+        let client = self.clients.get(client_id).ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("unknown client {client_id}"),
+        ))?;
+        log::info!(
+            "Disconnecting client {client_id} ({}@{}).",
+            client.user,
+            client.host
+        );
+        client.tx.close();
+        Ok(())
+    }
+
     pub fn get(&self, client_id: &str) -> Option<&Client> {
         self.clients.get(client_id)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
 }
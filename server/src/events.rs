@@ -1,16 +1,28 @@
-use tokio::sync::mpsc::Sender;
+use std::io;
 
 use common::messages::Message;
 
+use crate::admin::AdminRequest;
+use crate::authentication::Identity;
 use crate::authorization::AuthorizationSpec;
+use crate::clients::Transport;
+use crate::outbound::{OutboundSender, OverflowPolicy};
 
 pub enum ClientEvent {
-    OnConnect(String, String, String, Sender<ServerEvent>),
+    OnConnect(String, String, Identity, Transport, OutboundSender),
     OnClose(String),
-    OnMessage(String, Message),
+    OnMessage(String, u64, Message),
+    /// A message received by a federation link from the peer broker.
+    OnFederatedMessage(String, Message),
     OnReset(Vec<AuthorizationSpec>),
+    OnAdmin(AdminRequest),
+    /// A journal replay for a client request has finished, up to the returned
+    /// sequence number.
+    OnReplayed(String, u64, io::Result<u64>),
 }
 
+#[derive(Debug, PartialEq)]
 pub enum ServerEvent {
     OnMessage(Message),
+    OnOverflow(OverflowPolicy, u64),
 }
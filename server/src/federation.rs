@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::Duration;
+
+use http_auth_basic::Credentials;
+use pki_types::ServerName;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use common::messages::Message;
+use common::{MessageSocket, MessageStream};
+
+use crate::clients::Transport;
+use crate::events::{ClientEvent, ServerEvent};
+use crate::options::{FederationOption, HeartbeatOption, OutboundQueueOption};
+use crate::outbound::{self, OutboundReceiver};
+use crate::tls::create_connector;
+
+const RECONNECT_SECONDS: u64 = 5;
+
+/// A link to a peer broker.
+///
+/// The link is a client of the peer, and a special client of the local hub.
+/// It listens for local subscriptions to the configured topic patterns, makes
+/// the same subscriptions on the peer, and passes the data it receives to the
+/// local hub with the host and user of the original publisher.
+///
+/// Both ends of the link are marked as federated, and neither data nor
+/// subscriptions are passed from one link to another, so they cannot loop
+/// between brokers.
+pub struct FederationLink {
+    option: FederationOption,
+    broker_id: String,
+    heartbeat: HeartbeatOption,
+    outbound_queue: OutboundQueueOption,
+    hub: Sender<ClientEvent>,
+}
+
+/// The state of a single connection to the peer.
+struct Session {
+    link_id: String,
+    request_count: u64,
+    /// The local subscribers to each topic subscribed on the peer.
+    subscribers: HashMap<String, HashSet<String>>,
+}
+
+impl FederationLink {
+    pub fn new(
+        option: FederationOption,
+        broker_id: String,
+        heartbeat: HeartbeatOption,
+        outbound_queue: OutboundQueueOption,
+        hub: Sender<ClientEvent>,
+    ) -> FederationLink {
+        FederationLink {
+            option,
+            broker_id,
+            heartbeat,
+            outbound_queue,
+            hub,
+        }
+    }
+
+    /// Maintain the link, reconnecting when it drops.
+    pub async fn run(&self) -> io::Result<()> {
+        loop {
+            match self.connect().await {
+                Ok(()) => log::info!("Federation link to {} closed.", self.option.endpoint),
+                Err(error) => log::warn!(
+                    "Federation link to {} failed: {error}",
+                    self.option.endpoint
+                ),
+            }
+
+            tokio::time::sleep(Duration::from_secs(RECONNECT_SECONDS)).await;
+        }
+    }
+
+    async fn connect(&self) -> io::Result<()> {
+        let endpoint = self.option.endpoint.as_str();
+        log::info!("Connecting federation link to {endpoint}.");
+        let stream = TcpStream::connect(endpoint).await?;
+
+        match &self.option.cafile {
+            Some(cafile) => {
+                let connector = create_connector(cafile)?;
+                let host = endpoint.rsplit_once(':').map_or(endpoint, |(host, _)| host);
+                let domain = ServerName::try_from(host.to_string())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let stream = connector.connect(domain, stream).await?;
+                self.run_session(&mut MessageSocket::new(stream), Transport::TlsFederation)
+                    .await
+            }
+            None => {
+                self.run_session(&mut MessageSocket::new(stream), Transport::Federation)
+                    .await
+            }
+        }
+    }
+
+    async fn run_session(
+        &self,
+        peer: &mut impl MessageStream,
+        transport: Transport,
+    ) -> io::Result<()> {
+        self.authenticate(peer).await?;
+        peer.write(&Message::FederationRequest {
+            broker_id: self.broker_id.clone(),
+        })
+        .await?;
+
+        let (tx, mut rx) =
+            outbound::channel(self.outbound_queue.capacity, self.outbound_queue.policy);
+        let user = match &self.option.credentials {
+            Some(credentials) => credentials.username.clone(),
+            None => "nobody".to_string(),
+        };
+        let mut session = Session {
+            link_id: Uuid::new_v4().into(),
+            request_count: 0,
+            subscribers: HashMap::new(),
+        };
+
+        self.send_to_hub(ClientEvent::OnConnect(
+            session.link_id.clone(),
+            self.option.endpoint.clone(),
+            user.into(),
+            transport,
+            tx,
+        ))
+        .await?;
+
+        let result = self.forward(&mut session, peer, &mut rx).await;
+
+        // Remove the link from the hub, exactly as if a client had
+        // disconnected. The peer does the same when the connection closes.
+        self.send_to_hub(ClientEvent::OnClose(session.link_id.clone()))
+            .await?;
+
+        result
+    }
+
+    async fn authenticate(&self, peer: &mut impl MessageStream) -> io::Result<()> {
+        let request = match &self.option.credentials {
+            Some(credentials) => Message::AuthenticationRequest {
+                method: credentials.method.clone(),
+                credentials: Credentials::new(&credentials.username, &credentials.password()?)
+                    .encode()
+                    .into(),
+            },
+            None => Message::AuthenticationRequest {
+                method: "none".into(),
+                credentials: Vec::new(),
+            },
+        };
+        peer.write(&request).await?;
+
+        match peer.read().await? {
+            Message::AuthenticationResponse { client_id } => {
+                log::info!(
+                    "Federation link to {} authenticated as client {client_id}.",
+                    self.option.endpoint
+                );
+                Ok(())
+            }
+            Message::Error { code, reason, .. } => Err(io::Error::new(code.into(), reason)),
+            message => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected authentication response {message:?}"),
+            )),
+        }
+    }
+
+    async fn forward(
+        &self,
+        session: &mut Session,
+        peer: &mut impl MessageStream,
+        rx: &mut OutboundReceiver,
+    ) -> io::Result<()> {
+        // Mark the local end as federated, then listen for subscriptions.
+        self.send_to_local(
+            session,
+            Message::FederationRequest {
+                broker_id: self.option.endpoint.clone(),
+            },
+        )
+        .await?;
+        for pattern in &self.option.patterns {
+            self.send_to_local(
+                session,
+                Message::NotificationRequest {
+                    pattern: pattern.clone(),
+                    is_add: true,
+                },
+            )
+            .await?;
+        }
+
+        // Heartbeats are sent to the peer as any client would, and the link
+        // is dropped if the peer stops sending.
+        let interval = Duration::from_secs(self.heartbeat.seconds);
+        let mut deadline = Instant::now() + interval;
+        let mut last_received = Instant::now();
+        let mut heartbeat_count = 0;
+
+        loop {
+            tokio::select! {
+                result = peer.read() => {
+                    last_received = Instant::now();
+                    self.forward_peer_to_hub(session, result?).await?;
+                }
+                event = rx.recv() => {
+                    let Some(event) = event else {
+                        return Err(io::Error::new(io::ErrorKind::Other, "outbound queue closed"));
+                    };
+                    self.forward_hub_to_peer(session, event, peer).await?;
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    deadline += interval;
+                    if self.heartbeat.max_missed > 0
+                        && last_received.elapsed() >= interval * self.heartbeat.max_missed
+                    {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "federation peer missed heartbeats",
+                        ));
+                    }
+                    peer.write(&Message::Heartbeat { count: heartbeat_count }).await?;
+                    heartbeat_count += 1;
+                }
+            }
+        }
+    }
+
+    async fn forward_peer_to_hub(&self, session: &Session, message: Message) -> io::Result<()> {
+        match message {
+            Message::ForwardedMulticastData { .. } => {
+                self.send_to_hub(ClientEvent::OnFederatedMessage(
+                    session.link_id.clone(),
+                    message,
+                ))
+                .await
+            }
+            Message::Error {
+                request_id, reason, ..
+            } => {
+                log::warn!(
+                    "Request {request_id} on federation link to {} failed: {reason}",
+                    self.option.endpoint
+                );
+                Ok(())
+            }
+            message => {
+                log::trace!(
+                    "Ignoring message from federation peer {}: {message:?}",
+                    self.option.endpoint
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn forward_hub_to_peer(
+        &self,
+        session: &mut Session,
+        event: ServerEvent,
+        peer: &mut impl MessageStream,
+    ) -> io::Result<()> {
+        match event {
+            ServerEvent::OnMessage(Message::ForwardedSubscriptionRequest {
+                client_id,
+                topic,
+                count,
+                ..
+            }) => {
+                if count > 0 {
+                    let subscribers = session.subscribers.entry(topic.clone()).or_default();
+                    if subscribers.is_empty() {
+                        log::debug!(
+                            "Subscribing to \"{topic}\" on federation peer {}.",
+                            self.option.endpoint
+                        );
+                        peer.write(&Message::SubscriptionRequest {
+                            topic: topic.clone(),
+                            is_add: true,
+                        })
+                        .await?;
+                    }
+                    subscribers.insert(client_id);
+                } else if let Some(subscribers) = session.subscribers.get_mut(&topic) {
+                    subscribers.remove(&client_id);
+                    if subscribers.is_empty() {
+                        session.subscribers.remove(&topic);
+                        log::debug!(
+                            "Unsubscribing from \"{topic}\" on federation peer {}.",
+                            self.option.endpoint
+                        );
+                        peer.write(&Message::SubscriptionRequest {
+                            topic,
+                            is_add: false,
+                        })
+                        .await?;
+                    }
+                }
+                Ok(())
+            }
+            ServerEvent::OnMessage(Message::Error {
+                request_id, reason, ..
+            }) => {
+                log::warn!(
+                    "Local request {request_id} for federation link to {} failed: {reason}",
+                    self.option.endpoint
+                );
+                Ok(())
+            }
+            ServerEvent::OnMessage(message) => {
+                log::trace!("Ignoring local message for federation link: {message:?}");
+                Ok(())
+            }
+            ServerEvent::OnOverflow(policy, dropped) => {
+                log::warn!(
+                    "Federation link to {} dropped {dropped} local messages by policy {policy}.",
+                    self.option.endpoint
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn send_to_local(&self, session: &mut Session, message: Message) -> io::Result<()> {
+        session.request_count += 1;
+        self.send_to_hub(ClientEvent::OnMessage(
+            session.link_id.clone(),
+            session.request_count,
+            message,
+        ))
+        .await
+    }
+
+    async fn send_to_hub(&self, event: ClientEvent) -> io::Result<()> {
+        self.hub
+            .send(event)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
@@ -1,50 +1,95 @@
 use std::io;
 use std::sync::Arc;
+use std::time::Instant;
 
 use tokio::sync::Mutex;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::Receiver;
 
-use common::messages::Message;
+use common::messages::{DataPacket, ErrorCode, Message, ReplayFrom};
 
 use crate::{
-    authorization::{AuthorizationManager, AuthorizationSpec},
-    clients::ClientManager,
+    admin::AdminRequest,
+    authentication::Identity,
+    authorization::{AuthorizationManager, AuthorizationSpec, Role},
+    caching::CacheManager,
+    clients::{ClientManager, Transport},
     events::{ClientEvent, ServerEvent},
+    journal::JournalManager,
+    metrics::METRICS,
     notifications::NotificationManager,
-    publishing::PublisherManager,
+    outbound::OutboundSender,
+    publishing::{Publisher, PublisherManager},
     subscriptions::SubscriptionManager,
 };
 
 struct HubManager {
+    broker_id: String,
     client_manager: ClientManager,
     subscription_manager: SubscriptionManager,
     notification_manager: NotificationManager,
     publisher_manager: PublisherManager,
+    cache_manager: CacheManager,
+    journal_manager: Option<JournalManager>,
     authorization_manager: AuthorizationManager,
 }
 
 impl HubManager {
-    pub fn new(entitlement_manager: AuthorizationManager) -> Self {
+    pub fn new(
+        broker_id: String,
+        entitlement_manager: AuthorizationManager,
+        cache_manager: CacheManager,
+        journal_manager: Option<JournalManager>,
+    ) -> Self {
         HubManager {
+            broker_id,
             client_manager: ClientManager::new(),
             subscription_manager: SubscriptionManager::new(),
             notification_manager: NotificationManager::new(),
             publisher_manager: PublisherManager::new(),
+            cache_manager,
+            journal_manager,
             authorization_manager: entitlement_manager,
         }
     }
 
     pub async fn handle_event(&mut self, event: ClientEvent) -> io::Result<()> {
         match event {
-            ClientEvent::OnMessage(id, msg) => self.handle_message(&id, msg).await,
-            ClientEvent::OnConnect(id, host, user, server_tx) => {
-                Ok(self.handle_connect(&id, host, user, server_tx))
+            ClientEvent::OnMessage(id, request_id, msg) => {
+                self.handle_request(&id, request_id, msg).await;
+                Ok(())
+            }
+            ClientEvent::OnConnect(id, host, identity, transport, server_tx) => {
+                self.handle_connect(&id, host, identity, transport, server_tx);
+                Ok(())
             }
             ClientEvent::OnClose(id) => self.handle_close(&id).await,
-            ClientEvent::OnReset(specs) => Ok(self.handle_reset(specs)),
+            ClientEvent::OnFederatedMessage(id, msg) => {
+                self.handle_federated_message(&id, msg).await
+            }
+            ClientEvent::OnReset(specs) => {
+                self.handle_reset(specs);
+                Ok(())
+            }
+            ClientEvent::OnAdmin(request) => {
+                self.handle_admin(request);
+                Ok(())
+            }
+            ClientEvent::OnReplayed(id, request_id, result) => {
+                self.handle_replayed(&id, request_id, result).await;
+                Ok(())
+            }
         }
     }
 
+    fn handle_admin(&mut self, request: AdminRequest) {
+        request.reply(
+            &self.client_manager,
+            &self.subscription_manager,
+            &self.notification_manager,
+            &self.publisher_manager,
+        )
+    }
+
     fn handle_reset(&mut self, specs: Vec<AuthorizationSpec>) {
         log::info!("Resetting authorizations.");
         self.authorization_manager.reset(specs);
@@ -54,24 +99,77 @@ impl HubManager {
         &mut self,
         client_id: &str,
         host: String,
-        user: String,
-        server_tx: Sender<ServerEvent>,
+        identity: Identity,
+        transport: Transport,
+        server_tx: OutboundSender,
     ) {
         self.client_manager
-            .handle_connect(client_id, host, user, server_tx)
+            .handle_connect(client_id, host, identity, transport, server_tx)
     }
 
     async fn handle_close(&mut self, client_id: &str) -> io::Result<()> {
+        if let Some(journal_manager) = &mut self.journal_manager {
+            journal_manager.handle_close(client_id);
+        }
         self.client_manager
             .handle_close(
                 client_id,
                 &mut self.subscription_manager,
                 &mut self.notification_manager,
                 &mut self.publisher_manager,
+                &mut self.cache_manager,
+                &self.authorization_manager,
             )
             .await
     }
 
+    /// Handle a message from a client, replying with an error if it fails or
+    /// an acknowledgement if the request expects one.
+    async fn handle_request(&mut self, client_id: &str, request_id: u64, msg: Message) {
+        let is_ack_required = matches!(
+            msg,
+            Message::SubscriptionRequest { .. }
+                | Message::NotificationRequest { .. }
+                | Message::FederationRequest { .. }
+        );
+
+        let result = match msg {
+            Message::ReplaySubscriptionRequest { topic, from } => {
+                match self.handle_replay_request(client_id, request_id, topic, from) {
+                    // Acknowledged when the replay has caught up.
+                    Ok(()) => return,
+                    Err(error) => Err(error),
+                }
+            }
+            msg => self.handle_message(client_id, msg).await,
+        };
+
+        match result {
+            Ok(()) if !is_ack_required => {}
+            result => self.send_reply(client_id, request_id, result),
+        }
+    }
+
+    /// Acknowledge a request, or reply with the error it failed with.
+    fn send_reply(&self, client_id: &str, request_id: u64, result: io::Result<()>) {
+        let reply = match result {
+            Ok(()) => Message::Ack { request_id },
+            Err(error) => {
+                log::debug!("Request {request_id} from client {client_id} failed: {error}");
+                Message::Error {
+                    request_id,
+                    code: ErrorCode::from(error.kind()),
+                    reason: error.to_string(),
+                }
+            }
+        };
+
+        match self.client_manager.get(client_id) {
+            Some(client) => client.send(ServerEvent::OnMessage(reply)),
+            None => log::debug!("Client {client_id} is not known; dropping reply."),
+        }
+    }
+
     async fn handle_message(&mut self, client_id: &str, msg: Message) -> io::Result<()> {
         log::trace!("Received message from client {client_id}: {msg:?}");
 
@@ -80,15 +178,14 @@ impl HubManager {
                 topic,
                 data_packets,
             } => {
-                self.publisher_manager
-                    .send_multicast_data(
-                        client_id,
-                        topic.as_str(),
-                        data_packets,
-                        &self.subscription_manager,
-                        &self.client_manager,
-                        &self.authorization_manager,
-                    )
+                let Some(publisher) = self.client_manager.get(client_id) else {
+                    log::trace!("Publisher {client_id} is not known; skipping.");
+                    return Ok(());
+                };
+                let host = publisher.host.clone();
+                let user = publisher.user.clone();
+                let groups = publisher.groups.clone();
+                self.publish(client_id, &host, &user, &groups, topic, data_packets)
                     .await
             }
             Message::NotificationRequest { pattern, is_add } => {
@@ -99,19 +196,39 @@ impl HubManager {
                         is_add,
                         &self.client_manager,
                         &self.subscription_manager,
+                        &self.authorization_manager,
                     )
                     .await
             }
             Message::SubscriptionRequest { topic, is_add } => {
                 self.subscription_manager
                     .handle_subscription_request(
-                        &client_id,
-                        topic,
+                        client_id,
+                        topic.clone(),
                         is_add,
                         &self.client_manager,
                         &self.notification_manager,
+                        &self.authorization_manager,
                     )
-                    .await
+                    .await?;
+                if is_add {
+                    self.cache_manager.send_images(
+                        client_id,
+                        topic.as_str(),
+                        &self.client_manager,
+                        &self.authorization_manager,
+                    );
+                }
+                Ok(())
+            }
+            Message::FederationRequest { broker_id } => {
+                if broker_id == self.broker_id {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "a broker cannot federate with itself",
+                    ));
+                }
+                self.client_manager.handle_federation(client_id, broker_id)
             }
             Message::UnicastData {
                 client_id: destination_id,
@@ -129,9 +246,146 @@ impl HubManager {
                     )
                     .await
             }
-            _ => Err(io::Error::new(io::ErrorKind::Other, "unhandled message")),
+            msg => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unhandled message {:?}", msg.message_type()),
+            )),
         }
     }
+
+    /// Start a replay subscription, which is made once the journaled data has
+    /// been sent.
+    fn handle_replay_request(
+        &mut self,
+        client_id: &str,
+        request_id: u64,
+        topic: String,
+        from: ReplayFrom,
+    ) -> io::Result<()> {
+        let Some(journal_manager) = &mut self.journal_manager else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the journal is not enabled",
+            ));
+        };
+        let Some(subscriber) = self.client_manager.get(client_id) else {
+            log::debug!("Subscriber {client_id} is not known; skipping.");
+            return Ok(());
+        };
+        if !self.authorization_manager.is_authorized(
+            &subscriber.user,
+            &subscriber.groups,
+            &topic,
+            Role::Subscriber,
+        ) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("not authorized to subscribe to \"{topic}\""),
+            ));
+        }
+        journal_manager.handle_replay_request(
+            client_id,
+            request_id,
+            topic,
+            from,
+            &self.client_manager,
+            &self.authorization_manager,
+        )
+    }
+
+    async fn handle_replayed(&mut self, client_id: &str, request_id: u64, result: io::Result<u64>) {
+        let Some(journal_manager) = &mut self.journal_manager else {
+            return;
+        };
+        let Some(result) = journal_manager.handle_replayed(
+            client_id,
+            request_id,
+            result,
+            &self.client_manager,
+            &self.authorization_manager,
+        ) else {
+            return;
+        };
+
+        // Nothing was journaled since the replay started, so the live data
+        // follows on from it.
+        let result = match result {
+            Ok(topic) => {
+                self.subscription_manager
+                    .handle_subscription_request(
+                        client_id,
+                        topic,
+                        true,
+                        &self.client_manager,
+                        &self.notification_manager,
+                        &self.authorization_manager,
+                    )
+                    .await
+            }
+            Err(error) => Err(error),
+        };
+        self.send_reply(client_id, request_id, result);
+    }
+
+    /// Route data received by a federation link from the peer broker.
+    async fn handle_federated_message(&mut self, link_id: &str, msg: Message) -> io::Result<()> {
+        match msg {
+            Message::ForwardedMulticastData {
+                host,
+                user,
+                topic,
+                data_packets,
+            } => {
+                // The groups of a user of the peer broker are not known here.
+                self.publish(link_id, &host, &user, &[], topic, data_packets)
+                    .await
+            }
+            msg => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unhandled federated message {:?}", msg.message_type()),
+            )),
+        }
+    }
+
+    async fn publish(
+        &mut self,
+        publisher_id: &str,
+        host: &str,
+        user: &str,
+        groups: &[String],
+        topic: String,
+        data_packets: Vec<DataPacket>,
+    ) -> io::Result<()> {
+        self.cache_manager
+            .handle_multicast_data(host, user, groups, topic.as_str(), &data_packets);
+        if let Some(journal_manager) = &mut self.journal_manager {
+            // Live data is still routed when the journal cannot be written.
+            if let Err(error) = journal_manager.handle_multicast_data(
+                host,
+                user,
+                groups,
+                topic.as_str(),
+                &data_packets,
+            ) {
+                log::error!("Failed to journal topic \"{topic}\": {error}");
+            }
+        }
+        self.publisher_manager
+            .send_multicast_data(
+                &Publisher {
+                    client_id: publisher_id,
+                    host,
+                    user,
+                    groups,
+                },
+                topic.as_str(),
+                data_packets,
+                &self.subscription_manager,
+                &self.client_manager,
+                &self.authorization_manager,
+            )
+            .await
+    }
 }
 
 pub struct Hub {
@@ -139,16 +393,34 @@ pub struct Hub {
 }
 
 impl Hub {
-    pub fn new(entitlement_manager: AuthorizationManager) -> Self {
+    pub fn new(
+        broker_id: String,
+        entitlement_manager: AuthorizationManager,
+        cache_manager: CacheManager,
+        journal_manager: Option<JournalManager>,
+    ) -> Self {
         Hub {
-            state: Arc::new(Mutex::new(HubManager::new(entitlement_manager))),
+            state: Arc::new(Mutex::new(HubManager::new(
+                broker_id,
+                entitlement_manager,
+                cache_manager,
+                journal_manager,
+            ))),
         }
     }
     pub async fn run(
+        broker_id: String,
         authorizations: Vec<AuthorizationSpec>,
+        cache_patterns: Vec<String>,
+        journal_manager: Option<JournalManager>,
         server_rx: Receiver<ClientEvent>,
     ) -> io::Result<()> {
-        let mut hub_runner = Self::new(AuthorizationManager::new(authorizations));
+        let mut hub_runner = Self::new(
+            broker_id,
+            AuthorizationManager::new(authorizations),
+            CacheManager::new(&cache_patterns),
+            journal_manager,
+        );
         hub_runner.start(server_rx).await
     }
 
@@ -157,7 +429,12 @@ impl Hub {
             let msg = server_rx.recv().await.unwrap();
             let state = self.state.clone();
             let mut state = state.lock().await;
-            state.handle_event(msg).await?
+            let start = Instant::now();
+            if let Err(error) = state.handle_event(msg).await {
+                // A failure handling one client must not stop the hub.
+                log::error!("Failed to handle event: {error}");
+            }
+            METRICS.hub_event_handled(start.elapsed().as_secs_f64());
         }
     }
 }
@@ -1,23 +1,39 @@
 use std::io;
-use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::sync::RwLock;
-use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
 
 use uuid::Uuid;
 
 use common::MessageStream;
-use common::messages::Message;
+use common::messages::{ErrorCode, Message};
 
-use crate::authentication::AuthenticationManager;
+use crate::authentication::{AuthenticationManager, Identity, PeerInfo};
+use crate::clients::Transport;
 use crate::events::{ClientEvent, ServerEvent};
+use crate::limits::ConnectionPermit;
+use crate::metrics::METRICS;
+use crate::options::{HeartbeatOption, OutboundQueueOption};
+use crate::outbound::{self, OverflowPolicy};
+
+/// The settings a listener gives each connection it admits, with the permit
+/// that counts the connection against the limits of the listener.
+pub struct ListenerSettings {
+    pub permit: ConnectionPermit,
+    pub heartbeat: HeartbeatOption,
+    pub outbound_queue: OutboundQueueOption,
+}
 
 #[derive(Debug)]
 pub struct Interactor {
     pub id: String,
     heartbeat_count: u64,
+    // The number of messages received from the client. This is used as the
+    // request id when replying with an error or acknowledgement.
+    request_count: u64,
 }
 
 impl Interactor {
@@ -25,87 +41,153 @@ impl Interactor {
         Interactor {
             id: Uuid::new_v4().into(),
             heartbeat_count: 0,
+            request_count: 0,
         }
     }
 
-    pub async fn run<'a>(
+    pub async fn run(
         &mut self,
         stream: &mut impl MessageStream,
-        addr: SocketAddr,
+        peer: PeerInfo,
+        transport: Transport,
         hub: Sender<ClientEvent>,
         authentication_manager: Arc<RwLock<AuthenticationManager>>,
-        heartbeat_seconds: u64,
+        settings: ListenerSettings,
     ) -> io::Result<()> {
-        let (tx, mut rx) = mpsc::channel::<ServerEvent>(32);
+        let ListenerSettings {
+            mut permit,
+            heartbeat,
+            outbound_queue,
+        } = settings;
+        let (tx, mut rx) = outbound::channel(outbound_queue.capacity, outbound_queue.policy);
 
-        let user = self.authenticate(stream, authentication_manager).await?;
-
-        let host = match addr {
-            SocketAddr::V4(v4) => v4.ip().to_string(),
-            SocketAddr::V6(v6) => v6.ip().to_string(),
-        };
+        let identity = self
+            .authenticate(stream, &peer, authentication_manager, permit.deadline())
+            .await?;
+        permit.authenticated();
 
         // Inform the client
-        hub.send(ClientEvent::OnConnect(self.id.clone(), host, user, tx))
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        hub.send(ClientEvent::OnConnect(
+            self.id.clone(),
+            peer.host,
+            identity,
+            transport,
+            tx,
+        ))
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
-        let now = tokio::time::Instant::now();
-        let interval = Duration::from_secs(heartbeat_seconds);
+        let now = Instant::now();
+        let interval = Duration::from_secs(heartbeat.seconds);
         let mut deadline = now + interval;
+        // Any message from the client shows it is alive.
+        let mut last_received = now;
 
         loop {
             tokio::select! {
                 // forward client to hub
                 result = stream.read() => {
+                    last_received = Instant::now();
                     self.forward_client_to_hub(result, &hub).await
                 }
                 // forward hub to client
                 result = rx.recv() => {
-                    self.forward_hub_to_client(result, stream).await
+                    self.forward_hub_to_client(result, stream, &hub).await
                 }
                 _ = tokio::time::sleep_until(deadline) => {
                     deadline += interval;
-                    self.send_heartbeat(stream).await
+                    if heartbeat.max_missed > 0
+                        && last_received.elapsed() >= interval * heartbeat.max_missed
+                    {
+                        self.close_unresponsive(&hub).await
+                    } else {
+                        self.send_heartbeat(stream).await
+                    }
                 }
             }?
         }
     }
 
     async fn authenticate(
-        &self,
+        &mut self,
         stream: &mut impl MessageStream,
+        peer: &PeerInfo,
         authentication_manager: Arc<RwLock<AuthenticationManager>>,
-    ) -> io::Result<String> {
+        deadline: Instant,
+    ) -> io::Result<Identity> {
         // If successful, the authentication manager resolves the user for
         // authorization.
-        // If unsuccessful an error will be returned and propagated up until
-        // the connection is closed.
-        let user = authentication_manager
-            .read() // Acquire the lock.
-            .await
-            .authenticate(stream)
-            .await?;
+        // If unsuccessful the client is told, then the error is propagated
+        // up until the connection is closed.
+        let result = tokio::time::timeout_at(deadline, async {
+            authentication_manager
+                .read() // Acquire the lock.
+                .await
+                .authenticate(stream, peer)
+                .await
+        })
+        .await;
+        let result = match result {
+            Ok(Ok(identity)) => Ok(identity),
+            // The lock is released before waiting to answer a failure.
+            Ok(Err(failure)) => Err(failure.wait().await),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "authentication timed out",
+            )),
+        };
+
+        // The authentication request is the first request.
+        self.request_count = 1;
+
+        let identity = match result {
+            Ok(identity) => identity,
+            Err(error) => {
+                let response = Message::Error {
+                    request_id: self.request_count,
+                    code: ErrorCode::AuthenticationFailed,
+                    reason: error.to_string(),
+                };
+                METRICS.message_sent(&response);
+                // The connection may already be closed.
+                if let Err(write_error) = stream.write(&response).await {
+                    log::trace!("Failed to send authentication error: {write_error}");
+                }
+                return Err(error);
+            }
+        };
 
         // The id is returned to the client.
         let response = Message::AuthenticationResponse {
             client_id: self.id.clone(),
         };
+        METRICS.message_sent(&response);
         stream.write(&response).await?;
 
-        Ok(user)
+        Ok(identity)
     }
 
     async fn forward_client_to_hub(
-        &self,
+        &mut self,
         result: Result<Message, std::io::Error>,
         hub: &Sender<ClientEvent>,
     ) -> io::Result<()> {
         match result {
-            Ok(message) => hub
-                .send(ClientEvent::OnMessage(self.id.clone(), message))
+            Ok(message) => {
+                METRICS.message_received(&message);
+                self.request_count += 1;
+                if let Message::Heartbeat { count } = message {
+                    log::trace!("Received heartbeat {count} from client {}.", self.id);
+                    return Ok(());
+                }
+                hub.send(ClientEvent::OnMessage(
+                    self.id.clone(),
+                    self.request_count,
+                    message,
+                ))
                 .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }
             Err(error) => {
                 log::trace!("Client error: {error}");
                 hub.send(ClientEvent::OnClose(self.id.clone()))
@@ -120,11 +202,45 @@ impl Interactor {
         &self,
         event: Option<ServerEvent>,
         stream: &mut impl MessageStream,
+        hub: &Sender<ClientEvent>,
     ) -> io::Result<()> {
-        let event = event.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "missing event"))?;
+        let Some(event) = event else {
+            // The hub has closed the queue, so the client must be removed.
+            log::debug!("Outbound queue for client {} closed.", self.id);
+            hub.send(ClientEvent::OnClose(self.id.clone()))
+                .await
+                .map_err(|send_error| io::Error::new(io::ErrorKind::Other, send_error))?;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "outbound queue closed",
+            ));
+        };
+
         match event {
             ServerEvent::OnMessage(msg) => {
                 log::trace!("Sending message to client {}: {:?}", self.id, msg);
+                METRICS.message_sent(&msg);
+                stream.write(&msg).await?;
+            }
+            ServerEvent::OnOverflow(policy, dropped) => {
+                let reason = match policy {
+                    OverflowPolicy::Disconnect => {
+                        format!(
+                            "outbound queue full; disconnecting after {dropped} dropped messages"
+                        )
+                    }
+                    _ => format!(
+                        "outbound queue full; {dropped} messages dropped by policy {policy}"
+                    ),
+                };
+                log::trace!("Sending overflow to client {}: {}", self.id, reason);
+                // Overflow is not a reply to a request, so has no request id.
+                let msg = Message::Error {
+                    request_id: 0,
+                    code: ErrorCode::SlowConsumer,
+                    reason,
+                };
+                METRICS.message_sent(&msg);
                 stream.write(&msg).await?;
             }
         }
@@ -132,12 +248,146 @@ impl Interactor {
         Ok(())
     }
 
+    async fn close_unresponsive(&self, hub: &Sender<ClientEvent>) -> io::Result<()> {
+        log::info!("Closing client {} after missed heartbeats.", self.id);
+        hub.send(ClientEvent::OnClose(self.id.clone()))
+            .await
+            .map_err(|send_error| io::Error::new(io::ErrorKind::Other, send_error))?;
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "client missed heartbeats",
+        ))
+    }
+
     async fn send_heartbeat(&mut self, stream: &mut impl MessageStream) -> io::Result<()> {
         log::trace!("Sending heartbeat to client {}.", self.id);
         let message = Message::Heartbeat {
             count: self.heartbeat_count,
         };
         self.heartbeat_count += 1;
+        METRICS.message_sent(&message);
         stream.write(&message).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use common::MessageSocket;
+    use tokio::sync::mpsc;
+
+    use crate::authentication::LockoutManager;
+    use crate::limits::ConnectionLimiter;
+    use crate::options::{AuthenticationOption, ConnectionLimitOption, OutboundQueueOption};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn should_close_clients_that_miss_heartbeats() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let mut client = MessageSocket::new(client_stream);
+        client
+            .write(&Message::AuthenticationRequest {
+                method: "none".into(),
+                credentials: Vec::new(),
+            })
+            .await
+            .unwrap();
+
+        let (hub, mut hub_rx) = mpsc::channel(10);
+        let authentication_manager = Arc::new(RwLock::new(
+            AuthenticationManager::new(&[AuthenticationOption::None], LockoutManager::default())
+                .unwrap(),
+        ));
+
+        let mut interactor = Interactor::new();
+        let started = Instant::now();
+        let error = interactor
+            .run(
+                &mut MessageSocket::new(server_stream),
+                PeerInfo {
+                    host: "127.0.0.1".into(),
+                    certificates: Vec::new(),
+                    uid: None,
+                },
+                Transport::Socket,
+                hub,
+                authentication_manager,
+                ListenerSettings {
+                    permit: ConnectionLimiter::new(ConnectionLimitOption::default())
+                        .admit(None)
+                        .unwrap(),
+                    heartbeat: HeartbeatOption {
+                        seconds: 10,
+                        max_missed: 2,
+                    },
+                    outbound_queue: OutboundQueueOption {
+                        capacity: 10,
+                        policy: OverflowPolicy::Disconnect,
+                    },
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(started.elapsed(), Duration::from_secs(20));
+
+        let Some(ClientEvent::OnConnect(..)) = hub_rx.recv().await else {
+            panic!("expected connect");
+        };
+        let Some(ClientEvent::OnClose(id)) = hub_rx.recv().await else {
+            panic!("expected close");
+        };
+        assert_eq!(id, interactor.id);
+
+        // The client was sent a heartbeat before it was closed.
+        let Message::AuthenticationResponse { .. } = client.read().await.unwrap() else {
+            panic!("expected authentication response");
+        };
+        let Message::Heartbeat { count: 0 } = client.read().await.unwrap() else {
+            panic!("expected heartbeat");
+        };
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_close_clients_that_do_not_authenticate() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let mut client = MessageSocket::new(client_stream);
+
+        let (hub, _hub_rx) = mpsc::channel(10);
+        let authentication_manager = Arc::new(RwLock::new(
+            AuthenticationManager::new(&[AuthenticationOption::None], LockoutManager::default())
+                .unwrap(),
+        ));
+        let limiter = ConnectionLimiter::new(ConnectionLimitOption {
+            authentication_timeout_seconds: 5,
+            max_unauthenticated: 1,
+            max_per_host: 0,
+        });
+
+        let started = Instant::now();
+        let error = Interactor::new()
+            .run(
+                &mut MessageSocket::new(server_stream),
+                PeerInfo::default(),
+                Transport::Socket,
+                hub,
+                authentication_manager,
+                ListenerSettings {
+                    permit: limiter.admit(None).unwrap(),
+                    heartbeat: HeartbeatOption::default(),
+                    outbound_queue: OutboundQueueOption::default(),
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+
+        let Message::Error { reason, .. } = client.read().await.unwrap() else {
+            panic!("expected error");
+        };
+        assert_eq!(reason, "authentication timed out");
+        // The connection is no longer counted.
+        assert!(limiter.admit(None).is_ok());
+    }
+}
@@ -0,0 +1,956 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{mpsc, watch};
+use tokio::task::AbortHandle;
+use wildmatch::WildMatch;
+
+use common::{
+    Serializable,
+    messages::{DataPacket, Message, ReplayFrom},
+};
+
+use crate::{
+    authorization::{AuthorizationManager, Role},
+    clients::ClientManager,
+    events::{ClientEvent, ServerEvent},
+    options::JournalOption,
+    outbound::{Delivery, WaitingSender},
+    publishing::{get_authorized_data, get_authorized_entitlements},
+};
+
+const SEGMENT_EXTENSION: &str = "journal";
+
+/// The header added to replayed data packets holding the journal sequence
+/// number, so a client can resume a replay.
+pub const SEQUENCE_HEADER: &[u8] = b"journal-sequence";
+
+/// The most entries waiting to be written. Data published while the writer is
+/// this far behind is not journaled.
+const WRITER_QUEUE_CAPACITY: usize = 65536;
+
+/// When journal segments are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JournalSync {
+    /// Leave it to the operating system.
+    Never,
+    /// When a segment is full and the next one is started.
+    Segment,
+    /// After every entry.
+    Always,
+}
+
+impl FromStr for JournalSync {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(JournalSync::Never),
+            "segment" => Ok(JournalSync::Segment),
+            "always" => Ok(JournalSync::Always),
+            _ => Err(format!("invalid journal sync {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for JournalSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalSync::Never => write!(f, "never"),
+            JournalSync::Segment => write!(f, "segment"),
+            JournalSync::Always => write!(f, "always"),
+        }
+    }
+}
+
+/// A multicast message as recorded in the journal.
+#[derive(Debug, PartialEq)]
+struct JournalEntry {
+    sequence: u64,
+    /// Milliseconds since the Unix epoch.
+    timestamp: u64,
+    host: String,
+    user: String,
+    /// The groups of the publisher's session, for its entitlements.
+    groups: Vec<String>,
+    topic: String,
+    data_packets: Vec<DataPacket>,
+}
+
+impl JournalEntry {
+    fn is_after(&self, from: &ReplayFrom) -> bool {
+        match from {
+            ReplayFrom::Sequence(sequence) => self.sequence >= *sequence,
+            ReplayFrom::Timestamp(timestamp) => self.timestamp >= *timestamp,
+        }
+    }
+}
+
+impl Serializable for JournalEntry {
+    fn serialize(&self, writer: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.sequence.serialize(writer)?;
+        self.timestamp.serialize(writer)?;
+        self.host.serialize(writer)?;
+        self.user.serialize(writer)?;
+        self.groups.serialize(writer)?;
+        self.topic.serialize(writer)?;
+        self.data_packets.serialize(writer)?;
+        Ok(())
+    }
+
+    fn deserialize(reader: &mut Cursor<Vec<u8>>) -> io::Result<JournalEntry> {
+        Ok(JournalEntry {
+            sequence: u64::deserialize(reader)?,
+            timestamp: u64::deserialize(reader)?,
+            host: String::deserialize(reader)?,
+            user: String::deserialize(reader)?,
+            groups: Vec::<String>::deserialize(reader)?,
+            topic: String::deserialize(reader)?,
+            data_packets: Vec::<DataPacket>::deserialize(reader)?,
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.sequence.size()
+            + self.timestamp.size()
+            + self.host.size()
+            + self.user.size()
+            + self.groups.size()
+            + self.topic.size()
+            + self.data_packets.size()
+    }
+}
+
+/// The first sequence number of each segment on disk, in order. Shared by the
+/// writer and the replays.
+type Segments = Arc<Mutex<Vec<u64>>>;
+
+/// The segment currently being written.
+struct Segment {
+    file: File,
+    size: u64,
+}
+
+/// Writes entries to the segment files on its own thread, so the hub never
+/// waits for the disk.
+///
+/// Each segment file is named after the sequence number of its first entry.
+/// An entry is written as its length followed by the serialized entry.
+struct JournalWriter {
+    directory: PathBuf,
+    segment_size: u64,
+    max_segments: Option<usize>,
+    sync: JournalSync,
+    segments: Segments,
+    current: Option<Segment>,
+    /// The sequence number after the last entry written.
+    written: watch::Sender<u64>,
+}
+
+impl JournalWriter {
+    fn run(mut self, mut entries: mpsc::Receiver<JournalEntry>) {
+        while let Some(entry) = entries.blocking_recv() {
+            if let Err(error) = self.append(&entry) {
+                log::error!(
+                    "Failed to write journal sequence {}: {error}",
+                    entry.sequence
+                );
+            }
+            // Replays wait for this, so it moves on even when an entry could
+            // not be written.
+            self.written.send_replace(entry.sequence + 1);
+        }
+
+        if let Some(segment) = &self.current
+            && self.sync != JournalSync::Never
+            && let Err(error) = segment.file.sync_all()
+        {
+            log::error!("Failed to sync the journal: {error}");
+        }
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut cursor = Cursor::new(Vec::with_capacity(4 + entry.size()));
+        (entry.size() as u32).serialize(&mut cursor)?;
+        entry.serialize(&mut cursor)?;
+        let bytes = cursor.into_inner();
+
+        let is_full = match &self.current {
+            Some(segment) => {
+                segment.size > 0 && segment.size + bytes.len() as u64 > self.segment_size
+            }
+            None => true,
+        };
+        if is_full {
+            self.start_segment(entry.sequence)?;
+        }
+
+        let segment = self.current.as_mut().unwrap();
+        segment.file.write_all(&bytes)?;
+        segment.size += bytes.len() as u64;
+        if self.sync == JournalSync::Always {
+            segment.file.sync_data()?;
+        }
+
+        log::trace!(
+            "Journaled topic \"{}\" at sequence {}.",
+            entry.topic,
+            entry.sequence
+        );
+
+        Ok(())
+    }
+
+    fn start_segment(&mut self, first_sequence: u64) -> io::Result<()> {
+        if let Some(segment) = &self.current
+            && self.sync != JournalSync::Never
+        {
+            segment.file.sync_all()?;
+        }
+
+        let path = segment_path(&self.directory, first_sequence);
+        log::debug!("Starting journal segment {}.", path.display());
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)?;
+        if self.sync != JournalSync::Never {
+            // Make sure the new file is still there after a crash.
+            File::open(&self.directory)?.sync_all()?;
+        }
+        self.current = Some(Segment { file, size: 0 });
+        self.segments.lock().unwrap().push(first_sequence);
+
+        self.remove_old_segments();
+
+        Ok(())
+    }
+
+    /// Remove the oldest segments beyond the retention limit.
+    fn remove_old_segments(&self) {
+        let Some(max_segments) = self.max_segments else {
+            return;
+        };
+
+        let removed: Vec<u64> = {
+            let mut segments = self.segments.lock().unwrap();
+            let count = segments.len().saturating_sub(max_segments);
+            segments.drain(..count).collect()
+        };
+
+        for first_sequence in removed {
+            let path = segment_path(&self.directory, first_sequence);
+            log::info!("Removing journal segment {}.", path.display());
+            if let Err(error) = fs::remove_file(&path) {
+                log::error!(
+                    "Failed to remove journal segment {}: {error}",
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+/// Reads the segment files for replays.
+#[derive(Clone)]
+struct JournalReader {
+    directory: PathBuf,
+    segments: Segments,
+    written: watch::Receiver<u64>,
+}
+
+/// Sends the journaled entries between two sequence numbers to a subscriber,
+/// away from the hub. Each entry waits for room in the subscriber's queue, so
+/// a replay larger than the queue is not dropped.
+struct ReplayTask {
+    reader: JournalReader,
+    pattern: WildMatch,
+    from: ReplayFrom,
+    /// The first sequence number to consider.
+    start: u64,
+    /// The sequence number to stop before.
+    end: u64,
+    user: String,
+    groups: Vec<String>,
+    authorization_manager: AuthorizationManager,
+    tx: WaitingSender,
+}
+
+impl ReplayTask {
+    /// Send the entries, returning the sequence number the replay stopped
+    /// before.
+    async fn run(mut self) -> io::Result<u64> {
+        // The writer may not have caught up with the hub.
+        self.reader
+            .written
+            .wait_for(|written| *written >= self.end)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "the journal writer has stopped"))?;
+
+        let segments = self.reader.segments.lock().unwrap().clone();
+        let mut count = 0;
+
+        for (index, first_sequence) in segments.iter().enumerate() {
+            if *first_sequence >= self.end {
+                break;
+            }
+            // Skip segments which end before the first sequence required.
+            if segments
+                .get(index + 1)
+                .is_some_and(|next_sequence| *next_sequence <= self.start)
+            {
+                continue;
+            }
+
+            // Segments are read a whole one at a time, off the runtime.
+            let path = segment_path(&self.reader.directory, *first_sequence);
+            let entries = match tokio::task::spawn_blocking(move || read_segment(&path)).await? {
+                Ok((entries, _)) => entries,
+                // Removed by the retention limit since the list was taken.
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+
+            for entry in entries {
+                if entry.sequence < self.start || entry.sequence >= self.end {
+                    continue;
+                }
+                let Some(event) = self.authorized_event(entry) else {
+                    continue;
+                };
+                if self.tx.send(event).await == Delivery::Closed {
+                    log::debug!("Subscriber closed during replay; stopping.");
+                    return Ok(self.end);
+                }
+                count += 1;
+            }
+        }
+
+        // The subscription is made with nothing of the replay left queued, so
+        // the live data has the whole queue.
+        self.tx.flush().await;
+
+        log::debug!(
+            "Replayed {count} messages to {} up to sequence {}.",
+            self.user,
+            self.end
+        );
+
+        Ok(self.end)
+    }
+
+    /// The entry as it would be sent to the subscriber, if there is anything
+    /// the subscriber may see.
+    fn authorized_event(&self, entry: JournalEntry) -> Option<ServerEvent> {
+        if !entry.is_after(&self.from) || !self.pattern.matches(&entry.topic) {
+            return None;
+        }
+
+        // The data is filtered exactly as if it had just been published.
+        let publisher_entitlements = self.authorization_manager.entitlements(
+            &entry.user,
+            &entry.groups,
+            &entry.topic,
+            Role::Publisher,
+        );
+        let subscriber_entitlements = self.authorization_manager.entitlements(
+            &self.user,
+            &self.groups,
+            &entry.topic,
+            Role::Subscriber,
+        );
+        let Some(entitlements) =
+            get_authorized_entitlements(&publisher_entitlements, &subscriber_entitlements)
+        else {
+            log::trace!(
+                "Publisher {} is not authorized to replay topic \"{}\" to subscriber {}; skipping.",
+                entry.user,
+                entry.topic,
+                self.user,
+            );
+            return None;
+        };
+
+        let mut auth_data_packets = get_authorized_data(&entry.data_packets, &entitlements);
+        if auth_data_packets.is_empty() {
+            return None;
+        }
+
+        for data_packet in &mut auth_data_packets {
+            data_packet.headers.insert(
+                SEQUENCE_HEADER.to_vec(),
+                entry.sequence.to_string().into_bytes(),
+            );
+        }
+
+        Some(ServerEvent::OnMessage(Message::ForwardedMulticastData {
+            host: entry.host,
+            user: entry.user,
+            topic: entry.topic,
+            data_packets: auth_data_packets,
+        }))
+    }
+}
+
+/// What a replay request asked for.
+struct Replay {
+    pattern: String,
+    from: ReplayFrom,
+}
+
+/// Records multicast data for the configured topic patterns in append only
+/// segment files, so it can be replayed to subscribers.
+///
+/// The hub numbers the entries and hands them to the writer thread. Replays
+/// run as tasks which read the segments, and report back to the hub with
+/// `ClientEvent::OnReplayed`. Data journaled while a replay runs is sent by a
+/// further replay, until one finds nothing new and the subscription is made.
+pub struct JournalManager {
+    patterns: Vec<WildMatch>,
+    next_sequence: u64,
+    writer_tx: mpsc::Sender<JournalEntry>,
+    reader: JournalReader,
+    hub_tx: mpsc::Sender<ClientEvent>,
+    /// The replays which have not yet caught up with the journal, and the
+    /// tasks sending them, by client and request id.
+    replays: HashMap<(String, u64), (Replay, AbortHandle)>,
+}
+
+impl JournalManager {
+    pub fn open(
+        option: &JournalOption,
+        hub_tx: mpsc::Sender<ClientEvent>,
+    ) -> io::Result<JournalManager> {
+        fs::create_dir_all(&option.directory)?;
+
+        let segments = list_segments(&option.directory)?;
+
+        let mut next_sequence = 1;
+        let mut current = None;
+        if let Some(first_sequence) = segments.last() {
+            let path = segment_path(&option.directory, *first_sequence);
+            let (entries, size) = read_segment(&path)?;
+
+            let file = OpenOptions::new().append(true).open(&path)?;
+            if file.metadata()?.len() > size {
+                log::warn!(
+                    "Truncating incomplete entry at the end of journal segment {}.",
+                    path.display()
+                );
+                file.set_len(size)?;
+            }
+            current = Some(Segment { file, size });
+
+            next_sequence = match entries.last() {
+                Some(entry) => entry.sequence + 1,
+                None => *first_sequence,
+            };
+        }
+
+        let segments = Arc::new(Mutex::new(segments));
+        let (written_tx, written_rx) = watch::channel(next_sequence);
+
+        let writer = JournalWriter {
+            directory: option.directory.clone(),
+            segment_size: option.segment_size,
+            max_segments: option.max_segments,
+            sync: option.sync,
+            segments: segments.clone(),
+            current,
+            written: written_tx,
+        };
+        // The limit may have been lowered since the journal was last open.
+        writer.remove_old_segments();
+
+        let (writer_tx, writer_rx) = mpsc::channel(WRITER_QUEUE_CAPACITY);
+        thread::Builder::new()
+            .name("journal-writer".into())
+            .spawn(move || writer.run(writer_rx))?;
+
+        log::info!(
+            "Opened journal in {} at sequence {}.",
+            option.directory.display(),
+            next_sequence
+        );
+
+        Ok(JournalManager {
+            patterns: option
+                .patterns
+                .iter()
+                .map(|pattern| WildMatch::new(pattern))
+                .collect(),
+            next_sequence,
+            writer_tx,
+            reader: JournalReader {
+                directory: option.directory.clone(),
+                segments,
+                written: written_rx,
+            },
+            hub_tx,
+            replays: HashMap::new(),
+        })
+    }
+
+    pub fn is_journaled(&self, topic: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(topic))
+    }
+
+    /// Queue the data to be written to the journal, if the topic is
+    /// journaled.
+    pub fn handle_multicast_data(
+        &mut self,
+        host: &str,
+        user: &str,
+        groups: &[String],
+        topic: &str,
+        data_packets: &[DataPacket],
+    ) -> io::Result<()> {
+        if !self.is_journaled(topic) {
+            return Ok(());
+        }
+
+        let entry = JournalEntry {
+            sequence: self.next_sequence,
+            timestamp: now_millis(),
+            host: host.to_string(),
+            user: user.to_string(),
+            groups: groups.to_vec(),
+            topic: topic.to_string(),
+            data_packets: data_packets.to_vec(),
+        };
+        // The sequence is only used once the entry is queued, so there are no
+        // gaps for a replay to wait on.
+        self.writer_tx
+            .try_send(entry)
+            .map_err(|error| match error {
+                mpsc::error::TrySendError::Full(_) => {
+                    io::Error::new(io::ErrorKind::Other, "the journal writer is behind")
+                }
+                mpsc::error::TrySendError::Closed(_) => {
+                    io::Error::new(io::ErrorKind::Other, "the journal writer has stopped")
+                }
+            })?;
+        self.next_sequence += 1;
+
+        Ok(())
+    }
+
+    /// Start replaying the journaled data for topics matching the pattern,
+    /// from a sequence number or time. The subscription is made once the
+    /// replay has caught up.
+    pub fn handle_replay_request(
+        &mut self,
+        subscriber_id: &str,
+        request_id: u64,
+        pattern: String,
+        from: ReplayFrom,
+        client_manager: &ClientManager,
+        authorization_manager: &AuthorizationManager,
+    ) -> io::Result<()> {
+        let start = match from {
+            ReplayFrom::Sequence(sequence) => sequence,
+            ReplayFrom::Timestamp(_) => 0,
+        };
+        self.start_replay(
+            subscriber_id,
+            request_id,
+            Replay { pattern, from },
+            start,
+            client_manager,
+            authorization_manager,
+        )
+    }
+
+    /// Handle a replay task finishing. Returns the pattern to subscribe to
+    /// when the replay has caught up, or nothing while it continues or if it
+    /// is no longer wanted.
+    pub fn handle_replayed(
+        &mut self,
+        subscriber_id: &str,
+        request_id: u64,
+        result: io::Result<u64>,
+        client_manager: &ClientManager,
+        authorization_manager: &AuthorizationManager,
+    ) -> Option<io::Result<String>> {
+        let (replay, _) = self
+            .replays
+            .remove(&(subscriber_id.to_string(), request_id))?;
+
+        let end = match result {
+            Ok(end) => end,
+            Err(error) => return Some(Err(error)),
+        };
+        if end == self.next_sequence {
+            return Some(Ok(replay.pattern));
+        }
+
+        // More has been journaled since the replay started.
+        match self.start_replay(
+            subscriber_id,
+            request_id,
+            replay,
+            end,
+            client_manager,
+            authorization_manager,
+        ) {
+            Ok(()) => None,
+            Err(error) => Some(Err(error)),
+        }
+    }
+
+    /// Stop the replays to a client which has gone away.
+    pub fn handle_close(&mut self, client_id: &str) {
+        self.replays.retain(|(subscriber_id, _), (_, task)| {
+            if subscriber_id != client_id {
+                return true;
+            }
+            task.abort();
+            false
+        });
+    }
+
+    fn start_replay(
+        &mut self,
+        subscriber_id: &str,
+        request_id: u64,
+        replay: Replay,
+        start: u64,
+        client_manager: &ClientManager,
+        authorization_manager: &AuthorizationManager,
+    ) -> io::Result<()> {
+        let subscriber = client_manager.get(subscriber_id).ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("unknown client {subscriber_id}"),
+        ))?;
+
+        let task = ReplayTask {
+            reader: self.reader.clone(),
+            pattern: WildMatch::new(&replay.pattern),
+            from: replay.from,
+            start,
+            end: self.next_sequence,
+            user: subscriber.user.clone(),
+            groups: subscriber.groups.clone(),
+            // Authorizations reset during the replay apply to the next one.
+            authorization_manager: authorization_manager.snapshot(),
+            tx: subscriber.tx.waiting(),
+        };
+
+        let hub_tx = self.hub_tx.clone();
+        let client_id = subscriber_id.to_string();
+        let handle = tokio::spawn(async move {
+            let result = task.run().await;
+            if hub_tx
+                .send(ClientEvent::OnReplayed(client_id, request_id, result))
+                .await
+                .is_err()
+            {
+                log::debug!("The hub has stopped; dropping replay result.");
+            }
+        });
+
+        if let Some((_, replaced)) = self.replays.insert(
+            (subscriber_id.to_string(), request_id),
+            (replay, handle.abort_handle()),
+        ) {
+            replaced.abort();
+        }
+
+        Ok(())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn segment_path(directory: &Path, first_sequence: u64) -> PathBuf {
+    directory.join(format!("{first_sequence:020}.{SEGMENT_EXTENSION}"))
+}
+
+/// Find the first sequence numbers of the segments in the directory, in order.
+fn list_segments(directory: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for dir_entry in fs::read_dir(directory)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        let Some(first_sequence) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        else {
+            log::warn!("Ignoring unexpected journal file {}.", path.display());
+            continue;
+        };
+        segments.push(first_sequence);
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Read the entries of a segment, and the length of the complete entries. An
+/// entry left incomplete by a crash ends the segment.
+fn read_segment(path: &Path) -> io::Result<(Vec<JournalEntry>, u64)> {
+    let bytes = fs::read(path)?;
+    let len = bytes.len() as u64;
+    let mut reader = Cursor::new(bytes);
+
+    let mut entries = Vec::new();
+    let mut size = 0;
+    while len - size >= 4 {
+        let entry_size = u32::deserialize(&mut reader)? as u64;
+        if len - size - 4 < entry_size {
+            break;
+        }
+        let Ok(entry) = JournalEntry::deserialize(&mut reader) else {
+            break;
+        };
+        size += 4 + entry_size;
+        reader.set_position(size);
+        entries.push(entry);
+    }
+
+    Ok((entries, size))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::authorization::AuthorizationSpec;
+    use crate::clients::Transport;
+    use crate::outbound::{self, OutboundReceiver, OverflowPolicy};
+
+    use super::*;
+
+    fn journal_option(directory: &Path, max_segments: Option<usize>) -> JournalOption {
+        JournalOption {
+            directory: directory.to_path_buf(),
+            patterns: vec!["LSE.*".to_string()],
+            // Small enough for every entry to start a new segment.
+            segment_size: 1,
+            max_segments,
+            sync: JournalSync::Always,
+        }
+    }
+
+    fn connect_subscriber(capacity: usize) -> (ClientManager, OutboundReceiver) {
+        let mut client_manager = ClientManager::new();
+        let (subscriber_tx, subscriber_rx) =
+            outbound::channel(capacity, OverflowPolicy::Disconnect);
+        client_manager.handle_connect(
+            "s1",
+            "host2".into(),
+            "dick".into(),
+            Transport::Socket,
+            subscriber_tx,
+        );
+        (client_manager, subscriber_rx)
+    }
+
+    async fn wait_for_writer(journal_manager: &JournalManager) {
+        let mut written = journal_manager.reader.written.clone();
+        written
+            .wait_for(|written| *written == journal_manager.next_sequence)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_replay_authorized_data() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let option = journal_option(&directory, None);
+
+        let authorization_manager = AuthorizationManager::new(vec![
+            // The publisher is entitled by the groups it published with.
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("@desk"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::from([1, 2]),
+                roles: Role::Publisher,
+            },
+            AuthorizationSpec {
+                user_pattern: WildMatch::new("dick"),
+                topic_pattern: WildMatch::new("LSE.*"),
+                entitlements: HashSet::from([1]),
+                roles: Role::Subscriber,
+            },
+        ]);
+
+        let (client_manager, mut subscriber_rx) = connect_subscriber(10);
+        // Each replay waits for what it sent to be taken.
+        let subscriber = tokio::spawn(async move {
+            let mut events = Vec::new();
+            while let Some(event) = subscriber_rx.recv().await {
+                events.push(event);
+            }
+            events
+        });
+
+        let level1 = DataPacket::new(HashSet::from([1]), HashMap::new(), "level 1".into());
+        let level2 = DataPacket::new(HashSet::from([2]), HashMap::new(), "level 2".into());
+        let publish = |journal_manager: &mut JournalManager, topic: &str| {
+            journal_manager
+                .handle_multicast_data(
+                    "host1",
+                    "harry",
+                    &["desk".to_string()],
+                    topic,
+                    &[level1.clone(), level2.clone()],
+                )
+                .unwrap();
+        };
+
+        let (hub_tx, _) = mpsc::channel(10);
+        let mut journal_manager = JournalManager::open(&option, hub_tx).unwrap();
+        for topic in ["LSE.VOD", "NYSE.IBM", "LSE.TSCO"] {
+            publish(&mut journal_manager, topic);
+        }
+        wait_for_writer(&journal_manager).await;
+        assert_eq!(*journal_manager.reader.segments.lock().unwrap(), vec![1, 2]);
+        drop(journal_manager);
+
+        // Reopening continues the sequence.
+        let (hub_tx, mut hub_rx) = mpsc::channel(10);
+        let mut journal_manager = JournalManager::open(&option, hub_tx).unwrap();
+        assert_eq!(journal_manager.next_sequence, 3);
+
+        journal_manager
+            .handle_replay_request(
+                "s1",
+                1,
+                "LSE.*".into(),
+                ReplayFrom::Sequence(2),
+                &client_manager,
+                &authorization_manager,
+            )
+            .unwrap();
+        // Data journaled during the replay is sent by a further replay.
+        publish(&mut journal_manager, "LSE.BARC");
+
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let Some(ClientEvent::OnReplayed(client_id, request_id, result)) = hub_rx.recv().await
+            else {
+                panic!("expected a replay result");
+            };
+            results.push(
+                journal_manager
+                    .handle_replayed(
+                        &client_id,
+                        request_id,
+                        result,
+                        &client_manager,
+                        &authorization_manager,
+                    )
+                    .map(Result::unwrap),
+            );
+        }
+        assert_eq!(results, vec![None, Some("LSE.*".to_string())]);
+        drop(client_manager);
+
+        let expected_event = |topic: &str, sequence: &str| {
+            let mut packet = level1.clone();
+            packet
+                .headers
+                .insert(SEQUENCE_HEADER.to_vec(), sequence.as_bytes().to_vec());
+            ServerEvent::OnMessage(Message::ForwardedMulticastData {
+                host: "host1".into(),
+                user: "harry".into(),
+                topic: topic.into(),
+                data_packets: vec![packet],
+            })
+        };
+        let expected = vec![
+            expected_event("LSE.TSCO", "2"),
+            expected_event("LSE.BARC", "3"),
+        ];
+
+        assert_eq!(subscriber.await.unwrap(), expected);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_replay_more_than_the_queue_holds_from_retained_segments() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let option = journal_option(&directory, Some(3));
+
+        let authorization_manager = AuthorizationManager::new(vec![AuthorizationSpec {
+            user_pattern: WildMatch::new("*"),
+            topic_pattern: WildMatch::new("LSE.*"),
+            entitlements: HashSet::from([0]),
+            roles: Role::Publisher | Role::Subscriber,
+        }]);
+
+        // The queue holds one event, and overflowing it disconnects.
+        let (client_manager, mut subscriber_rx) = connect_subscriber(1);
+
+        let (hub_tx, mut hub_rx) = mpsc::channel(10);
+        let mut journal_manager = JournalManager::open(&option, hub_tx).unwrap();
+        for _ in 0..5 {
+            journal_manager
+                .handle_multicast_data(
+                    "host1",
+                    "harry",
+                    &[],
+                    "LSE.VOD",
+                    &[DataPacket::new(
+                        HashSet::new(),
+                        HashMap::new(),
+                        "data".into(),
+                    )],
+                )
+                .unwrap();
+        }
+        wait_for_writer(&journal_manager).await;
+        // Only the newest segments are kept.
+        assert_eq!(
+            *journal_manager.reader.segments.lock().unwrap(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(list_segments(&directory).unwrap(), vec![3, 4, 5]);
+
+        journal_manager
+            .handle_replay_request(
+                "s1",
+                1,
+                "LSE.*".into(),
+                ReplayFrom::Sequence(1),
+                &client_manager,
+                &authorization_manager,
+            )
+            .unwrap();
+
+        let mut sequences = Vec::new();
+        for _ in 0..3 {
+            let Some(ServerEvent::OnMessage(Message::ForwardedMulticastData {
+                data_packets, ..
+            })) = subscriber_rx.recv().await
+            else {
+                panic!("expected replayed data");
+            };
+            sequences.push(data_packets[0].headers[SEQUENCE_HEADER].clone());
+        }
+        assert_eq!(sequences, vec![b"3".to_vec(), b"4".to_vec(), b"5".to_vec()]);
+
+        let Some(ClientEvent::OnReplayed(_, _, result)) = hub_rx.recv().await else {
+            panic!("expected a replay result");
+        };
+        assert_eq!(result.unwrap(), 6);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::metrics::METRICS;
+use crate::options::ConnectionLimitOption;
+
+#[derive(Default)]
+struct ConnectionCounts {
+    unauthenticated: usize,
+    hosts: HashMap<IpAddr, usize>,
+}
+
+/// Counts the connections of a listener, to cap those which have not yet
+/// authenticated, and those from each host.
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    option: ConnectionLimitOption,
+    counts: Arc<Mutex<ConnectionCounts>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(option: ConnectionLimitOption) -> Self {
+        ConnectionLimiter {
+            option,
+            counts: Arc::new(Mutex::new(ConnectionCounts::default())),
+        }
+    }
+
+    /// Admit a new connection, unless it would exceed a limit. Connections
+    /// on a Unix socket have no host.
+    pub fn admit(&self, host: Option<IpAddr>) -> io::Result<ConnectionPermit> {
+        let mut counts = self.counts.lock().unwrap();
+
+        if self.option.max_unauthenticated > 0
+            && counts.unauthenticated >= self.option.max_unauthenticated
+        {
+            METRICS.connection_refused("unauthenticated");
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "too many unauthenticated connections",
+            ));
+        }
+
+        if let Some(host) = host {
+            let count = counts.hosts.entry(host).or_default();
+            if self.option.max_per_host > 0 && *count >= self.option.max_per_host {
+                METRICS.connection_refused("host");
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("too many connections from {host}"),
+                ));
+            }
+            *count += 1;
+        }
+        counts.unauthenticated += 1;
+
+        Ok(ConnectionPermit {
+            counts: self.counts.clone(),
+            host,
+            is_authenticated: false,
+            deadline: Instant::now()
+                + Duration::from_secs(self.option.authentication_timeout_seconds),
+        })
+    }
+}
+
+/// A connection admitted by a listener. The client must authenticate by the
+/// deadline. The connection is no longer counted when the permit is dropped.
+pub struct ConnectionPermit {
+    counts: Arc<Mutex<ConnectionCounts>>,
+    host: Option<IpAddr>,
+    is_authenticated: bool,
+    deadline: Instant,
+}
+
+impl ConnectionPermit {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn authenticated(&mut self) {
+        if !self.is_authenticated {
+            self.is_authenticated = true;
+            self.counts.lock().unwrap().unauthenticated -= 1;
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if !self.is_authenticated {
+            counts.unauthenticated -= 1;
+        }
+        if let Some(host) = self.host
+            && let Some(count) = counts.hosts.get_mut(&host)
+        {
+            *count -= 1;
+            if *count == 0 {
+                counts.hosts.remove(&host);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_limit_connections() {
+        let limiter = ConnectionLimiter::new(ConnectionLimitOption {
+            authentication_timeout_seconds: 10,
+            max_unauthenticated: 2,
+            max_per_host: 2,
+        });
+        let h1: IpAddr = "10.0.0.1".parse().unwrap();
+        let h2: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let mut p1 = limiter.admit(Some(h1)).unwrap();
+        let p2 = limiter.admit(Some(h1)).unwrap();
+        // Too many unauthenticated connections.
+        assert!(limiter.admit(Some(h2)).is_err());
+
+        p1.authenticated();
+        // Too many connections from the host.
+        assert!(limiter.admit(Some(h1)).is_err());
+        let p3 = limiter.admit(Some(h2)).unwrap();
+
+        drop(p2);
+        drop(p3);
+        let _p4 = limiter.admit(Some(h1)).unwrap();
+        let _p5 = limiter.admit(None).unwrap();
+        assert!(limiter.admit(None).is_err());
+    }
+}
@@ -2,46 +2,70 @@
 
 use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::RwLock;
-use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::task::JoinSet;
 use tokio_rustls::TlsAcceptor;
+use uuid::Uuid;
 
 use common::{MessageSocket, MessageWebSocket};
 
+mod admin;
+use admin::{ResetRequest, start_admin_listener};
+
 mod authentication;
-use authentication::AuthenticationManager;
+use authentication::{AuthenticationManager, LockoutManager, PeerInfo};
 
 mod authorization;
 use authorization::{AuthorizationSpec, load_authorizations};
 
+mod caching;
+
 mod clients;
+use clients::Transport;
 
 mod events;
 use events::ClientEvent;
 
+mod federation;
+use federation::FederationLink;
+
 mod hub;
 use hub::Hub;
 
 mod interactor;
-use interactor::Interactor;
+use interactor::{Interactor, ListenerSettings};
+
+mod journal;
+use journal::JournalManager;
+
+mod limits;
+use limits::ConnectionLimiter;
 
 mod options;
-use options::Options;
+use options::{
+    ConnectionLimitOption, HeartbeatOption, ListenerTransport, Options, OutboundQueueOption,
+};
+
+mod metrics;
+use metrics::start_metrics_listener;
 
 mod notifications;
 
+mod outbound;
+
 mod publishing;
 
 mod subscriptions;
 
 mod tls;
-use tls::create_acceptor;
+use tls::TlsManager;
 
 /// The server starts by creating a `hub` task to process messages. It then
 /// listens for client connections. When a client connects an interactor is
@@ -50,92 +74,192 @@ use tls::create_acceptor;
 async fn main() -> io::Result<()> {
     env_logger::init();
 
-    // Command line options.
+    // Command line options and the configuration file.
     let options = Options::load()?;
 
     let authorizations =
         load_authorizations(&options.authorizations_file, &options.authorizations)?;
+    // Failed authentication is counted across all the listeners.
+    let lockout_manager = LockoutManager::new(options.authentication_lockout);
     let authentication_manager = Arc::new(RwLock::new(AuthenticationManager::new(
         &options.authentication,
+        lockout_manager.clone(),
     )?));
 
+    // The managers are kept to be reset with the configuration.
+    let mut authentication_managers = vec![authentication_manager.clone()];
+    let mut tls_managers = Vec::new();
+
+    let mut listeners = Vec::new();
+    for listener in options.listeners {
+        let endpoint = match listener.transport {
+            ListenerTransport::Unix => ListenerEndpoint::Unix(listener.endpoint.into()),
+            transport => ListenerEndpoint::Tcp {
+                addr: listener
+                    .endpoint
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?,
+                is_web_socket: transport == ListenerTransport::WebSocket,
+            },
+        };
+        let tls_manager = match listener.tls {
+            Some(option) => Some(Arc::new(RwLock::new(TlsManager::new(option)?))),
+            None => None,
+        };
+        tls_managers.extend(tls_manager.clone());
+        let listener_authentication_manager = match &listener.authentication {
+            Some(option) => {
+                let manager = Arc::new(RwLock::new(AuthenticationManager::new(
+                    option,
+                    lockout_manager.clone(),
+                )?));
+                authentication_managers.push(manager.clone());
+                manager
+            }
+            None => authentication_manager.clone(),
+        };
+        listeners.push(Listener {
+            endpoint,
+            heartbeat: listener.heartbeat.unwrap_or(options.heartbeat),
+            connection_limits: listener
+                .connection_limits
+                .unwrap_or(options.connection_limits),
+            outbound_queue: options.outbound_queue,
+            tls_manager,
+            authentication_manager: listener_authentication_manager,
+        });
+    }
+
+    if options.check_config {
+        println!("The configuration is valid.");
+        return Ok(());
+    }
+
     // Make the channel for the client-to-server communication.
     let (client_tx, server_rx) = mpsc::channel::<ClientEvent>(32);
 
+    let journal_manager = match &options.journal {
+        Some(journal) => Some(JournalManager::open(journal, client_tx.clone())?),
+        None => None,
+    };
+
     let mut join_set = JoinSet::new();
 
     // Start the hub message processor. Note that is takes the receive end of
     // the mpsc channel.
-    join_set.spawn(async move { Hub::run(authorizations, server_rx).await });
+    // Identifies this broker to federation peers.
+    let broker_id: String = Uuid::new_v4().into();
+    log::info!("Starting broker {broker_id}.");
+
+    let hub_broker_id = broker_id.clone();
+    let cache_patterns = options.cache_patterns.clone();
+    join_set.spawn(async move {
+        Hub::run(
+            hub_broker_id,
+            authorizations,
+            cache_patterns,
+            journal_manager,
+            server_rx,
+        )
+        .await
+    });
+
+    for federation in options.federations {
+        let link = FederationLink::new(
+            federation,
+            broker_id.clone(),
+            options.heartbeat,
+            options.outbound_queue,
+            client_tx.clone(),
+        );
+        join_set.spawn(async move { link.run().await });
+    }
+
+    // Resets are requested by SIGHUP, or by the admin endpoint.
+    let (reset_tx, reset_rx) = mpsc::channel::<ResetRequest>(1);
 
     handle_config_reset(
         options.authorizations_file.clone(),
         options.authorizations.clone(),
-        authentication_manager.clone(),
+        authentication_managers,
+        tls_managers,
         client_tx.clone(),
+        reset_rx,
     )
     .await;
 
-    let tls_acceptor = match options.tls {
-        Some(option) => Some(create_acceptor(&option.certfile, &option.keyfile)?),
-        None => None,
-    };
-
-    let socket_addr = options
-        .socket_endpoint
-        .to_socket_addrs()?
-        .next()
-        .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
-    let socket_tls_acceptor = tls_acceptor.clone();
-    let socket_client_tx = client_tx.clone();
-    let socket_authentication_manager = authentication_manager.clone();
-
-    join_set.spawn(async move {
-        start_listener(
-            false,
-            socket_addr,
-            options.heartbeat_seconds,
-            socket_tls_acceptor,
-            socket_client_tx,
-            socket_authentication_manager,
-        )
-        .await
-    });
+    if let Some(endpoint) = options.metrics_endpoint {
+        join_set.spawn(async move { start_metrics_listener(endpoint).await });
+    }
 
-    let web_socket_addr = options
-        .web_socket_endpoint
-        .to_socket_addrs()?
-        .next()
-        .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
-    let web_socket_tls_acceptor = tls_acceptor.clone();
-    let web_socket_client_tx = client_tx.clone();
-    let web_socket_authentication_manager = authentication_manager.clone();
+    if let Some(admin) = options.admin {
+        let admin_client_tx = client_tx.clone();
+        let admin_authentication_manager = authentication_manager.clone();
+        join_set.spawn(async move {
+            start_admin_listener(
+                admin,
+                admin_client_tx,
+                reset_tx,
+                admin_authentication_manager,
+            )
+            .await
+        });
+    }
 
-    join_set.spawn(async move {
-        start_listener(
-            true,
-            web_socket_addr,
-            options.heartbeat_seconds,
-            web_socket_tls_acceptor,
-            web_socket_client_tx,
-            web_socket_authentication_manager,
-        )
-        .await
-    });
+    for listener in listeners {
+        let listener_client_tx = client_tx.clone();
+        join_set.spawn(async move {
+            match listener.endpoint {
+                ListenerEndpoint::Tcp {
+                    addr,
+                    is_web_socket,
+                } => start_listener(is_web_socket, addr, listener, listener_client_tx).await,
+                ListenerEndpoint::Unix(ref path) => {
+                    start_unix_listener(path.clone(), listener, listener_client_tx).await
+                }
+            }
+        });
+    }
 
     join_set.join_all().await;
 
     Ok(())
 }
 
+/// Where a listener accepts connections.
+enum ListenerEndpoint {
+    Tcp {
+        addr: SocketAddr,
+        is_web_socket: bool,
+    },
+    Unix(PathBuf),
+}
+
+/// A listener with the managers for its certificates and authentication.
+struct Listener {
+    endpoint: ListenerEndpoint,
+    heartbeat: HeartbeatOption,
+    connection_limits: ConnectionLimitOption,
+    outbound_queue: OutboundQueueOption,
+    tls_manager: Option<Arc<RwLock<TlsManager>>>,
+    authentication_manager: Arc<RwLock<AuthenticationManager>>,
+}
+
 async fn start_listener(
     is_web_socket: bool,
     addr: SocketAddr,
-    heartbeat_seconds: u64,
-    tls_acceptor: Option<TlsAcceptor>,
+    listener: Listener,
     client_tx: Sender<ClientEvent>,
-    authentication_manager: Arc<RwLock<AuthenticationManager>>,
 ) -> io::Result<()> {
+    let Listener {
+        heartbeat,
+        connection_limits,
+        outbound_queue,
+        tls_manager,
+        authentication_manager,
+        ..
+    } = listener;
     log::info!(
         "Listening on address {} for {}{}.",
         &addr,
@@ -143,25 +267,47 @@ async fn start_listener(
             true => "web sockets",
             false => "sockets",
         },
-        match tls_acceptor {
+        match tls_manager {
             Some(_) => " using TLS",
             None => "",
         }
     );
 
     let listener = TcpListener::bind(&addr).await?;
+    let limiter = ConnectionLimiter::new(connection_limits);
 
     loop {
         // Wait for a client to connect.
         let (stream, addr) = listener.accept().await?;
 
+        // Drop the connection if there are too many.
+        let permit = match limiter.admit(Some(addr.ip())) {
+            Ok(permit) => permit,
+            Err(error) => {
+                log::debug!("Refused connection from {addr}: {error}");
+                continue;
+            }
+        };
+
+        // Take the acceptor for each connection, as it is replaced when the
+        // certificates are reloaded.
+        let tls_acceptor = match &tls_manager {
+            Some(tls_manager) => Some(tls_manager.read().await.acceptor()),
+            None => None,
+        };
+
         // Start an interactor.
+        let settings = ListenerSettings {
+            permit,
+            heartbeat,
+            outbound_queue,
+        };
         spawn_interactor(
             is_web_socket,
             stream,
             addr,
-            heartbeat_seconds,
-            tls_acceptor.clone(),
+            settings,
+            tls_acceptor,
             client_tx.clone(),
             authentication_manager.clone(),
         )
@@ -169,36 +315,148 @@ async fn start_listener(
     }
 }
 
+async fn start_unix_listener(
+    path: PathBuf,
+    listener: Listener,
+    client_tx: Sender<ClientEvent>,
+) -> io::Result<()> {
+    let Listener {
+        heartbeat,
+        connection_limits,
+        outbound_queue,
+        authentication_manager,
+        ..
+    } = listener;
+    log::info!("Listening on unix socket {}.", path.display());
+
+    // Remove a socket left by a previous run.
+    if let Ok(metadata) = std::fs::symlink_metadata(&path)
+        && metadata.file_type().is_socket()
+    {
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    let limiter = ConnectionLimiter::new(connection_limits);
+
+    loop {
+        // Wait for a client to connect.
+        let (stream, _) = listener.accept().await?;
+
+        // Drop the connection if there are too many.
+        let permit = match limiter.admit(None) {
+            Ok(permit) => permit,
+            Err(error) => {
+                log::debug!("Refused unix socket connection: {error}");
+                continue;
+            }
+        };
+
+        // Start an interactor.
+        let client_tx = client_tx.clone();
+        let authentication_manager = authentication_manager.clone();
+        let settings = ListenerSettings {
+            permit,
+            heartbeat,
+            outbound_queue,
+        };
+        tokio::spawn(async move {
+            let result =
+                start_unix_interactor(stream, settings, client_tx, authentication_manager).await;
+            log_client_exit(result);
+        });
+    }
+}
+
 async fn handle_config_reset(
     authorizations_file: Option<PathBuf>,
     authorizations: Vec<AuthorizationSpec>,
-    authentication_manager: Arc<RwLock<AuthenticationManager>>,
+    authentication_managers: Vec<Arc<RwLock<AuthenticationManager>>>,
+    tls_managers: Vec<Arc<RwLock<TlsManager>>>,
     client_tx: Sender<ClientEvent>,
+    mut reset_rx: Receiver<ResetRequest>,
 ) {
     let mut hangup_stream = signal(SignalKind::hangup()).unwrap();
     tokio::spawn(async move {
         loop {
-            // Wait for SIGHUP.
-            hangup_stream.recv().await.unwrap();
+            tokio::select! {
+                // Wait for SIGHUP.
+                _ = hangup_stream.recv() => {
+                    // Failures are logged as each part is reloaded.
+                    let _ = reset_config(
+                        &authorizations_file,
+                        &authorizations,
+                        &authentication_managers,
+                        &tls_managers,
+                        &client_tx,
+                    )
+                    .await;
+                }
+                Some(reply) = reset_rx.recv() => {
+                    let result = reset_config(
+                        &authorizations_file,
+                        &authorizations,
+                        &authentication_managers,
+                        &tls_managers,
+                        &client_tx,
+                    )
+                    .await;
+                    let _ = reply.send(result);
+                }
+            }
+        }
+    });
+}
 
-            authentication_manager.write().await.reset().await.unwrap();
+/// Reload the authentication, authorizations and certificates. Each is
+/// reloaded independently, so a part which fails keeps its previous state
+/// without stopping the others.
+async fn reset_config(
+    authorizations_file: &Option<PathBuf>,
+    authorizations: &[AuthorizationSpec],
+    authentication_managers: &[Arc<RwLock<AuthenticationManager>>],
+    tls_managers: &[Arc<RwLock<TlsManager>>],
+    client_tx: &Sender<ClientEvent>,
+) -> io::Result<()> {
+    let mut errors = Vec::new();
 
-            log::info!("Reloading authorizations.");
-            let authorizations =
-                load_authorizations(&authorizations_file, &authorizations).unwrap();
-            client_tx
-                .send(ClientEvent::OnReset(authorizations))
-                .await
-                .unwrap();
+    for authentication_manager in authentication_managers {
+        if let Err(error) = authentication_manager.read().await.reset().await {
+            log::error!("Failed to reload authentication: {error}");
+            errors.push(format!("authentication: {error}"));
         }
-    });
+    }
+
+    log::info!("Reloading authorizations.");
+    match load_authorizations(authorizations_file, authorizations) {
+        Ok(authorizations) => client_tx
+            .send(ClientEvent::OnReset(authorizations))
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+        Err(error) => {
+            log::error!("Failed to reload authorizations: {error}");
+            errors.push(format!("authorizations: {error}"));
+        }
+    }
+
+    for tls_manager in tls_managers {
+        if let Err(error) = tls_manager.write().await.reset() {
+            log::error!("Failed to reload TLS certificates: {error}");
+            errors.push(format!("tls: {error}"));
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(io::Error::new(io::ErrorKind::Other, errors.join("; "))),
+    }
 }
 
 async fn spawn_interactor(
     is_web_socket: bool,
     stream: TcpStream,
     addr: SocketAddr,
-    heartbeat_seconds: u64,
+    settings: ListenerSettings,
     tls_acceptor: Option<TlsAcceptor>,
     client_tx: Sender<ClientEvent>,
     authentication_manager: Arc<RwLock<AuthenticationManager>>,
@@ -208,44 +466,66 @@ async fn spawn_interactor(
             is_web_socket,
             stream,
             addr,
-            heartbeat_seconds,
+            settings,
             tls_acceptor,
             client_tx,
             authentication_manager,
         )
         .await;
+        log_client_exit(result);
+    });
+}
 
-        match result {
-            Ok(()) => log::info!("Client exited normally."),
-            Err(error) => {
-                if error.kind() == io::ErrorKind::UnexpectedEof {
-                    log::info!("Client closed connection.")
-                } else {
-                    log::info!("Client faulted with error: {error}")
-                }
+fn log_client_exit(result: io::Result<()>) {
+    match result {
+        Ok(()) => log::info!("Client exited normally."),
+        Err(error) => {
+            if error.kind() == io::ErrorKind::UnexpectedEof {
+                log::info!("Client closed connection.")
+            } else {
+                log::info!("Client faulted with error: {error}")
             }
         }
-    });
+    }
 }
 
 async fn start_interactor(
     is_web_socket: bool,
     stream: TcpStream,
     addr: SocketAddr,
-    heartbeat_seconds: u64,
+    settings: ListenerSettings,
     tls_acceptor: Option<TlsAcceptor>,
     client_tx: Sender<ClientEvent>,
     authentication_manager: Arc<RwLock<AuthenticationManager>>,
 ) -> io::Result<()> {
     let mut interactor = Interactor::new();
+    let mut peer = PeerInfo {
+        host: addr.ip().to_string(),
+        certificates: Vec::new(),
+        uid: None,
+    };
 
     match tls_acceptor {
         Some(acceptor) => {
-            let stream = acceptor.accept(stream).await?;
+            // The handshakes count towards the authentication timeout.
+            let stream =
+                tokio::time::timeout_at(settings.permit.deadline(), acceptor.accept(stream))
+                    .await
+                    .map_err(handshake_timed_out)??;
+            // The certificate has been verified if one was required.
+            if let Some(certificates) = stream.get_ref().1.peer_certificates() {
+                peer.certificates = certificates.to_vec();
+            }
             match is_web_socket {
                 true => {
                     log::info!("Accepting web socket connection on adress {addr} over TLS.");
-                    let stream = tokio_tungstenite::accept_async(stream).await.map_err(|e| {
+                    let stream = tokio::time::timeout_at(
+                        settings.permit.deadline(),
+                        tokio_tungstenite::accept_async(stream),
+                    )
+                    .await
+                    .map_err(handshake_timed_out)?
+                    .map_err(|e| {
                         io::Error::new(
                             io::ErrorKind::Other,
                             format!("failed to accept websocket: {}", e),
@@ -255,10 +535,11 @@ async fn start_interactor(
                     interactor
                         .run(
                             &mut stream,
-                            addr,
+                            peer,
+                            Transport::TlsWebSocket,
                             client_tx,
                             authentication_manager,
-                            heartbeat_seconds,
+                            settings,
                         )
                         .await
                 }
@@ -268,10 +549,11 @@ async fn start_interactor(
                     interactor
                         .run(
                             &mut stream,
-                            addr,
+                            peer,
+                            Transport::TlsSocket,
                             client_tx,
                             authentication_manager,
-                            heartbeat_seconds,
+                            settings,
                         )
                         .await
                 }
@@ -280,7 +562,13 @@ async fn start_interactor(
         None => match is_web_socket {
             true => {
                 log::info!("Accepting web socket connection on address {addr}.");
-                let stream = tokio_tungstenite::accept_async(stream).await.map_err(|e| {
+                let stream = tokio::time::timeout_at(
+                    settings.permit.deadline(),
+                    tokio_tungstenite::accept_async(stream),
+                )
+                .await
+                .map_err(handshake_timed_out)?
+                .map_err(|e| {
                     io::Error::new(
                         io::ErrorKind::Other,
                         format!("failed to accept websocket: {}", e),
@@ -290,10 +578,11 @@ async fn start_interactor(
                 interactor
                     .run(
                         &mut stream,
-                        addr,
+                        peer,
+                        Transport::WebSocket,
                         client_tx,
                         authentication_manager,
-                        heartbeat_seconds,
+                        settings,
                     )
                     .await
             }
@@ -303,13 +592,251 @@ async fn start_interactor(
                 interactor
                     .run(
                         &mut stream,
-                        addr,
+                        peer,
+                        Transport::Socket,
                         client_tx,
                         authentication_manager,
-                        heartbeat_seconds,
+                        settings,
                     )
                     .await
             }
         },
     }
 }
+
+async fn start_unix_interactor(
+    stream: UnixStream,
+    settings: ListenerSettings,
+    client_tx: Sender<ClientEvent>,
+    authentication_manager: Arc<RwLock<AuthenticationManager>>,
+) -> io::Result<()> {
+    let peer = PeerInfo {
+        host: "localhost".into(),
+        certificates: Vec::new(),
+        uid: stream.peer_cred().ok().map(|credentials| credentials.uid()),
+    };
+
+    log::info!("Accepting unix socket connection.");
+    let mut stream = MessageSocket::new(stream);
+    Interactor::new()
+        .run(
+            &mut stream,
+            peer,
+            Transport::Unix,
+            client_tx,
+            authentication_manager,
+            settings,
+        )
+        .await
+}
+
+fn handshake_timed_out(_: tokio::time::error::Elapsed) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "handshake timed out")
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use client::{BasicCredentials, ClientProtocol};
+    use common::messages::DataPacket;
+
+    use crate::options::AuthenticationOption;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_authenticate_clients_with_password_file() {
+        // The password for tom is "password".
+        let path = std::env::temp_dir().join(format!("{}.htpasswd", Uuid::new_v4()));
+        std::fs::write(&path, "tom:$apr1$xxxxxxxx$dxHfLAsjHkDRmG83UXe8K0\n").unwrap();
+        let authentication_manager = Arc::new(RwLock::new(
+            AuthenticationManager::new(
+                &[AuthenticationOption::Basic(path.clone(), None)],
+                LockoutManager::default(),
+            )
+            .unwrap(),
+        ));
+        let authorizations = load_authorizations(&None::<PathBuf>, &[]).unwrap();
+
+        // Find a free port for the server.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let (client_tx, server_rx) = mpsc::channel::<ClientEvent>(32);
+        tokio::spawn(Hub::run(
+            "broker".into(),
+            authorizations,
+            Vec::new(),
+            None,
+            server_rx,
+        ));
+        let listener = Listener {
+            endpoint: ListenerEndpoint::Tcp {
+                addr,
+                is_web_socket: false,
+            },
+            heartbeat: HeartbeatOption {
+                seconds: 30,
+                max_missed: 0,
+            },
+            connection_limits: ConnectionLimitOption::default(),
+            outbound_queue: OutboundQueueOption {
+                capacity: 16,
+                policy: outbound::OverflowPolicy::Disconnect,
+            },
+            tls_manager: None,
+            authentication_manager,
+        };
+        tokio::spawn(start_listener(false, addr, listener, client_tx));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let host = addr.ip().to_string();
+
+        let error = client::connect(
+            &host,
+            addr.port(),
+            None,
+            Arc::new(BasicCredentials::basic("tom", "wrong")),
+            client::Heartbeat::default(),
+            None,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        let (mut client, mut events) = client::connect(
+            &host,
+            addr.port(),
+            None,
+            Arc::new(BasicCredentials::basic("tom", "password")),
+            client::Heartbeat::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        client.add_subscription("LSE.VOD".into()).await.unwrap();
+        let data_packet = DataPacket::new(HashSet::new(), HashMap::new(), "hello".into());
+        client
+            .publish("LSE.VOD".into(), vec![data_packet.clone()])
+            .await
+            .unwrap();
+
+        let Some(client::ClientEvent::Data {
+            user, data_packets, ..
+        }) = events.recv().await
+        else {
+            panic!("expected data");
+        };
+        assert_eq!(user, "tom");
+        assert_eq!(data_packets, vec![data_packet]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_authenticate_unix_socket_clients_by_peer_credentials() {
+        let path = std::env::temp_dir().join(format!("{}.sock", Uuid::new_v4()));
+        let authentication_manager = Arc::new(RwLock::new(
+            AuthenticationManager::new(
+                &[AuthenticationOption::PeerCredentials],
+                LockoutManager::default(),
+            )
+            .unwrap(),
+        ));
+        let authorizations = load_authorizations(&None::<PathBuf>, &[]).unwrap();
+
+        let (client_tx, server_rx) = mpsc::channel::<ClientEvent>(32);
+        tokio::spawn(Hub::run(
+            "broker".into(),
+            authorizations,
+            Vec::new(),
+            None,
+            server_rx,
+        ));
+        let listener = Listener {
+            endpoint: ListenerEndpoint::Unix(path.clone()),
+            heartbeat: HeartbeatOption {
+                seconds: 30,
+                max_missed: 0,
+            },
+            connection_limits: ConnectionLimitOption::default(),
+            outbound_queue: OutboundQueueOption {
+                capacity: 16,
+                policy: outbound::OverflowPolicy::Disconnect,
+            },
+            tls_manager: None,
+            authentication_manager,
+        };
+        tokio::spawn(start_unix_listener(path.clone(), listener, client_tx));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let (mut client, mut events) = client::connect_unix(
+            &path,
+            Arc::new(client::PeerCredentials {}),
+            client::Heartbeat::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        client.add_subscription("LSE.VOD".into()).await.unwrap();
+        client
+            .publish(
+                "LSE.VOD".into(),
+                vec![DataPacket::new(
+                    HashSet::new(),
+                    HashMap::new(),
+                    "hello".into(),
+                )],
+            )
+            .await
+            .unwrap();
+
+        let Some(client::ClientEvent::Data { user, .. }) = events.recv().await else {
+            panic!("expected data");
+        };
+        let expected = nix::unistd::User::from_uid(nix::unistd::Uid::current())
+            .unwrap()
+            .unwrap();
+        assert_eq!(user, expected.name);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_reload_each_part_independently() {
+        let path = std::env::temp_dir().join(format!("{}.htpasswd", Uuid::new_v4()));
+        std::fs::write(&path, "tom:$apr1$xxxxxxxx$dxHfLAsjHkDRmG83UXe8K0\n").unwrap();
+        let authentication_manager = Arc::new(RwLock::new(
+            AuthenticationManager::new(
+                &[AuthenticationOption::Basic(path.clone(), None)],
+                LockoutManager::default(),
+            )
+            .unwrap(),
+        ));
+        // The password file can no longer be read.
+        std::fs::remove_file(&path).unwrap();
+
+        let (client_tx, mut client_rx) = mpsc::channel(10);
+        let error = reset_config(
+            &None::<PathBuf>,
+            &[],
+            &[authentication_manager],
+            &[],
+            &client_tx,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().starts_with("authentication: "));
+
+        // The authorizations are still reloaded.
+        let Some(ClientEvent::OnReset(_)) = client_rx.recv().await else {
+            panic!("expected the authorizations to be reloaded");
+        };
+    }
+}
@@ -0,0 +1,249 @@
+use std::io;
+use std::sync::LazyLock;
+
+use axum::Router;
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use tokio::net::TcpListener;
+
+use common::Serializable;
+use common::messages::Message;
+
+use crate::clients::Transport;
+
+/// The broker metrics.
+///
+/// They are updated from the hub, the interactors and the functions that
+/// filter data, so they are kept in a static rather than passed to each.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    clients_connected: IntGaugeVec,
+    messages_received: IntCounterVec,
+    messages_sent: IntCounterVec,
+    bytes_received: IntCounter,
+    bytes_sent: IntCounter,
+    packets_dropped: IntCounter,
+    authentication_failures: IntCounterVec,
+    authentication_lockouts: IntCounterVec,
+    connections_refused: IntCounterVec,
+    hub_event_seconds: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("squawkbus".into()), None).unwrap();
+
+        let clients_connected = IntGaugeVec::new(
+            Opts::new("clients_connected", "The connected clients."),
+            &["transport", "tls"],
+        )
+        .unwrap();
+        let messages_received = IntCounterVec::new(
+            Opts::new(
+                "messages_received_total",
+                "The messages received from clients.",
+            ),
+            &["message_type"],
+        )
+        .unwrap();
+        let messages_sent = IntCounterVec::new(
+            Opts::new("messages_sent_total", "The messages sent to clients."),
+            &["message_type"],
+        )
+        .unwrap();
+        let bytes_received = IntCounter::new(
+            "bytes_received_total",
+            "The size of the messages received from clients.",
+        )
+        .unwrap();
+        let bytes_sent = IntCounter::new(
+            "bytes_sent_total",
+            "The size of the messages sent to clients.",
+        )
+        .unwrap();
+        let packets_dropped = IntCounter::new(
+            "packets_dropped_total",
+            "The data packets not sent because the receiver was not entitled to them.",
+        )
+        .unwrap();
+        let authentication_failures = IntCounterVec::new(
+            Opts::new(
+                "authentication_failures_total",
+                "The failed authentication requests.",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let authentication_lockouts = IntCounterVec::new(
+            Opts::new(
+                "authentication_lockouts_total",
+                "The users and hosts locked out after repeated authentication failures.",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let connections_refused = IntCounterVec::new(
+            Opts::new(
+                "connections_refused_total",
+                "The connections refused by the limits of a listener.",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let hub_event_seconds = Histogram::with_opts(HistogramOpts::new(
+            "hub_event_seconds",
+            "The time taken by the hub to handle an event.",
+        ))
+        .unwrap();
+
+        registry
+            .register(Box::new(clients_connected.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(messages_received.clone()))
+            .unwrap();
+        registry.register(Box::new(messages_sent.clone())).unwrap();
+        registry.register(Box::new(bytes_received.clone())).unwrap();
+        registry.register(Box::new(bytes_sent.clone())).unwrap();
+        registry
+            .register(Box::new(packets_dropped.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(authentication_failures.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(authentication_lockouts.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(connections_refused.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(hub_event_seconds.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            clients_connected,
+            messages_received,
+            messages_sent,
+            bytes_received,
+            bytes_sent,
+            packets_dropped,
+            authentication_failures,
+            authentication_lockouts,
+            connections_refused,
+            hub_event_seconds,
+        }
+    }
+
+    pub fn client_connected(&self, transport: Transport) {
+        self.clients_connected
+            .with_label_values(&[transport.name(), &transport.is_tls().to_string()])
+            .inc();
+    }
+
+    pub fn client_disconnected(&self, transport: Transport) {
+        self.clients_connected
+            .with_label_values(&[transport.name(), &transport.is_tls().to_string()])
+            .dec();
+    }
+
+    pub fn message_received(&self, message: &Message) {
+        self.messages_received
+            .with_label_values(&[format!("{:?}", message.message_type())])
+            .inc();
+        self.bytes_received.inc_by(message.size() as u64);
+    }
+
+    pub fn message_sent(&self, message: &Message) {
+        self.messages_sent
+            .with_label_values(&[format!("{:?}", message.message_type())])
+            .inc();
+        self.bytes_sent.inc_by(message.size() as u64);
+    }
+
+    pub fn packets_dropped(&self, count: usize) {
+        self.packets_dropped.inc_by(count as u64);
+    }
+
+    pub fn authentication_failed(&self, method: &str) {
+        self.authentication_failures
+            .with_label_values(&[method])
+            .inc();
+    }
+
+    /// A user or host has been locked out. The kind is "user" or "host".
+    pub fn authentication_locked_out(&self, kind: &str) {
+        self.authentication_lockouts
+            .with_label_values(&[kind])
+            .inc();
+    }
+
+    /// A connection has been refused. The reason is "unauthenticated" or
+    /// "host".
+    pub fn connection_refused(&self, reason: &str) {
+        self.connections_refused.with_label_values(&[reason]).inc();
+    }
+
+    pub fn hub_event_handled(&self, seconds: f64) {
+        self.hub_event_seconds.observe(seconds);
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn encode(&self) -> io::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Serve the metrics for Prometheus to scrape.
+pub async fn start_metrics_listener(endpoint: String) -> io::Result<()> {
+    log::info!("Listening on address {endpoint} for metrics requests.");
+
+    let app = Router::new().route("/metrics", get(get_metrics));
+
+    let listener = TcpListener::bind(&endpoint).await?;
+    axum::serve(listener, app).await
+}
+
+async fn get_metrics() -> impl IntoResponse {
+    match METRICS.encode() {
+        Ok(text) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, TextEncoder::new().format_type())],
+            text,
+        )
+            .into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_encode_metrics() {
+        let metrics = Metrics::new();
+        metrics.client_connected(Transport::TlsWebSocket);
+        metrics.message_received(&Message::Heartbeat { count: 1 });
+        metrics.packets_dropped(3);
+
+        let text = metrics.encode().unwrap();
+        assert!(
+            text.contains("squawkbus_clients_connected{tls=\"true\",transport=\"web-socket\"} 1")
+        );
+        assert!(text.contains("squawkbus_messages_received_total{message_type=\"Heartbeat\"} 1"));
+        assert!(text.contains("squawkbus_packets_dropped_total 3"));
+    }
+}
@@ -1,9 +1,14 @@
 use std::{collections::HashMap, io};
 
-use common::messages::Message;
+use common::{TopicIndex, messages::Message};
 use wildmatch::WildMatch;
 
-use crate::{clients::ClientManager, events::ServerEvent, subscriptions::SubscriptionManager};
+use crate::{
+    authorization::{AuthorizationManager, Role},
+    clients::ClientManager,
+    events::ServerEvent,
+    subscriptions::SubscriptionManager,
+};
 
 struct Notification {
     pattern: WildMatch,
@@ -20,16 +25,23 @@ impl Notification {
 }
 
 pub struct NotificationManager {
-    notifications: HashMap<String, Notification>,
+    notifications: TopicIndex<Notification>,
 }
 
 impl NotificationManager {
     pub fn new() -> NotificationManager {
         NotificationManager {
-            notifications: HashMap::new(),
+            notifications: TopicIndex::new(),
         }
     }
 
+    /// The listeners and their request counts for each topic pattern.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &HashMap<String, u32>)> {
+        self.notifications
+            .iter()
+            .map(|(pattern, notification)| (pattern, &notification.listeners))
+    }
+
     pub async fn handle_notification_request(
         &mut self,
         client_id: &str,
@@ -37,6 +49,7 @@ impl NotificationManager {
         is_add: bool,
         client_manager: &ClientManager,
         subscription_manager: &SubscriptionManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
         if is_add {
             self.add_notification(
@@ -44,6 +57,7 @@ impl NotificationManager {
                 pattern.as_str(),
                 client_manager,
                 subscription_manager,
+                authorization_manager,
             )
             .await
         } else {
@@ -58,13 +72,35 @@ impl NotificationManager {
         pattern: &str,
         client_manager: &ClientManager,
         subscription_manager: &SubscriptionManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
-        // Add or get the subscription.
-        if !self.notifications.contains_key(pattern) {
-            self.notifications
-                .insert(pattern.to_owned(), Notification::new(pattern));
+        let listener = client_manager.get(listener_id).ok_or(io::Error::new(
+            io::ErrorKind::Other,
+            format!("unknown client {listener_id}"),
+        ))?;
+
+        if !authorization_manager.is_authorized(
+            &listener.user,
+            &listener.groups,
+            pattern,
+            Role::Notifier,
+        ) {
+            log::debug!(
+                "Client {} ({}) is not authorized for notifications on \"{}\".",
+                listener_id,
+                listener.user,
+                pattern
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("not authorized for notifications on \"{pattern}\""),
+            ));
         }
-        let notification = self.notifications.get_mut(pattern).unwrap();
+
+        // Add or get the subscription.
+        let notification = self
+            .notifications
+            .get_or_insert_with(pattern, || Notification::new(pattern));
 
         if let Some(count) = notification.listeners.get_mut(listener_id) {
             *count += 1;
@@ -72,18 +108,23 @@ impl NotificationManager {
             notification.listeners.insert(listener_id.into(), 1);
         }
 
-        let listener = client_manager.get(listener_id).ok_or(io::Error::new(
-            io::ErrorKind::Other,
-            format!("unknown client {listener_id}"),
-        ))?;
-
         for (topic, subscribers) in subscription_manager.find_subscriptions(&notification.pattern) {
-            if notification.pattern.matches(topic.as_str()) {
+            if notification.pattern.matches(topic.as_str())
+                && authorization_manager.is_authorized(
+                    &listener.user,
+                    &listener.groups,
+                    &topic,
+                    Role::Notifier,
+                )
+            {
                 for (subscriber_id, count) in subscribers {
                     let client = client_manager.get(subscriber_id).ok_or(io::Error::new(
                         io::ErrorKind::Other,
                         format!("unknown client {subscriber_id}"),
                     ))?;
+                    if listener.is_federated() && client.is_federated() {
+                        continue;
+                    }
                     let message = Message::ForwardedSubscriptionRequest {
                         client_id: subscriber_id.clone(),
                         host: client.host.clone(),
@@ -92,11 +133,7 @@ impl NotificationManager {
                         count: *count,
                     };
                     let event = ServerEvent::OnMessage(message);
-                    listener
-                        .tx
-                        .send(event)
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+                    listener.send(event);
                 }
             }
         }
@@ -135,7 +172,7 @@ impl NotificationManager {
             )
         }
 
-        if notification.listeners.len() == 0 {
+        if notification.listeners.is_empty() {
             self.notifications.remove(pattern);
         }
 
@@ -149,6 +186,7 @@ impl NotificationManager {
         is_add: bool,
         count: u32,
         client_manager: &ClientManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
         log::debug!(
             "Client {} has {} a subscription for topic \"{}\".",
@@ -157,31 +195,46 @@ impl NotificationManager {
             topic
         );
 
-        for (_pattern, notification) in &self.notifications {
-            if notification.pattern.matches(topic) {
-                let subscriber = client_manager.get(&subscriber_id).ok_or(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("unknown client {subscriber_id}"),
-                ))?;
-
-                let message = Message::ForwardedSubscriptionRequest {
-                    host: subscriber.host.clone(),
-                    user: subscriber.user.clone(),
-                    client_id: subscriber_id.into(),
-                    topic: topic.to_string(),
-                    count,
-                };
-
-                for listener_id in notification.listeners.keys() {
-                    if let Some(listener) = client_manager.get(listener_id) {
-                        let event = ServerEvent::OnMessage(message.clone());
-
-                        listener
-                            .tx
-                            .send(event)
-                            .await
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        for (_pattern, notification) in self.notifications.matches(topic) {
+            let subscriber = client_manager.get(&subscriber_id).ok_or(io::Error::new(
+                io::ErrorKind::Other,
+                format!("unknown client {subscriber_id}"),
+            ))?;
+
+            let message = Message::ForwardedSubscriptionRequest {
+                host: subscriber.host.clone(),
+                user: subscriber.user.clone(),
+                client_id: subscriber_id.into(),
+                topic: topic.to_string(),
+                count,
+            };
+
+            for listener_id in notification.listeners.keys() {
+                if let Some(listener) = client_manager.get(listener_id) {
+                    // A federation link is not told about subscriptions from
+                    // other links, so subscriptions cannot loop between brokers.
+                    if listener.is_federated() && subscriber.is_federated() {
+                        log::trace!(
+                            "Not notifying federation link {listener_id} of a federated subscription; skipping."
+                        );
+                        continue;
+                    }
+
+                    if !authorization_manager.is_authorized(
+                        &listener.user,
+                        &listener.groups,
+                        topic,
+                        Role::Notifier,
+                    ) {
+                        log::trace!(
+                            "Client {listener_id} is not authorized for notifications on \"{topic}\"; skipping."
+                        );
+                        continue;
                     }
+
+                    let event = ServerEvent::OnMessage(message.clone());
+
+                    listener.send(event);
                 }
             }
         }
@@ -200,9 +253,9 @@ impl NotificationManager {
 
     fn find_listener_patterns(&self, listener_id: &str) -> Vec<String> {
         let mut patterns: Vec<String> = Vec::new();
-        for (pattern, notification) in &self.notifications {
+        for (pattern, notification) in self.notifications.iter() {
             if notification.listeners.contains_key(listener_id) {
-                patterns.push(pattern.clone());
+                patterns.push(pattern.to_string());
             }
         }
         patterns
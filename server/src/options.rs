@@ -1,16 +1,53 @@
-// Command line parameters.
+// Command line parameters and the configuration file.
 
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::marker::PhantomData;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::{collections::HashSet, io};
 
+use config::{Config, Environment, File, Map, Value};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
 use wildmatch::WildMatch;
 
+use crate::authentication::CertificateUser;
 use crate::authorization::{AuthorizationSpec, Role};
+use crate::journal::JournalSync;
+use crate::outbound::OverflowPolicy;
 
 const DEFAULT_SOCKET_ENDPOINT: &str = "0.0.0.0:8558";
 const DEFAULT_WEB_SOCKET_ENDPOINT: &str = "0.0.0.0:8559";
-const DEFAULT_HEARTBEAT_SECONDS: &str = "30";
+const DEFAULT_HEARTBEAT_SECONDS: u64 = 30;
+const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 3;
+const DEFAULT_OUTBOUND_QUEUE_CAPACITY: usize = 1024;
+const DEFAULT_OUTBOUND_QUEUE_POLICY: OverflowPolicy = OverflowPolicy::Disconnect;
+const DEFAULT_JOURNAL_SEGMENT_SIZE: u64 = 67108864;
+const DEFAULT_JOURNAL_SYNC: JournalSync = JournalSync::Segment;
+const DEFAULT_MAX_AUTHENTICATION_FAILURES: u32 = 5;
+const DEFAULT_MAX_HOST_AUTHENTICATION_FAILURES: u32 = 20;
+const DEFAULT_AUTHENTICATION_LOCKOUT_SECONDS: u64 = 300;
+const DEFAULT_AUTHENTICATION_DELAY_MILLISECONDS: u64 = 250;
+const DEFAULT_MAX_AUTHENTICATION_DELAY_MILLISECONDS: u64 = 5000;
+const DEFAULT_AUTHENTICATION_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_MAX_UNAUTHENTICATED_CONNECTIONS: usize = 1024;
+const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 0;
+
+/// Environment variables with this prefix override the configuration file.
+const ENV_PREFIX: &str = "SQUAWKBUS";
+/// The environment variable naming the configuration file.
+const ENV_CONFIG_FILE: &str = "SQUAWKBUS_CONFIG";
+/// The settings given as a list in an environment variable, separated by
+/// spaces.
+const ENV_LIST_KEYS: [&str; 4] = [
+    "authorizations",
+    "cache_patterns",
+    "journal.patterns",
+    "admin.users",
+];
 
 /// Parses the string <user-pattern>:<topic-pattern>:<entitlements>:<roles>
 impl FromStr for AuthorizationSpec {
@@ -44,25 +81,389 @@ impl FromStr for AuthorizationSpec {
     }
 }
 
+/// Authorizations in the configuration file use the command line format.
+impl<'de> Deserialize<'de> for AuthorizationSpec {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid authorization \"{s}\": {e}")))
+    }
+}
+
+fn parse_str<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+/// Settings which may be given as a single table, or a list of them.
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrMany<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a table or a list of tables")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> std::result::Result<Vec<T>, A::Error> {
+            T::deserialize(MapAccessDeserializer::new(map)).map(|value| vec![value])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> std::result::Result<Vec<T>, A::Error> {
+            Vec::deserialize(SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany(PhantomData))
+}
+
+fn optional_one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    one_or_many(deserializer).map(Some)
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct TLSOption {
     pub keyfile: PathBuf,
     pub certfile: PathBuf,
+    pub client_auth: Option<ClientAuthOption>,
+}
+
+/// Client certificates, verified against the certificates in the CA file.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuthOption {
+    pub cafile: PathBuf,
+    /// Reject clients that do not present a certificate.
+    #[serde(default = "default_required")]
+    pub required: bool,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatOption {
+    pub seconds: u64,
+    /// Close a connection when nothing has been received from the peer for
+    /// this many heartbeats. Zero never closes the connection.
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatOption {
+    fn default() -> Self {
+        HeartbeatOption {
+            seconds: DEFAULT_HEARTBEAT_SECONDS,
+            max_missed: DEFAULT_MAX_MISSED_HEARTBEATS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutboundQueueOption {
+    pub capacity: usize,
+    #[serde(deserialize_with = "parse_str")]
+    pub policy: OverflowPolicy,
+}
+
+impl Default for OutboundQueueOption {
+    fn default() -> Self {
+        OutboundQueueOption {
+            capacity: DEFAULT_OUTBOUND_QUEUE_CAPACITY,
+            policy: DEFAULT_OUTBOUND_QUEUE_POLICY,
+        }
+    }
+}
+
+/// Limits on failed authentication, by the user named in the credentials and
+/// by the host of the client.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutOption {
+    /// Lock out a user after this many failures. Zero never locks out.
+    pub max_failures: u32,
+    /// Lock out a host after this many failures, for any user. Zero never
+    /// locks out.
+    pub max_host_failures: u32,
+    /// How long a lockout lasts, and how long failures are remembered.
+    pub lockout_seconds: u64,
+    /// The delay before answering a failed request, which doubles with each
+    /// failure up to the maximum.
+    pub delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+}
+
+impl Default for LockoutOption {
+    fn default() -> Self {
+        LockoutOption {
+            max_failures: DEFAULT_MAX_AUTHENTICATION_FAILURES,
+            max_host_failures: DEFAULT_MAX_HOST_AUTHENTICATION_FAILURES,
+            lockout_seconds: DEFAULT_AUTHENTICATION_LOCKOUT_SECONDS,
+            delay_milliseconds: DEFAULT_AUTHENTICATION_DELAY_MILLISECONDS,
+            max_delay_milliseconds: DEFAULT_MAX_AUTHENTICATION_DELAY_MILLISECONDS,
+        }
+    }
+}
+
+/// Limits on the connections to a listener.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionLimitOption {
+    /// Close a connection which has not authenticated within this time,
+    /// including any TLS or web socket handshake. It must not be zero.
+    pub authentication_timeout_seconds: u64,
+    /// The most connections waiting to authenticate. Zero is unlimited.
+    pub max_unauthenticated: usize,
+    /// The most connections from one address. Zero is unlimited.
+    pub max_per_host: usize,
+}
+
+impl Default for ConnectionLimitOption {
+    fn default() -> Self {
+        ConnectionLimitOption {
+            authentication_timeout_seconds: DEFAULT_AUTHENTICATION_TIMEOUT_SECONDS,
+            max_unauthenticated: DEFAULT_MAX_UNAUTHENTICATED_CONNECTIONS,
+            max_per_host: DEFAULT_MAX_CONNECTIONS_PER_HOST,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalOption {
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default = "default_journal_segment_size")]
+    pub segment_size: u64,
+    /// The most segments to keep, removing the oldest. All are kept if not
+    /// given.
+    #[serde(default)]
+    pub max_segments: Option<usize>,
+    #[serde(default = "default_journal_sync", deserialize_with = "parse_str")]
+    pub sync: JournalSync,
+}
+
+fn default_journal_segment_size() -> u64 {
+    DEFAULT_JOURNAL_SEGMENT_SIZE
+}
+
+fn default_journal_sync() -> JournalSync {
+    DEFAULT_JOURNAL_SYNC
+}
+
+/// The credentials a federation link presents to its peer.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FederationCredentials {
+    pub method: String,
+    pub username: String,
+    pub password: Option<String>,
+    /// A file holding the password, so it need not be in the configuration.
+    pub password_file: Option<PathBuf>,
 }
 
+impl FederationCredentials {
+    /// The password. A password file is read each time, so a changed
+    /// password is used when the link next connects.
+    pub fn password(&self) -> io::Result<String> {
+        match (&self.password, &self.password_file) {
+            (Some(password), None) => Ok(password.clone()),
+            (None, Some(path)) => {
+                let password = fs::read_to_string(path)?;
+                Ok(password.trim_end_matches(['\r', '\n']).to_string())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "federation credentials need one of a password or a password file",
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FederationOption {
+    pub endpoint: String,
+    pub patterns: Vec<String>,
+    pub credentials: Option<FederationCredentials>,
+    /// Connect with TLS, trusting the certificates in this file.
+    pub cafile: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminOption {
+    pub endpoint: String,
+    /// The users who may make admin requests.
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "AuthenticationConfig")]
 pub enum AuthenticationOption {
     None,
-    Basic(PathBuf),
-    Ldap(String),
+    /// The htpasswd file, and an optional file of the groups users belong
+    /// to.
+    Basic(PathBuf, Option<PathBuf>),
+    /// The url of the server, and an optional configuration file.
+    Ldap(String, Option<PathBuf>),
+    Jwt(PathBuf),
+    Certificate(CertificateUser),
+    PeerCredentials,
+}
+
+impl AuthenticationOption {
+    /// The method a client names in its authentication request.
+    pub fn method(&self) -> &'static str {
+        match self {
+            AuthenticationOption::None => "none",
+            AuthenticationOption::Basic(..) => "basic",
+            AuthenticationOption::Ldap(..) => "ldap",
+            AuthenticationOption::Jwt(_) => "jwt",
+            AuthenticationOption::Certificate(_) => "tls",
+            AuthenticationOption::PeerCredentials => "unix",
+        }
+    }
+}
+
+fn default_authentication() -> Vec<AuthenticationOption> {
+    vec![AuthenticationOption::None]
+}
+
+/// An authentication section of the configuration file. The method selects
+/// which of the other settings is used.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuthenticationConfig {
+    method: String,
+    passwd_file: Option<PathBuf>,
+    groups_file: Option<PathBuf>,
+    url: Option<String>,
+    config_file: Option<PathBuf>,
+    user: Option<String>,
+}
+
+fn required<T>(value: Option<T>, method: &str, name: &str) -> Result<T, String> {
+    value.ok_or_else(|| format!("authentication method {method} requires {name}"))
+}
+
+impl TryFrom<AuthenticationConfig> for AuthenticationOption {
+    type Error = String;
+
+    fn try_from(config: AuthenticationConfig) -> Result<Self, Self::Error> {
+        let method = config.method.as_str();
+        match method {
+            "none" => Ok(AuthenticationOption::None),
+            "basic" => Ok(AuthenticationOption::Basic(
+                required(config.passwd_file, method, "passwd_file")?,
+                config.groups_file,
+            )),
+            "ldap" => Ok(AuthenticationOption::Ldap(
+                required(config.url, method, "url")?,
+                config.config_file,
+            )),
+            "jwt" => Ok(AuthenticationOption::Jwt(required(
+                config.config_file,
+                method,
+                "config_file",
+            )?)),
+            "unix" => Ok(AuthenticationOption::PeerCredentials),
+            "tls" => {
+                let user = required(config.user, method, "user")?;
+                Ok(AuthenticationOption::Certificate(
+                    user.parse().map_err(|e: io::Error| e.to_string())?,
+                ))
+            }
+            _ => Err(format!("invalid authentication method {method}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListenerTransport {
+    #[default]
+    Socket,
+    WebSocket,
+    /// The endpoint is the path of a Unix socket.
+    Unix,
 }
 
+/// An address the server accepts connections on.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerOption {
+    pub endpoint: String,
+    #[serde(default)]
+    pub transport: ListenerTransport,
+    pub tls: Option<TLSOption>,
+    /// The authentication methods clients may use. Defaults to those of the
+    /// server.
+    #[serde(default, deserialize_with = "optional_one_or_many")]
+    pub authentication: Option<Vec<AuthenticationOption>>,
+    /// Defaults to the heartbeat of the server.
+    pub heartbeat: Option<HeartbeatOption>,
+    /// Defaults to the connection limits of the server.
+    pub connection_limits: Option<ConnectionLimitOption>,
+}
+
+/// The server configuration. It is read from the configuration file, then
+/// overridden by `SQUAWKBUS_*` environment variables and the command line.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Options {
-    pub socket_endpoint: String,
-    pub web_socket_endpoint: String,
+    /// When no listeners are given the server listens for sockets and web
+    /// sockets on these endpoints, using the TLS option.
+    pub socket_endpoint: Option<String>,
+    pub web_socket_endpoint: Option<String>,
+    #[serde(default)]
+    pub listeners: Vec<ListenerOption>,
+    #[serde(default)]
     pub authorizations: Vec<AuthorizationSpec>,
     pub authorizations_file: Option<PathBuf>,
     pub tls: Option<TLSOption>,
-    pub authentication: AuthenticationOption,
-    pub heartbeat_seconds: u64,
+    #[serde(default = "default_authentication", deserialize_with = "one_or_many")]
+    pub authentication: Vec<AuthenticationOption>,
+    #[serde(default)]
+    pub authentication_lockout: LockoutOption,
+    #[serde(default)]
+    pub connection_limits: ConnectionLimitOption,
+    #[serde(default)]
+    pub heartbeat: HeartbeatOption,
+    #[serde(default)]
+    pub outbound_queue: OutboundQueueOption,
+    #[serde(default)]
+    pub cache_patterns: Vec<String>,
+    pub journal: Option<JournalOption>,
+    #[serde(default)]
+    pub federations: Vec<FederationOption>,
+    pub admin: Option<AdminOption>,
+    pub metrics_endpoint: Option<String>,
+    /// Validate the configuration and exit.
+    #[serde(skip)]
+    pub check_config: bool,
 }
 
 fn fetch_arg(arg_name: &str, args: &[String], arg_index: &mut usize) -> io::Result<String> {
@@ -113,20 +514,66 @@ fn check_fetch_two_args<T>(
     Ok((arg1, arg2))
 }
 
+/// Set a configuration key from the command line.
+fn set_override<T: Into<Value>>(
+    overrides: &mut Vec<(&str, Value)>,
+    key: &'static str,
+    value: Option<T>,
+) {
+    if let Some(value) = value {
+        overrides.push((key, value.into()));
+    }
+}
+
 impl Options {
-    pub fn parse(args: &[String]) -> io::Result<Self> {
+    /// Parse the command line, reading the configuration file it names, if
+    /// any, and the environment.
+    pub fn parse(args: &[String], mut env: HashMap<String, String>) -> io::Result<Self> {
+        let mut config_file: Option<PathBuf> = env.remove(ENV_CONFIG_FILE).map(PathBuf::from);
+        let mut check_config = false;
         let mut socket_endpoint: Option<String> = None;
         let mut websocket_endpoint: Option<String> = None;
-        let mut authorizations: Vec<AuthorizationSpec> = Vec::new();
-        let mut authorizations_file: Option<PathBuf> = None;
-        let mut tls: Option<TLSOption> = None;
-        let mut authentication: Option<AuthenticationOption> = None;
+        let mut authorizations: Vec<String> = Vec::new();
+        let mut authorizations_file: Option<String> = None;
+        let mut tls: Option<(String, String)> = None;
+        let mut tls_client_auth: Option<(bool, String)> = None;
+        let mut authentication: Vec<(String, Option<(&str, String)>)> = Vec::new();
+        let mut authentication_groups_file: Option<String> = None;
+        let mut max_authentication_failures: Option<String> = None;
+        let mut authentication_lockout_seconds: Option<String> = None;
+        let mut authentication_timeout_seconds: Option<String> = None;
+        let mut max_unauthenticated_connections: Option<String> = None;
+        let mut max_connections_per_host: Option<String> = None;
         let mut heartbeat_seconds: Option<String> = None;
+        let mut max_missed_heartbeats: Option<String> = None;
+        let mut outbound_queue_capacity: Option<String> = None;
+        let mut outbound_queue_policy: Option<String> = None;
+        let mut cache_patterns: Vec<String> = Vec::new();
+        let mut journal_directory: Option<String> = None;
+        let mut journal_patterns: Vec<String> = Vec::new();
+        let mut journal_segment_size: Option<String> = None;
+        let mut journal_max_segments: Option<String> = None;
+        let mut journal_sync: Option<String> = None;
+        let mut federation_patterns: Vec<(String, String)> = Vec::new();
+        let mut federation_credentials: Option<FederationCredentials> = None;
+        let mut federation_cafile: Option<String> = None;
+        let mut admin_endpoint: Option<String> = None;
+        let mut admin_users: Vec<String> = Vec::new();
+        let mut metrics_endpoint: Option<String> = None;
 
         let mut arg_index = 1;
+        let mut is_config_file_arg = false;
         while arg_index < args.len() {
             let arg_name = args.get(arg_index).unwrap().as_str();
             match arg_name {
+                "--config" => {
+                    let current_value = is_config_file_arg.then_some(());
+                    let filename =
+                        check_fetch_arg(arg_name, &current_value, &args, &mut arg_index)?;
+                    config_file = Some(filename.into());
+                    is_config_file_arg = true;
+                }
+                "--check-config" => check_config = true,
                 "--socket-endpoint" => {
                     let endpoint =
                         check_fetch_arg(arg_name, &socket_endpoint, &args, &mut arg_index)?;
@@ -139,48 +586,186 @@ impl Options {
                 }
                 "--authorization" => {
                     let authorization = fetch_arg(arg_name, &args, &mut arg_index)?;
-                    let authorization = authorization
-                        .parse()
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
                     authorizations.push(authorization);
                 }
                 "--authorizations-file" => {
                     let filename =
                         check_fetch_arg(arg_name, &authorizations_file, &args, &mut arg_index)?;
-                    authorizations_file = Some(filename.into());
+                    authorizations_file = Some(filename);
                 }
                 "--tls" => {
-                    let (certfile, keyfile) =
-                        check_fetch_two_args(arg_name, &tls, &args, &mut arg_index)?;
-                    tls = Some(TLSOption {
-                        certfile: certfile.into(),
-                        keyfile: keyfile.into(),
-                    });
+                    let files = check_fetch_two_args(arg_name, &tls, &args, &mut arg_index)?;
+                    tls = Some(files);
+                }
+                "--tls-client-auth" => {
+                    let (mode, cafile) =
+                        check_fetch_two_args(arg_name, &tls_client_auth, &args, &mut arg_index)?;
+                    let required = match mode.as_str() {
+                        "required" => true,
+                        "optional" => false,
+                        _ => Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            "invalid tls client auth option",
+                        ))?,
+                    };
+                    tls_client_auth = Some((required, cafile));
                 }
                 "--authentication" => {
-                    let method = check_fetch_arg(arg_name, &authentication, &args, &mut arg_index)?;
-                    authentication = Some(match method.as_str() {
-                        "none" => AuthenticationOption::None,
-                        "basic" => {
-                            let filename =
-                                check_fetch_arg(arg_name, &authentication, &args, &mut arg_index)?;
-                            AuthenticationOption::Basic(filename.into())
-                        }
-                        "ldap" => {
-                            let url =
-                                check_fetch_arg(arg_name, &authentication, &args, &mut arg_index)?;
-                            AuthenticationOption::Ldap(url)
-                        }
+                    let method = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    // The setting which goes with the method.
+                    let key = match method.as_str() {
+                        "none" | "unix" => None,
+                        "basic" => Some("passwd_file"),
+                        "ldap" => Some("url"),
+                        "jwt" => Some("config_file"),
+                        "tls" => Some("user"),
                         _ => Err(io::Error::new(
                             io::ErrorKind::Other,
                             "invalid authentication option",
                         ))?,
-                    });
+                    };
+                    let setting = match key {
+                        Some(key) => Some((key, fetch_arg(arg_name, &args, &mut arg_index)?)),
+                        None => None,
+                    };
+                    authentication.push((method, setting));
+                }
+                "--authentication-groups-file" => {
+                    let path = check_fetch_arg(
+                        arg_name,
+                        &authentication_groups_file,
+                        &args,
+                        &mut arg_index,
+                    )?;
+                    authentication_groups_file = Some(path);
+                }
+                "--max-authentication-failures" => {
+                    let count = check_fetch_arg(
+                        arg_name,
+                        &max_authentication_failures,
+                        &args,
+                        &mut arg_index,
+                    )?;
+                    max_authentication_failures = Some(count);
+                }
+                "--authentication-lockout-seconds" => {
+                    let seconds = check_fetch_arg(
+                        arg_name,
+                        &authentication_lockout_seconds,
+                        &args,
+                        &mut arg_index,
+                    )?;
+                    authentication_lockout_seconds = Some(seconds);
+                }
+                "--authentication-timeout-seconds" => {
+                    let seconds = check_fetch_arg(
+                        arg_name,
+                        &authentication_timeout_seconds,
+                        &args,
+                        &mut arg_index,
+                    )?;
+                    authentication_timeout_seconds = Some(seconds);
+                }
+                "--max-unauthenticated-connections" => {
+                    let count = check_fetch_arg(
+                        arg_name,
+                        &max_unauthenticated_connections,
+                        &args,
+                        &mut arg_index,
+                    )?;
+                    max_unauthenticated_connections = Some(count);
+                }
+                "--max-connections-per-host" => {
+                    let count = check_fetch_arg(
+                        arg_name,
+                        &max_connections_per_host,
+                        &args,
+                        &mut arg_index,
+                    )?;
+                    max_connections_per_host = Some(count);
                 }
                 "--heartbeat-seconds" => {
                     let seconds =
                         check_fetch_arg(arg_name, &heartbeat_seconds, &args, &mut arg_index)?;
-                    heartbeat_seconds = Some(seconds.into());
+                    heartbeat_seconds = Some(seconds);
+                }
+                "--max-missed-heartbeats" => {
+                    let count =
+                        check_fetch_arg(arg_name, &max_missed_heartbeats, &args, &mut arg_index)?;
+                    max_missed_heartbeats = Some(count);
+                }
+                "--outbound-queue-capacity" => {
+                    let capacity =
+                        check_fetch_arg(arg_name, &outbound_queue_capacity, &args, &mut arg_index)?;
+                    outbound_queue_capacity = Some(capacity);
+                }
+                "--outbound-queue-policy" => {
+                    let policy =
+                        check_fetch_arg(arg_name, &outbound_queue_policy, &args, &mut arg_index)?;
+                    outbound_queue_policy = Some(policy);
+                }
+                "--cache" => {
+                    let pattern = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    cache_patterns.push(pattern);
+                }
+                "--journal-directory" => {
+                    let directory =
+                        check_fetch_arg(arg_name, &journal_directory, &args, &mut arg_index)?;
+                    journal_directory = Some(directory);
+                }
+                "--journal" => {
+                    let pattern = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    journal_patterns.push(pattern);
+                }
+                "--journal-segment-size" => {
+                    let size =
+                        check_fetch_arg(arg_name, &journal_segment_size, &args, &mut arg_index)?;
+                    journal_segment_size = Some(size);
+                }
+                "--journal-max-segments" => {
+                    let count =
+                        check_fetch_arg(arg_name, &journal_max_segments, &args, &mut arg_index)?;
+                    journal_max_segments = Some(count);
+                }
+                "--journal-sync" => {
+                    let sync = check_fetch_arg(arg_name, &journal_sync, &args, &mut arg_index)?;
+                    journal_sync = Some(sync);
+                }
+                "--federation" => {
+                    let endpoint = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    let pattern = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    federation_patterns.push((endpoint, pattern));
+                }
+                "--federation-authentication" => {
+                    let method =
+                        check_fetch_arg(arg_name, &federation_credentials, &args, &mut arg_index)?;
+                    let username = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    let password_file = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    federation_credentials = Some(FederationCredentials {
+                        method,
+                        username,
+                        password: None,
+                        password_file: Some(password_file.into()),
+                    });
+                }
+                "--federation-cafile" => {
+                    let cafile =
+                        check_fetch_arg(arg_name, &federation_cafile, &args, &mut arg_index)?;
+                    federation_cafile = Some(cafile);
+                }
+                "--admin-endpoint" => {
+                    let endpoint =
+                        check_fetch_arg(arg_name, &admin_endpoint, &args, &mut arg_index)?;
+                    admin_endpoint = Some(endpoint);
+                }
+                "--admin-user" => {
+                    let user = fetch_arg(arg_name, &args, &mut arg_index)?;
+                    admin_users.push(user);
+                }
+                "--metrics-endpoint" => {
+                    let endpoint =
+                        check_fetch_arg(arg_name, &metrics_endpoint, &args, &mut arg_index)?;
+                    metrics_endpoint = Some(endpoint);
                 }
                 "--help" => Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -195,31 +780,321 @@ impl Options {
             arg_index += 1
         }
 
-        // Default socket endpoint
-        let socket_endpoint = socket_endpoint
-            .or(Some(DEFAULT_SOCKET_ENDPOINT.into()))
-            .unwrap();
-        // Default websocket endpoint
-        let websocket_endpoint = websocket_endpoint
-            .or(Some(DEFAULT_WEB_SOCKET_ENDPOINT.into()))
-            .unwrap();
-        // Default authentication to none
-        let authentication = authentication.or(Some(AuthenticationOption::None)).unwrap();
-        let heartbeat_seconds = heartbeat_seconds
-            .or(Some(DEFAULT_HEARTBEAT_SECONDS.into()))
-            .unwrap()
-            .parse::<u64>()
-            .unwrap();
-
-        return Ok(Self {
-            socket_endpoint,
-            web_socket_endpoint: websocket_endpoint,
-            authorizations,
-            authorizations_file,
-            tls,
-            authentication,
-            heartbeat_seconds,
-        });
+        // The command line overrides the configuration file and environment.
+        let mut overrides: Vec<(&str, Value)> = Vec::new();
+        set_override(&mut overrides, "socket_endpoint", socket_endpoint);
+        set_override(&mut overrides, "web_socket_endpoint", websocket_endpoint);
+        set_override(
+            &mut overrides,
+            "authorizations",
+            (!authorizations.is_empty()).then_some(authorizations),
+        );
+        set_override(&mut overrides, "authorizations_file", authorizations_file);
+        if let Some((certfile, keyfile)) = tls {
+            set_override(&mut overrides, "tls.certfile", Some(certfile));
+            set_override(&mut overrides, "tls.keyfile", Some(keyfile));
+        }
+        if let Some((required, cafile)) = tls_client_auth {
+            set_override(&mut overrides, "tls.client_auth.cafile", Some(cafile));
+            set_override(&mut overrides, "tls.client_auth.required", Some(required));
+        }
+        if authentication_groups_file.is_some()
+            && !authentication.iter().any(|(method, _)| method == "basic")
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "--authentication-groups-file requires --authentication basic",
+            ));
+        }
+        let authentication: Vec<Map<String, Value>> = authentication
+            .into_iter()
+            .map(|(method, setting)| {
+                let mut section: Map<String, Value> = Map::new();
+                if let Some(groups_file) = &authentication_groups_file
+                    && method == "basic"
+                {
+                    section.insert("groups_file".into(), groups_file.as_str().into());
+                }
+                section.insert("method".into(), method.into());
+                if let Some((key, value)) = setting {
+                    section.insert(key.into(), value.into());
+                }
+                section
+            })
+            .collect();
+        set_override(
+            &mut overrides,
+            "authentication",
+            (!authentication.is_empty()).then_some(authentication),
+        );
+        set_override(
+            &mut overrides,
+            "authentication_lockout.max_failures",
+            max_authentication_failures,
+        );
+        set_override(
+            &mut overrides,
+            "authentication_lockout.lockout_seconds",
+            authentication_lockout_seconds,
+        );
+        set_override(
+            &mut overrides,
+            "connection_limits.authentication_timeout_seconds",
+            authentication_timeout_seconds,
+        );
+        set_override(
+            &mut overrides,
+            "connection_limits.max_unauthenticated",
+            max_unauthenticated_connections,
+        );
+        set_override(
+            &mut overrides,
+            "connection_limits.max_per_host",
+            max_connections_per_host,
+        );
+        set_override(&mut overrides, "heartbeat.seconds", heartbeat_seconds);
+        set_override(
+            &mut overrides,
+            "heartbeat.max_missed",
+            max_missed_heartbeats,
+        );
+        set_override(
+            &mut overrides,
+            "outbound_queue.capacity",
+            outbound_queue_capacity,
+        );
+        set_override(
+            &mut overrides,
+            "outbound_queue.policy",
+            outbound_queue_policy,
+        );
+        set_override(
+            &mut overrides,
+            "cache_patterns",
+            (!cache_patterns.is_empty()).then_some(cache_patterns),
+        );
+        set_override(&mut overrides, "journal.directory", journal_directory);
+        set_override(
+            &mut overrides,
+            "journal.patterns",
+            (!journal_patterns.is_empty()).then_some(journal_patterns),
+        );
+        set_override(&mut overrides, "journal.segment_size", journal_segment_size);
+        set_override(&mut overrides, "journal.max_segments", journal_max_segments);
+        set_override(&mut overrides, "journal.sync", journal_sync);
+        set_override(
+            &mut overrides,
+            "federations",
+            federations_override(
+                federation_patterns,
+                federation_credentials,
+                federation_cafile,
+            ),
+        );
+        set_override(&mut overrides, "admin.endpoint", admin_endpoint);
+        set_override(
+            &mut overrides,
+            "admin.users",
+            (!admin_users.is_empty()).then_some(admin_users),
+        );
+        set_override(&mut overrides, "metrics_endpoint", metrics_endpoint);
+
+        let mut environment = Environment::with_prefix(ENV_PREFIX)
+            .prefix_separator("_")
+            .separator("__")
+            .list_separator(" ")
+            .try_parsing(true)
+            .source(Some(env.into_iter().collect()));
+        for key in ENV_LIST_KEYS {
+            environment = environment.with_list_parse_key(key);
+        }
+
+        let mut builder = Config::builder();
+        if let Some(config_file) = &config_file {
+            builder = builder.add_source(File::from(config_file.as_path()));
+        }
+        builder = builder.add_source(environment);
+        for (key, value) in overrides {
+            builder = builder.set_override(key, value).map_err(invalid_config)?;
+        }
+
+        let mut options: Options = builder
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(invalid_config)?;
+        options.check_config = check_config;
+        options.make_default_listeners()?;
+        options.validate()?;
+
+        Ok(options)
+    }
+
+    /// Listen for sockets and web sockets when no listeners are given.
+    fn make_default_listeners(&mut self) -> io::Result<()> {
+        if !self.listeners.is_empty() {
+            if self.socket_endpoint.is_some()
+                || self.web_socket_endpoint.is_some()
+                || self.tls.is_some()
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "socket_endpoint, web_socket_endpoint and tls cannot be used with listeners",
+                ))?;
+            }
+            return Ok(());
+        }
+
+        self.listeners = vec![
+            ListenerOption {
+                endpoint: self
+                    .socket_endpoint
+                    .clone()
+                    .unwrap_or(DEFAULT_SOCKET_ENDPOINT.into()),
+                transport: ListenerTransport::Socket,
+                tls: self.tls.clone(),
+                authentication: None,
+                heartbeat: None,
+                connection_limits: None,
+            },
+            ListenerOption {
+                endpoint: self
+                    .web_socket_endpoint
+                    .clone()
+                    .unwrap_or(DEFAULT_WEB_SOCKET_ENDPOINT.into()),
+                transport: ListenerTransport::WebSocket,
+                tls: self.tls.clone(),
+                authentication: None,
+                heartbeat: None,
+                connection_limits: None,
+            },
+        ];
+
+        Ok(())
+    }
+
+    /// Check the settings which depend on each other.
+    fn validate(&self) -> io::Result<()> {
+        if self.heartbeat.seconds == 0
+            || self.listeners.iter().any(|listener| {
+                listener
+                    .heartbeat
+                    .is_some_and(|heartbeat| heartbeat.seconds == 0)
+            })
+        {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the heartbeat seconds must be greater than zero",
+            ))?;
+        }
+        if self.outbound_queue.capacity == 0 {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the outbound queue capacity must be greater than zero",
+            ))?;
+        }
+
+        for listener in &self.listeners {
+            let authentication = listener
+                .authentication
+                .as_ref()
+                .unwrap_or(&self.authentication);
+            let mut methods = HashSet::new();
+            if let Some(option) = authentication
+                .iter()
+                .find(|option| !methods.insert(option.method()))
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "authentication method {} is given more than once",
+                        option.method()
+                    ),
+                ))?;
+            }
+            let is_unix = listener.transport == ListenerTransport::Unix;
+            if is_unix && listener.tls.is_some() {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("unix socket {} cannot use tls", listener.endpoint),
+                ))?;
+            }
+            if methods.contains("unix") && !is_unix {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "unix authentication on {} requires a unix socket",
+                        listener.endpoint
+                    ),
+                ))?;
+            }
+            if methods.contains("tls")
+                && listener
+                    .tls
+                    .as_ref()
+                    .is_none_or(|tls| tls.client_auth.is_none())
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "tls authentication on {} requires tls client auth",
+                        listener.endpoint
+                    ),
+                ))?;
+            }
+            let connection_limits = listener.connection_limits.unwrap_or(self.connection_limits);
+            if connection_limits.authentication_timeout_seconds == 0 {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "the authentication timeout on {} must be greater than zero",
+                        listener.endpoint
+                    ),
+                ))?;
+            }
+        }
+
+        if let Some(journal) = &self.journal {
+            if journal.segment_size == 0 {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the journal segment size must be greater than zero",
+                ))?;
+            }
+            if journal.max_segments == Some(0) {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the journal max segments must be greater than zero",
+                ))?;
+            }
+        }
+
+        for federation in &self.federations {
+            if let Some(credentials) = &federation.credentials
+                && credentials.method != "basic"
+                && credentials.method != "ldap"
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "invalid federation authentication method {}",
+                        credentials.method
+                    ),
+                ))?;
+            }
+            if let Some(credentials) = &federation.credentials
+                && credentials.password.is_some() == credentials.password_file.is_some()
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "the federation credentials for {} need one of a password or a password file",
+                        federation.endpoint
+                    ),
+                ))?;
+            }
+        }
+
+        Ok(())
     }
 
     pub fn usage(prog_name: &str) -> String {
@@ -228,22 +1103,54 @@ impl Options {
             \t{prog_name} [<options>]
             
             options:
+            \t--config <filename> # a YAML or TOML file, or from {ENV_CONFIG_FILE}
+            \t--check-config # validate the configuration and exit
             \t--socket-endpoint <ip-address>:<port> # defaults to {DEFAULT_SOCKET_ENDPOINT}
             \t--web-socket-endpoint <ip-address>:<port> # defaults to {DEFAULT_WEB_SOCKET_ENDPOINT}
             \t--tls <certfile> <keyfile>
-            \t--authentication none # the default
+            \t--tls-client-auth required|optional <cafile>
+            \t--authentication none # the default, and may be repeated for several methods
             \t--authentication basic <passwd-file>
+            \t--authentication-groups-file <groups-file> # the groups of the users of basic authentication
             \t--authentication ldap <url>
+            \t--authentication jwt <config-file>
+            \t--authentication tls cn|san-email|san-dns|san-uri
+            \t--authentication unix # for unix socket listeners
+            \t--max-authentication-failures <count> # defaults to {DEFAULT_MAX_AUTHENTICATION_FAILURES}, zero never locking out
+            \t--authentication-lockout-seconds <seconds> # defaults to {DEFAULT_AUTHENTICATION_LOCKOUT_SECONDS}
+            \t--authentication-timeout-seconds <seconds> # defaults to {DEFAULT_AUTHENTICATION_TIMEOUT_SECONDS}
+            \t--max-unauthenticated-connections <count> # per listener, defaults to {DEFAULT_MAX_UNAUTHENTICATED_CONNECTIONS}, zero for no limit
+            \t--max-connections-per-host <count> # per listener, defaults to {DEFAULT_MAX_CONNECTIONS_PER_HOST}, no limit
             \t--authorizations-file <filename>
             \t--authorization <user:topic:entitlements:roles>
             \t--heartbeat-seconds <seconds> # defaults to {DEFAULT_HEARTBEAT_SECONDS}
+            \t--max-missed-heartbeats <count> # defaults to {DEFAULT_MAX_MISSED_HEARTBEATS}, zero never closes
+            \t--outbound-queue-capacity <messages> # defaults to {DEFAULT_OUTBOUND_QUEUE_CAPACITY}
+            \t--outbound-queue-policy disconnect|drop-oldest|drop-newest|conflate-by-topic # defaults to {DEFAULT_OUTBOUND_QUEUE_POLICY}
+            \t--cache <topic-pattern>
+            \t--journal-directory <directory>
+            \t--journal <topic-pattern>
+            \t--journal-segment-size <bytes> # defaults to {DEFAULT_JOURNAL_SEGMENT_SIZE}
+            \t--journal-max-segments <count> # defaults to keeping every segment
+            \t--journal-sync never|segment|always # defaults to {DEFAULT_JOURNAL_SYNC}
+            \t--federation <host>:<port> <topic-pattern>
+            \t--federation-authentication basic|ldap <username> <password-file>
+            \t--federation-cafile <cafile>
+            \t--admin-endpoint <ip-address>:<port>
+            \t--admin-user <user>
+            \t--metrics-endpoint <ip-address>:<port>
+
+            Settings in the configuration file can also be given by environment
+            variables, such as {ENV_PREFIX}_HEARTBEAT__SECONDS, with \"__\" between
+            the names of nested settings. The command line overrides the
+            environment, which overrides the configuration file.
             "
         )
     }
 
     pub fn load() -> io::Result<Self> {
         let args: Vec<String> = std::env::args().collect();
-        match Self::parse(&args) {
+        match Self::parse(&args, std::env::vars().collect()) {
             Ok(args) => Ok(args),
             Err(error) => {
                 let prog_name = args.get(0).unwrap();
@@ -255,6 +1162,58 @@ impl Options {
     }
 }
 
+/// The federation links given on the command line, with the patterns grouped
+/// by endpoint, keeping the order of the links.
+fn federations_override(
+    patterns: Vec<(String, String)>,
+    credentials: Option<FederationCredentials>,
+    cafile: Option<String>,
+) -> Option<Vec<Map<String, Value>>> {
+    let mut federations: Vec<(String, Vec<String>)> = Vec::new();
+    for (endpoint, pattern) in patterns {
+        match federations.iter_mut().find(|(other, _)| *other == endpoint) {
+            Some((_, patterns)) => patterns.push(pattern),
+            None => federations.push((endpoint, vec![pattern])),
+        }
+    }
+    if federations.is_empty() {
+        return None;
+    }
+
+    let federations = federations
+        .into_iter()
+        .map(|(endpoint, patterns)| {
+            let mut federation: Map<String, Value> = Map::new();
+            federation.insert("endpoint".into(), endpoint.into());
+            federation.insert("patterns".into(), patterns.into());
+            if let Some(credentials) = &credentials {
+                let mut section: Map<String, Value> = Map::new();
+                section.insert("method".into(), credentials.method.clone().into());
+                section.insert("username".into(), credentials.username.clone().into());
+                if let Some(password_file) = &credentials.password_file {
+                    section.insert(
+                        "password_file".into(),
+                        password_file.to_string_lossy().into_owned().into(),
+                    );
+                }
+                federation.insert("credentials".into(), section.into());
+            }
+            if let Some(cafile) = &cafile {
+                federation.insert("cafile".into(), cafile.clone().into());
+            }
+            federation
+        })
+        .collect();
+    Some(federations)
+}
+
+fn invalid_config(error: config::ConfigError) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("invalid configuration: {}", error),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use crate::authorization::AuthorizationManager;
@@ -268,8 +1227,208 @@ mod test {
         let user_entitlements_spec = vec![spec];
         let entitlements_manager = AuthorizationManager::new(user_entitlements_spec);
 
-        let actual = entitlements_manager.entitlements("nobody", "PUB.foo", Role::Subscriber);
+        let actual = entitlements_manager.entitlements("nobody", &[], "PUB.foo", Role::Subscriber);
         let expected: HashSet<i32> = HashSet::from([1, 2]);
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn should_override_the_config_file() {
+        let path = std::env::temp_dir().join(format!("{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "socket_endpoint: 127.0.0.1:9000\n\
+            heartbeat:\n  seconds: 10\n  max_missed: 2\n\
+            authentication:\n  method: jwt\n  config_file: jwt.yaml\n",
+        )
+        .unwrap();
+        let parse = |args: &[&str], env: &[(&str, &str)]| {
+            let args: Vec<String> = std::iter::once("squawkbus")
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect();
+            let mut env: HashMap<String, String> = env
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            env.insert(ENV_CONFIG_FILE.into(), path.to_string_lossy().into());
+            Options::parse(&args, env)
+        };
+
+        let options = parse(&[], &[]).unwrap();
+        assert_eq!(options.listeners[0].endpoint, "127.0.0.1:9000");
+        assert_eq!(options.listeners[1].endpoint, DEFAULT_WEB_SOCKET_ENDPOINT);
+        assert_eq!(options.heartbeat.seconds, 10);
+        assert!(matches!(
+            options.authentication[..],
+            [AuthenticationOption::Jwt(_)]
+        ));
+
+        let env = [("SQUAWKBUS_HEARTBEAT__SECONDS", "20")];
+        let options = parse(&[], &env).unwrap();
+        assert_eq!(options.heartbeat.seconds, 20);
+        assert_eq!(options.heartbeat.max_missed, 2);
+
+        let options = parse(
+            &["--heartbeat-seconds", "40", "--authentication", "none"],
+            &env,
+        )
+        .unwrap();
+        assert_eq!(options.heartbeat.seconds, 40);
+        assert!(matches!(
+            options.authentication[..],
+            [AuthenticationOption::None]
+        ));
+
+        assert!(parse(&[], &[("SQUAWKBUS_HEARTBEAT__SECONDS", "soon")]).is_err());
+        assert!(parse(&[], &[("SQUAWKBUS_UNKNOWN", "1")]).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_configure_listeners() {
+        let path = std::env::temp_dir().join(format!("{}.yaml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "listeners:\n\
+            - endpoint: 127.0.0.1:8558\n  authentication: { method: none }\n\
+            - endpoint: 127.0.0.1:8560\n  authentication:\n\
+            \x20 - { method: none }\n\
+            \x20 - { method: basic, passwd_file: passwd }\n\
+            - endpoint: 0.0.0.0:8559\n  transport: web-socket\n  heartbeat: { seconds: 5 }\n",
+        )
+        .unwrap();
+        let args = |args: &[&str]| -> Vec<String> {
+            std::iter::once("squawkbus")
+                .chain(["--config", path.to_str().unwrap()])
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect()
+        };
+
+        let options = Options::parse(&args(&[]), HashMap::new()).unwrap();
+        assert_eq!(options.listeners.len(), 3);
+        assert_eq!(
+            options.listeners[0].authentication.as_ref().unwrap().len(),
+            1
+        );
+        assert_eq!(
+            options.listeners[1].authentication.as_ref().unwrap().len(),
+            2
+        );
+        assert!(options.listeners[2].transport == ListenerTransport::WebSocket);
+        assert_eq!(options.listeners[2].heartbeat.unwrap().seconds, 5);
+
+        // The endpoints of the default listeners cannot be mixed in.
+        let args = args(&["--socket-endpoint", "0.0.0.0:9000"]);
+        assert!(Options::parse(&args, HashMap::new()).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_read_the_federation_password_from_a_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        let password_file = directory.join("password");
+        std::fs::write(&password_file, "secret\n").unwrap();
+        let config_file = directory.join("config.yaml");
+        let args = |args: &[&str]| -> Vec<String> {
+            std::iter::once("squawkbus")
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect()
+        };
+
+        let options = Options::parse(
+            &args(&[
+                "--federation",
+                "broker2:8558",
+                "LSE.*",
+                "--federation-authentication",
+                "basic",
+                "bridge",
+                password_file.to_str().unwrap(),
+            ]),
+            HashMap::new(),
+        )
+        .unwrap();
+        let credentials = options.federations[0].credentials.as_ref().unwrap();
+        assert_eq!(credentials.username, "bridge");
+        assert_eq!(credentials.password().unwrap(), "secret");
+
+        // Only one of the password and the password file may be given.
+        std::fs::write(
+            &config_file,
+            format!(
+                "federations:\n\
+                - endpoint: broker2:8558\n  patterns: [\"LSE.*\"]\n  credentials:\n\
+                \x20   {{ method: basic, username: bridge, password: secret, password_file: {} }}\n",
+                password_file.display()
+            ),
+        )
+        .unwrap();
+        let args = args(&["--config", config_file.to_str().unwrap()]);
+        assert!(Options::parse(&args, HashMap::new()).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn should_give_the_groups_file_to_basic_authentication() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = std::iter::once("squawkbus")
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect();
+            Options::parse(&args, HashMap::new())
+        };
+
+        let options = parse(&[
+            "--authentication",
+            "none",
+            "--authentication",
+            "basic",
+            "passwd",
+            "--authentication-groups-file",
+            "groups",
+        ])
+        .unwrap();
+        let [
+            AuthenticationOption::None,
+            AuthenticationOption::Basic(path, groups_path),
+        ] = &options.authentication[..]
+        else {
+            panic!("expected none and basic authentication");
+        };
+        assert_eq!(path, &PathBuf::from("passwd"));
+        assert_eq!(groups_path, &Some(PathBuf::from("groups")));
+
+        assert!(parse(&["--authentication-groups-file", "groups"]).is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_settings() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = std::iter::once("squawkbus")
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect();
+            Options::parse(&args, HashMap::new())
+        };
+
+        assert!(parse(&["--authentication-timeout-seconds", "1"]).is_ok());
+        assert!(parse(&["--authentication-timeout-seconds", "0"]).is_err());
+        assert!(parse(&["--heartbeat-seconds", "0"]).is_err());
+        assert!(parse(&["--outbound-queue-capacity", "0"]).is_err());
+        let journal = ["--journal-directory", "journal"];
+        assert!(parse(&[&journal[..], &["--journal-max-segments", "2"]].concat()).is_ok());
+        assert!(parse(&[&journal[..], &["--journal-max-segments", "0"]].concat()).is_err());
+        assert!(parse(&[&journal[..], &["--journal-segment-size", "0"]].concat()).is_err());
+        assert!(parse(&[&journal[..], &["--journal-sync", "sometimes"]].concat()).is_err());
+        // Zero is allowed where it disables a limit.
+        assert!(parse(&["--max-missed-heartbeats", "0"]).is_ok());
+        assert!(parse(&["--max-unauthenticated-connections", "0"]).is_ok());
+    }
 }
@@ -0,0 +1,497 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use common::messages::Message;
+
+use crate::events::ServerEvent;
+
+/// What to do when a client's outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Close the client connection.
+    Disconnect,
+    /// Discard the oldest queued event to make room for the new one.
+    DropOldest,
+    /// Discard the new event.
+    DropNewest,
+    /// Replace a queued multicast message for the same topic with the new
+    /// one, otherwise discard the oldest queued event.
+    ConflateByTopic,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "conflate-by-topic" => Ok(OverflowPolicy::ConflateByTopic),
+            _ => Err(format!("invalid overflow policy {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowPolicy::Disconnect => write!(f, "disconnect"),
+            OverflowPolicy::DropOldest => write!(f, "drop-oldest"),
+            OverflowPolicy::DropNewest => write!(f, "drop-newest"),
+            OverflowPolicy::ConflateByTopic => write!(f, "conflate-by-topic"),
+        }
+    }
+}
+
+/// The outcome of queuing an event for a client.
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    Queued,
+    /// The queue was full and the policy was applied. The count is the
+    /// number of events dropped since the client was last told.
+    Overflowed(OverflowPolicy, u64),
+    Closed,
+}
+
+struct State {
+    events: VecDeque<ServerEvent>,
+    dropped: u64,
+    is_closed: bool,
+}
+
+struct Shared {
+    capacity: usize,
+    policy: OverflowPolicy,
+    state: Mutex<State>,
+    notify: Notify,
+    /// Notified when an event is taken from the queue, or it closes.
+    space: Notify,
+}
+
+impl Shared {
+    fn close(&self) {
+        self.state.lock().unwrap().is_closed = true;
+        self.notify.notify_one();
+        self.space.notify_waiters();
+    }
+}
+
+/// Create a bounded queue of events from the hub to a single client.
+///
+/// Unlike a channel the sender never waits: when the queue is full the
+/// overflow policy is applied immediately, so one slow client cannot hold up
+/// the hub.
+pub fn channel(capacity: usize, policy: OverflowPolicy) -> (OutboundSender, OutboundReceiver) {
+    let shared = Arc::new(Shared {
+        capacity,
+        policy,
+        state: Mutex::new(State {
+            events: VecDeque::with_capacity(capacity),
+            dropped: 0,
+            is_closed: false,
+        }),
+        notify: Notify::new(),
+        space: Notify::new(),
+    });
+    (
+        OutboundSender {
+            shared: shared.clone(),
+        },
+        OutboundReceiver { shared },
+    )
+}
+
+pub struct OutboundSender {
+    shared: Arc<Shared>,
+}
+
+impl OutboundSender {
+    /// Close the queue. The client is disconnected once it has been sent the
+    /// events already queued.
+    pub fn close(&self) {
+        self.shared.close()
+    }
+
+    /// A sender which waits for room in the queue.
+    pub fn waiting(&self) -> WaitingSender {
+        WaitingSender {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Queue an event. Only data is ever dropped for the overflow policy:
+    /// replies and heartbeats are queued even when the queue is full, as a
+    /// client may be waiting on them.
+    pub fn send(&self, event: ServerEvent) -> Delivery {
+        let mut state = self.shared.state.lock().unwrap();
+
+        if state.is_closed {
+            return Delivery::Closed;
+        }
+
+        if state.events.len() < self.shared.capacity || !is_data(&event) {
+            state.events.push_back(event);
+            drop(state);
+            self.shared.notify.notify_one();
+            return Delivery::Queued;
+        }
+
+        match self.shared.policy {
+            OverflowPolicy::Disconnect => {
+                // Discard everything; the receiver reports the overflow then
+                // closes.
+                state.dropped += state.events.len() as u64 + 1;
+                state.events.clear();
+                state.is_closed = true;
+            }
+            OverflowPolicy::DropOldest => {
+                if let Some(index) = find_oldest_data(&state.events) {
+                    state.events.remove(index);
+                    state.events.push_back(event);
+                }
+                state.dropped += 1;
+            }
+            OverflowPolicy::DropNewest => {
+                state.dropped += 1;
+            }
+            OverflowPolicy::ConflateByTopic => {
+                match find_same_topic(&state.events, &event) {
+                    Some(index) => state.events[index] = event,
+                    None => {
+                        if let Some(index) = find_oldest_data(&state.events) {
+                            state.events.remove(index);
+                            state.events.push_back(event);
+                        }
+                    }
+                }
+                state.dropped += 1;
+            }
+        }
+
+        let dropped = state.dropped;
+        drop(state);
+        self.shared.notify.notify_one();
+
+        Delivery::Overflowed(self.shared.policy, dropped)
+    }
+}
+
+impl Drop for OutboundSender {
+    fn drop(&mut self) {
+        self.shared.close()
+    }
+}
+
+/// Queues events for a client, waiting while the queue is full rather than
+/// applying the overflow policy. Unlike `OutboundSender` it does not close the
+/// queue when dropped.
+pub struct WaitingSender {
+    shared: Arc<Shared>,
+}
+
+impl WaitingSender {
+    /// Wait for room in the queue, then queue the event. Returns
+    /// `Delivery::Closed` if the queue closes first.
+    pub async fn send(&self, event: ServerEvent) -> Delivery {
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            // Register for a notification before looking at the queue, so
+            // one given in between is not missed.
+            space.as_mut().enable();
+
+            {
+                let mut state = self.shared.state.lock().unwrap();
+
+                if state.is_closed {
+                    return Delivery::Closed;
+                }
+
+                if state.events.len() < self.shared.capacity {
+                    state.events.push_back(event);
+                    drop(state);
+                    self.shared.notify.notify_one();
+                    return Delivery::Queued;
+                }
+            }
+
+            space.await;
+        }
+    }
+
+    /// Wait until every queued event has been taken, or the queue closes.
+    pub async fn flush(&self) {
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+
+            {
+                let state = self.shared.state.lock().unwrap();
+                if state.is_closed || state.events.is_empty() {
+                    return;
+                }
+            }
+
+            space.await;
+        }
+    }
+}
+
+pub struct OutboundReceiver {
+    shared: Arc<Shared>,
+}
+
+impl OutboundReceiver {
+    /// Wait for the next event. Dropped events are reported with
+    /// `ServerEvent::OnOverflow` before the next queued event is returned.
+    pub async fn recv(&mut self) -> Option<ServerEvent> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+
+                if state.dropped > 0 {
+                    let dropped = state.dropped;
+                    state.dropped = 0;
+                    return Some(ServerEvent::OnOverflow(self.shared.policy, dropped));
+                }
+
+                if let Some(event) = state.events.pop_front() {
+                    drop(state);
+                    self.shared.space.notify_waiters();
+                    return Some(event);
+                }
+
+                if state.is_closed {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        self.shared.close()
+    }
+}
+
+/// Data may be dropped for the overflow policy, unlike replies to requests.
+fn is_data(event: &ServerEvent) -> bool {
+    matches!(
+        event,
+        ServerEvent::OnMessage(
+            Message::ForwardedMulticastData { .. } | Message::ForwardedUnicastData { .. }
+        )
+    )
+}
+
+/// The oldest data in the queue. If the queue holds only replies the new
+/// data is dropped instead.
+fn find_oldest_data(events: &VecDeque<ServerEvent>) -> Option<usize> {
+    events.iter().position(is_data)
+}
+
+fn find_same_topic(events: &VecDeque<ServerEvent>, event: &ServerEvent) -> Option<usize> {
+    let ServerEvent::OnMessage(Message::ForwardedMulticastData { topic, .. }) = event else {
+        return None;
+    };
+
+    events.iter().position(|queued| {
+        matches!(
+            queued,
+            ServerEvent::OnMessage(Message::ForwardedMulticastData { topic: queued_topic, .. })
+                if queued_topic == topic
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use common::messages::DataPacket;
+
+    use super::*;
+
+    fn heartbeat(count: u64) -> ServerEvent {
+        ServerEvent::OnMessage(Message::Heartbeat { count })
+    }
+
+    fn ack(request_id: u64) -> ServerEvent {
+        ServerEvent::OnMessage(Message::Ack { request_id })
+    }
+
+    fn data(topic: &str, value: &str) -> ServerEvent {
+        ServerEvent::OnMessage(Message::ForwardedMulticastData {
+            host: "host".into(),
+            user: "user".into(),
+            topic: topic.into(),
+            data_packets: vec![DataPacket::new(
+                HashSet::new(),
+                HashMap::new(),
+                value.into(),
+            )],
+        })
+    }
+
+    async fn drain(rx: &mut OutboundReceiver) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::DropOldest);
+        assert_eq!(tx.send(data("A", "1")), Delivery::Queued);
+        assert_eq!(tx.send(data("A", "2")), Delivery::Queued);
+        assert_eq!(
+            tx.send(data("A", "3")),
+            Delivery::Overflowed(OverflowPolicy::DropOldest, 1)
+        );
+        drop(tx);
+
+        let actual = drain(&mut rx).await;
+        let expected = vec![
+            ServerEvent::OnOverflow(OverflowPolicy::DropOldest, 1),
+            data("A", "2"),
+            data("A", "3"),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_replies() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::DropOldest);
+        tx.send(ack(1));
+        tx.send(data("A", "1"));
+        // The oldest data is dropped rather than the reply.
+        assert_eq!(
+            tx.send(data("A", "2")),
+            Delivery::Overflowed(OverflowPolicy::DropOldest, 1)
+        );
+        // A reply is queued even though the queue is full.
+        assert_eq!(tx.send(ack(2)), Delivery::Queued);
+        // With only replies left to drop, the new data is dropped.
+        let (only_replies_tx, mut only_replies_rx) = channel(1, OverflowPolicy::DropOldest);
+        only_replies_tx.send(ack(3));
+        assert_eq!(
+            only_replies_tx.send(data("A", "3")),
+            Delivery::Overflowed(OverflowPolicy::DropOldest, 1)
+        );
+        drop(tx);
+        drop(only_replies_tx);
+
+        let actual = drain(&mut rx).await;
+        let expected = vec![
+            ServerEvent::OnOverflow(OverflowPolicy::DropOldest, 1),
+            ack(1),
+            data("A", "2"),
+            ack(2),
+        ];
+        assert_eq!(actual, expected);
+
+        let actual = drain(&mut only_replies_rx).await;
+        let expected = vec![
+            ServerEvent::OnOverflow(OverflowPolicy::DropOldest, 1),
+            ack(3),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::DropNewest);
+        tx.send(data("A", "1"));
+        tx.send(data("A", "2"));
+        tx.send(data("A", "3"));
+        assert_eq!(
+            tx.send(data("A", "4")),
+            Delivery::Overflowed(OverflowPolicy::DropNewest, 2)
+        );
+        drop(tx);
+
+        let actual = drain(&mut rx).await;
+        let expected = vec![
+            ServerEvent::OnOverflow(OverflowPolicy::DropNewest, 2),
+            data("A", "1"),
+            data("A", "2"),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn conflate_by_topic() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::ConflateByTopic);
+        tx.send(data("A", "1"));
+        tx.send(data("B", "1"));
+        assert_eq!(
+            tx.send(data("A", "2")),
+            Delivery::Overflowed(OverflowPolicy::ConflateByTopic, 1)
+        );
+        // Nothing to conflate with, so the oldest is dropped.
+        assert_eq!(
+            tx.send(data("C", "1")),
+            Delivery::Overflowed(OverflowPolicy::ConflateByTopic, 2)
+        );
+        drop(tx);
+
+        let actual = drain(&mut rx).await;
+        let expected = vec![
+            ServerEvent::OnOverflow(OverflowPolicy::ConflateByTopic, 2),
+            data("B", "1"),
+            data("C", "1"),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn waiting_sender() {
+        let (tx, mut rx) = channel(1, OverflowPolicy::Disconnect);
+        let waiting = tx.waiting();
+        assert_eq!(waiting.send(heartbeat(1)).await, Delivery::Queued);
+
+        // The queue is full, so the second send waits for the first event to
+        // be taken rather than disconnecting.
+        let sender = tokio::spawn(async move {
+            let delivery = waiting.send(heartbeat(2)).await;
+            (waiting, delivery)
+        });
+        assert_eq!(rx.recv().await, Some(heartbeat(1)));
+        let (waiting, delivery) = sender.await.unwrap();
+        assert_eq!(delivery, Delivery::Queued);
+        assert_eq!(rx.recv().await, Some(heartbeat(2)));
+        waiting.flush().await;
+
+        // Dropping a waiting sender leaves the queue open.
+        drop(waiting);
+        tx.send(heartbeat(3));
+        drop(tx);
+        assert_eq!(drain(&mut rx).await, vec![heartbeat(3)]);
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let (tx, mut rx) = channel(1, OverflowPolicy::Disconnect);
+        tx.send(data("A", "1"));
+        assert_eq!(
+            tx.send(data("A", "2")),
+            Delivery::Overflowed(OverflowPolicy::Disconnect, 2)
+        );
+        assert_eq!(tx.send(heartbeat(1)), Delivery::Closed);
+
+        let actual = drain(&mut rx).await;
+        let expected = vec![ServerEvent::OnOverflow(OverflowPolicy::Disconnect, 2)];
+        assert_eq!(actual, expected);
+    }
+}
@@ -7,11 +7,23 @@ use common::messages::{DataPacket, Message};
 
 use crate::{
     authorization::{AuthorizationManager, Role},
+    caching::CacheManager,
     clients::ClientManager,
     events::ServerEvent,
+    metrics::METRICS,
     subscriptions::SubscriptionManager,
 };
 
+/// The client which published data, with the host, user and groups of the
+/// original publisher. These differ from the client's when the data was
+/// forwarded by a federation link.
+pub struct Publisher<'a> {
+    pub client_id: &'a str,
+    pub host: &'a str,
+    pub user: &'a str,
+    pub groups: &'a [String],
+}
+
 pub struct PublisherManager {
     topics_by_publisher: HashMap<String, HashSet<String>>,
     publishers_by_topic: HashMap<String, HashSet<String>>,
@@ -25,18 +37,11 @@ impl PublisherManager {
         }
     }
 
-    fn get_authorized_data(
-        &self,
-        data_packets: Vec<DataPacket>,
-        entitlements: &HashSet<i32>,
-    ) -> Vec<DataPacket> {
-        let mut authorised_data_packets = Vec::new();
-        for data_packet in data_packets {
-            if data_packet.is_authorized(&entitlements) {
-                authorised_data_packets.push(data_packet)
-            }
-        }
-        authorised_data_packets
+    /// The publishers of each topic.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &HashSet<String>)> {
+        self.publishers_by_topic
+            .iter()
+            .map(|(topic, publishers)| (topic.as_str(), publishers))
     }
 
     /// Send data from one client to another.
@@ -56,30 +61,40 @@ impl PublisherManager {
 
         let Some(receiver) = client_manager.get(&receiver_id) else {
             log::trace!("Receiver {receiver_id} is not known; skipping.");
-            return Ok(());
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown client {receiver_id}"),
+            ));
         };
 
         // Get the entitlements.
-        let sender_entitlements =
-            entitlements_manager.entitlements(sender.user.as_str(), topic, Role::Publisher);
-        let receiver_entitlements =
-            entitlements_manager.entitlements(receiver.user.as_str(), topic, Role::Subscriber);
-        let entitlements: HashSet<i32> = sender_entitlements
-            .intersection(&receiver_entitlements)
-            .cloned()
-            .collect();
-
-        if !sender_entitlements.is_empty() && entitlements.is_empty() {
-            // Entitlements only operate if the sender has entitlements.
+        let sender_entitlements = entitlements_manager.entitlements(
+            sender.user.as_str(),
+            &sender.groups,
+            topic,
+            Role::Publisher,
+        );
+        let receiver_entitlements = entitlements_manager.entitlements(
+            receiver.user.as_str(),
+            &receiver.groups,
+            topic,
+            Role::Subscriber,
+        );
+        let Some(entitlements) =
+            get_authorized_entitlements(&sender_entitlements, &receiver_entitlements)
+        else {
             log::trace!(
                 "Client {} is not authorized to send topic \"{}\".",
                 sender.user,
                 topic
             );
-            return Ok(());
-        }
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("not authorized to send \"{topic}\" to {receiver_id}"),
+            ));
+        };
 
-        let auth_data_packets = self.get_authorized_data(data_packets, &entitlements);
+        let auth_data_packets = get_authorized_data(&data_packets, &entitlements);
 
         if auth_data_packets.is_empty() {
             log::trace!(
@@ -88,7 +103,10 @@ impl PublisherManager {
                 receiver.user,
                 topic
             );
-            return Ok(());
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("no authorized packets to send \"{topic}\" to {receiver_id}"),
+            ));
         }
 
         self.add_as_topic_publisher(sender_id, topic);
@@ -105,11 +123,7 @@ impl PublisherManager {
 
         let event = ServerEvent::OnMessage(message);
 
-        receiver
-            .tx
-            .send(event)
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        receiver.send(event);
 
         log::trace!("Sent to client {receiver_id}.");
 
@@ -119,45 +133,58 @@ impl PublisherManager {
     /// Send data to clients that subscribe to a topic.
     pub async fn send_multicast_data(
         &mut self,
-        publisher_id: &str,
+        publisher: &Publisher<'_>,
         topic: &str,
         data_packets: Vec<DataPacket>,
         subscription_manager: &SubscriptionManager,
         client_manager: &ClientManager,
         entitlements_manager: &AuthorizationManager,
     ) -> io::Result<()> {
+        let publisher_id = publisher.client_id;
+        let Some(publishing_client) = client_manager.get(publisher_id) else {
+            log::trace!("Publisher {publisher_id} is not known; skipping.");
+            return Ok(());
+        };
+
+        // Register the publisher even when there are no subscribers, so any
+        // cached image is cleared when the publisher goes away.
+        self.add_as_topic_publisher(publisher_id, topic);
+
         let subscribers = subscription_manager.subscribers_for_topic(topic);
         if subscribers.is_empty() {
             log::trace!("No subscribers for topic \"{topic}\"; skipping.");
             return Ok(());
         }
 
-        let Some(publisher) = client_manager.get(publisher_id) else {
-            log::trace!("Publisher {publisher_id} is not known; skipping.");
-            return Ok(());
-        };
-
-        let publisher_entitlements =
-            entitlements_manager.entitlements(publisher.user.as_str(), topic, Role::Publisher);
-
-        self.add_as_topic_publisher(publisher_id, topic);
+        let publisher_entitlements = entitlements_manager.entitlements(
+            publisher.user,
+            publisher.groups,
+            topic,
+            Role::Publisher,
+        );
 
         for subscriber_id in &subscribers {
             if let Some(subscriber) = client_manager.get(subscriber_id) {
+                // Data from one federation link is never sent to another, so
+                // it cannot loop between brokers.
+                if publishing_client.is_federated() && subscriber.is_federated() {
+                    log::trace!(
+                        "Not forwarding federated topic \"{topic}\" to federation link {subscriber_id}; skipping."
+                    );
+                    continue;
+                }
+
                 log::trace!("Pubilshing topic \"{topic}\" to subscriber {subscriber_id}.");
 
                 let subscriber_entitlements = entitlements_manager.entitlements(
                     subscriber.user.as_str(),
+                    &subscriber.groups,
                     topic,
                     Role::Subscriber,
                 );
-                let entitlements: HashSet<i32> = publisher_entitlements
-                    .intersection(&subscriber_entitlements)
-                    .cloned()
-                    .collect();
-
-                if !publisher_entitlements.is_empty() && entitlements.is_empty() {
-                    // Entitlements only operate if the publisher has entitlements.
+                let Some(entitlements) =
+                    get_authorized_entitlements(&publisher_entitlements, &subscriber_entitlements)
+                else {
                     log::trace!(
                         "Publisher {} is not authorized to send topic \"{}\" to subscriber {}; skipping.",
                         publisher.user,
@@ -165,10 +192,9 @@ impl PublisherManager {
                         subscriber.user,
                     );
                     continue;
-                }
+                };
 
-                let auth_data_packets =
-                    self.get_authorized_data(data_packets.clone(), &entitlements);
+                let auth_data_packets = get_authorized_data(&data_packets, &entitlements);
 
                 if auth_data_packets.is_empty() {
                     log::trace!(
@@ -181,8 +207,8 @@ impl PublisherManager {
                 }
 
                 let message = Message::ForwardedMulticastData {
-                    host: publisher.host.clone(),
-                    user: publisher.user.clone(),
+                    host: publisher.host.into(),
+                    user: publisher.user.into(),
                     topic: topic.into(),
                     data_packets: auth_data_packets,
                 };
@@ -193,11 +219,7 @@ impl PublisherManager {
 
                 let event = ServerEvent::OnMessage(message);
 
-                subscriber
-                    .tx
-                    .send(event)
-                    .await
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                subscriber.send(event);
             }
         }
 
@@ -229,6 +251,7 @@ impl PublisherManager {
         closed_client_id: &str,
         client_manager: &ClientManager,
         subscription_manager: &SubscriptionManager,
+        cache_manager: &mut CacheManager,
     ) -> io::Result<()> {
         let topics_without_publishers = remove_publisher(
             closed_client_id,
@@ -236,7 +259,9 @@ impl PublisherManager {
             &mut self.publishers_by_topic,
         );
 
-        if topics_without_publishers.len() > 0 {
+        if !topics_without_publishers.is_empty() {
+            cache_manager.handle_stale_topics(&topics_without_publishers);
+
             notify_subscribers_of_stale_topics(
                 closed_client_id,
                 topics_without_publishers,
@@ -250,6 +275,39 @@ impl PublisherManager {
     }
 }
 
+/// Find the entitlements a receiver can be sent by a sender, or `None` if the
+/// sender is not authorized to send to the receiver. Entitlements only operate
+/// if the sender has entitlements.
+pub fn get_authorized_entitlements(
+    sender_entitlements: &HashSet<i32>,
+    receiver_entitlements: &HashSet<i32>,
+) -> Option<HashSet<i32>> {
+    let entitlements: HashSet<i32> = sender_entitlements
+        .intersection(receiver_entitlements)
+        .cloned()
+        .collect();
+
+    if !sender_entitlements.is_empty() && entitlements.is_empty() {
+        None
+    } else {
+        Some(entitlements)
+    }
+}
+
+/// Select the packets covered by the entitlements.
+pub fn get_authorized_data(
+    data_packets: &[DataPacket],
+    entitlements: &HashSet<i32>,
+) -> Vec<DataPacket> {
+    let authorized_data: Vec<DataPacket> = data_packets
+        .iter()
+        .filter(|data_packet| data_packet.is_authorized(entitlements))
+        .cloned()
+        .collect();
+    METRICS.packets_dropped(data_packets.len() - authorized_data.len());
+    authorized_data
+}
+
 fn remove_publisher(
     closed_client_id: &str,
     topics_by_publisher: &mut HashMap<String, HashSet<String>>,
@@ -262,7 +320,7 @@ fn remove_publisher(
         for topic in publisher_topics {
             if let Some(topic_publishers) = publishers_by_topic.get_mut(topic.as_str()) {
                 topic_publishers.remove(closed_client_id);
-                if topic_publishers.len() == 0 {
+                if topic_publishers.is_empty() {
                     topics_without_publishers.push(topic);
                 }
             }
@@ -302,14 +360,99 @@ async fn notify_subscribers_of_stale_topics(
 
                 let event = ServerEvent::OnMessage(stale_data_message.clone());
 
-                subscriber
-                    .tx
-                    .send(event)
-                    .await
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                subscriber.send(event);
             }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use wildmatch::WildMatch;
+
+    use crate::authorization::AuthorizationSpec;
+    use crate::clients::Transport;
+    use crate::notifications::NotificationManager;
+    use crate::outbound::{self, OverflowPolicy};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_not_forward_federated_data_to_links() {
+        let authorization_manager = AuthorizationManager::new(vec![AuthorizationSpec {
+            user_pattern: WildMatch::new("*"),
+            topic_pattern: WildMatch::new("*"),
+            entitlements: HashSet::new(),
+            roles: Role::Subscriber | Role::Notifier | Role::Publisher,
+        }]);
+
+        let mut client_manager = ClientManager::new();
+        let mut receivers = HashMap::new();
+        for id in ["link1", "link2", "local"] {
+            let (tx, rx) = outbound::channel(10, OverflowPolicy::Disconnect);
+            client_manager.handle_connect(id, "host".into(), "user".into(), Transport::Socket, tx);
+            receivers.insert(id, rx);
+        }
+        client_manager
+            .handle_federation("link1", "broker1".into())
+            .unwrap();
+        client_manager
+            .handle_federation("link2", "broker2".into())
+            .unwrap();
+
+        let notification_manager = NotificationManager::new();
+        let mut subscription_manager = SubscriptionManager::new();
+        for id in ["link2", "local"] {
+            subscription_manager
+                .handle_subscription_request(
+                    id,
+                    "LSE.VOD".into(),
+                    true,
+                    &client_manager,
+                    &notification_manager,
+                    &authorization_manager,
+                )
+                .await
+                .unwrap();
+        }
+
+        let data_packets = vec![DataPacket::new(
+            HashSet::new(),
+            HashMap::new(),
+            "data".into(),
+        )];
+        let mut publisher_manager = PublisherManager::new();
+        publisher_manager
+            .send_multicast_data(
+                &Publisher {
+                    client_id: "link1",
+                    host: "origin",
+                    user: "harry",
+                    groups: &[],
+                },
+                "LSE.VOD",
+                data_packets.clone(),
+                &subscription_manager,
+                &client_manager,
+                &authorization_manager,
+            )
+            .await
+            .unwrap();
+        drop(client_manager);
+
+        let expected = ServerEvent::OnMessage(Message::ForwardedMulticastData {
+            host: "origin".into(),
+            user: "harry".into(),
+            topic: "LSE.VOD".into(),
+            data_packets,
+        });
+        let local_rx = receivers.get_mut("local").unwrap();
+        assert_eq!(local_rx.recv().await, Some(expected));
+        assert_eq!(local_rx.recv().await, None);
+        assert_eq!(receivers.get_mut("link2").unwrap().recv().await, None);
+    }
+}
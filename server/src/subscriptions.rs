@@ -3,43 +3,33 @@ use std::{
     io,
 };
 
+use common::TopicIndex;
 use wildmatch::WildMatch;
 
-use crate::{clients::ClientManager, notifications::NotificationManager};
-
-struct Subscription {
-    pattern: WildMatch,
-    subscribers: HashMap<String, u32>,
-}
-
-impl Subscription {
-    pub fn new(topic: &str) -> Self {
-        Subscription {
-            pattern: WildMatch::new(topic),
-            subscribers: HashMap::new(),
-        }
-    }
-}
+use crate::{
+    authorization::{AuthorizationManager, Role},
+    clients::ClientManager,
+    notifications::NotificationManager,
+};
 
 pub struct SubscriptionManager {
-    subscriptions: HashMap<String, Subscription>,
+    /// The subscribers and their request counts for each topic pattern.
+    subscriptions: TopicIndex<HashMap<String, u32>>,
 }
 
 impl SubscriptionManager {
     pub fn new() -> SubscriptionManager {
         SubscriptionManager {
-            subscriptions: HashMap::new(),
+            subscriptions: TopicIndex::new(),
         }
     }
 
     pub fn subscribers_for_topic(&self, topic: &str) -> HashSet<String> {
         let mut subscribers: HashSet<String> = HashSet::new();
 
-        for subscription in self.subscriptions.values() {
-            if subscription.pattern.matches(topic) {
-                for key in subscription.subscribers.keys() {
-                    subscribers.insert(key.clone());
-                }
+        for (_pattern, subscription) in self.subscriptions.matches(topic) {
+            for key in subscription.keys() {
+                subscribers.insert(key.clone());
             }
         }
 
@@ -53,16 +43,24 @@ impl SubscriptionManager {
         is_add: bool,
         client_manager: &ClientManager,
         notification_manager: &NotificationManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
         if is_add {
-            self.add_subscription(id, topic.as_str(), client_manager, notification_manager)
-                .await
+            self.add_subscription(
+                id,
+                topic.as_str(),
+                client_manager,
+                notification_manager,
+                authorization_manager,
+            )
+            .await
         } else {
             self.remove_subscription(
                 id,
                 topic.as_str(),
                 client_manager,
                 notification_manager,
+                authorization_manager,
                 false,
             )
             .await
@@ -75,16 +73,36 @@ impl SubscriptionManager {
         topic: &str,
         client_manager: &ClientManager,
         notification_manager: &NotificationManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
-        // Add or get the subscription.
-        if !self.subscriptions.contains_key(topic) {
-            self.subscriptions
-                .insert(topic.to_owned(), Subscription::new(topic));
+        let Some(subscriber) = client_manager.get(subscriber_id) else {
+            log::debug!("Subscriber {subscriber_id} is not known; skipping.");
+            return Ok(());
+        };
+
+        if !authorization_manager.is_authorized(
+            &subscriber.user,
+            &subscriber.groups,
+            topic,
+            Role::Subscriber,
+        ) {
+            log::debug!(
+                "Client {} ({}) is not authorized to subscribe to \"{}\".",
+                subscriber_id,
+                subscriber.user,
+                topic
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("not authorized to subscribe to \"{topic}\""),
+            ));
         }
-        let subscription = self.subscriptions.get_mut(topic).unwrap();
+
+        // Add or get the subscription.
+        let subscription = self.subscriptions.get_or_insert_with(topic, HashMap::new);
 
         // Keep a request count.
-        let count = if let Some(count) = subscription.subscribers.get_mut(subscriber_id) {
+        let count = if let Some(count) = subscription.get_mut(subscriber_id) {
             log::debug!(
                 "Incrementing subscription for subscriber {subscriber_id} on topic \"{topic}\"."
             );
@@ -93,12 +111,19 @@ impl SubscriptionManager {
         } else {
             log::debug!("Adding subscription for subscriber {subscriber_id} on topic \"{topic}\".");
             let count = 1;
-            subscription.subscribers.insert(subscriber_id.into(), count);
+            subscription.insert(subscriber_id.into(), count);
             count
         };
 
         notification_manager
-            .notify_listeners(subscriber_id, topic, true, count, client_manager)
+            .notify_listeners(
+                subscriber_id,
+                topic,
+                true,
+                count,
+                client_manager,
+                authorization_manager,
+            )
             .await
     }
 
@@ -108,13 +133,14 @@ impl SubscriptionManager {
         topic: &str,
         client_manager: &ClientManager,
         notification_manager: &NotificationManager,
+        authorization_manager: &AuthorizationManager,
         is_subscriber_closed: bool,
     ) -> io::Result<()> {
         let Some(subscription) = self.subscriptions.get_mut(topic) else {
             return Ok(());
         };
 
-        let Some(count) = subscription.subscribers.get_mut(subscriber_id) else {
+        let Some(count) = subscription.get_mut(subscriber_id) else {
             return Ok(());
         };
 
@@ -127,18 +153,25 @@ impl SubscriptionManager {
         let count = *count;
 
         if count == 0 {
-            subscription.subscribers.remove(subscriber_id);
+            subscription.remove(subscriber_id);
             log::debug!("Removed all subscriptions for {subscriber_id} on topic \"{topic}\".");
         } else {
             log::debug!("Removed a subscription for {subscriber_id} on topic \"{topic}\".");
         }
 
-        if subscription.subscribers.is_empty() {
+        if subscription.is_empty() {
             self.subscriptions.remove(topic);
         }
 
         notification_manager
-            .notify_listeners(subscriber_id, topic, false, 0, client_manager)
+            .notify_listeners(
+                subscriber_id,
+                topic,
+                false,
+                count,
+                client_manager,
+                authorization_manager,
+            )
             .await
     }
 
@@ -147,6 +180,7 @@ impl SubscriptionManager {
         closed_client_id: &str,
         client_manager: &ClientManager,
         notification_manager: &NotificationManager,
+        authorization_manager: &AuthorizationManager,
     ) -> io::Result<()> {
         let closed_client_topic_subscriptions = self.find_client_topics(closed_client_id);
         for topic in closed_client_topic_subscriptions {
@@ -155,6 +189,7 @@ impl SubscriptionManager {
                 &topic,
                 client_manager,
                 notification_manager,
+                authorization_manager,
                 true,
             )
             .await?;
@@ -165,19 +200,24 @@ impl SubscriptionManager {
 
     fn find_client_topics(&self, client_id: &str) -> Vec<String> {
         let mut topics: Vec<String> = Vec::new();
-        for (topic, subscription) in &self.subscriptions {
-            if subscription.subscribers.contains_key(client_id) {
-                topics.push(topic.clone());
+        for (topic, subscription) in self.subscriptions.iter() {
+            if subscription.contains_key(client_id) {
+                topics.push(topic.to_string());
             }
         }
         topics
     }
 
+    /// The subscribers and their request counts for each topic pattern.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &HashMap<String, u32>)> {
+        self.subscriptions.iter()
+    }
+
     pub fn find_subscriptions(&self, pattern: &WildMatch) -> Vec<(String, &HashMap<String, u32>)> {
         let mut subscriptions: Vec<(String, &HashMap<String, u32>)> = Vec::new();
-        for (topic, subscription) in &self.subscriptions {
-            if pattern.matches(topic.as_str()) {
-                subscriptions.push((topic.clone(), &subscription.subscribers));
+        for (topic, subscribers) in self.subscriptions.iter() {
+            if pattern.matches(topic) {
+                subscriptions.push((topic.to_string(), subscribers));
             }
         }
         subscriptions